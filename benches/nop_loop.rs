@@ -0,0 +1,46 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use gb_emulator_rust::components::cpu::CPU;
+
+/// Number of cycles executed by each iteration of the benchmarks
+const CYCLES_PER_ITERATION: u64 = 70224;
+
+/// Writes a ROM filling the first bank with one instruction and jumping back
+/// to the entry point at its end
+///
+/// # Arguments
+/// **name (&str)**: Name of the ROM file
+/// **op (u8)**: One byte instruction repeated in the loop
+///
+/// # Returns
+/// **PathBuf**: Path of the written ROM
+fn loop_rom(name: &str, op: u8) -> PathBuf {
+    let mut rom = vec![op; 0x8000];
+    // Empty header, read as a ROM without mapper
+    rom[0x0100..0x0150].fill(0x00);
+    rom[0x3FFD..0x4000].copy_from_slice(&[0xC3, 0x00, 0x01]);
+    rom[0x4000..].fill(0x00);
+    let path = env::temp_dir().join(name);
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+/// Measures the instructions that make most of the time spent by a game
+fn bench_loops(c: &mut Criterion) {
+    let mut group = c.benchmark_group("loop");
+    group.throughput(Throughput::Elements(CYCLES_PER_ITERATION));
+    for (name, op) in [("nop", 0x00), ("ld_b_c", 0x41)] {
+        let path = loop_rom(&format!("gb_emulator_rust_bench_{}.gb", name), op);
+        let mut cpu = CPU::new_embedded(&path.display().to_string());
+        group.bench_function(name, |b| {
+            b.iter(|| cpu.run_headless(CYCLES_PER_ITERATION))
+        });
+        fs::remove_file(&path).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_loops);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use gb_emulator_rust::components::gpu::Gpu;
+
+/// Number of dots in one line
+const DOTS_PER_LINE: u32 = 456;
+/// Number of lines in one frame, including the vertical blank
+const LINES_PER_FRAME: u16 = 154;
+
+/// Creates a GPU whose background is filled with various tiles
+///
+/// # Returns
+/// **Gpu**: GPU with the LCD, background, window and objects on
+fn gpu_with_background() -> Gpu {
+    let mut gpu = Gpu::headless();
+    // LCD, background, window and objects on, tiles from 0x8000
+    gpu.write_lcd(0x40, 0xF3);
+    gpu.write_lcd(0x47, 0xE4);
+    for index in 0..256 {
+        let mut data = [0x00; 16];
+        for (i, value) in data.iter_mut().enumerate() {
+            *value = (index as u8).wrapping_mul(31).wrapping_add(i as u8);
+        }
+        gpu.set_tile(index, data);
+    }
+    for y in 0..32 {
+        for x in 0..32 {
+            gpu.set_bg_map_entry(x, y, x.wrapping_mul(7).wrapping_add(y));
+        }
+    }
+    gpu
+}
+
+/// Measures the time needed by the GPU to draw a whole frame
+fn bench_frame(c: &mut Criterion) {
+    let mut plain_gpu = gpu_with_background();
+    let mut gpu_with_objects = gpu_with_background();
+    // 40 objects spread over the screen, up to 10 per line
+    for i in 0..40u16 {
+        let object = 0xFE00 + 4 * i;
+        gpu_with_objects.write_oam(object, 16 + (i as u8 % 18) * 8);
+        gpu_with_objects.write_oam(object + 1, 8 + (i as u8) * 4);
+        gpu_with_objects.write_oam(object + 2, i as u8);
+    }
+    let mut group = c.benchmark_group("render");
+    group.throughput(Throughput::Elements(1));
+    for (name, gpu) in [
+        ("frame", &mut plain_gpu),
+        ("frame_with_objects", &mut gpu_with_objects),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..LINES_PER_FRAME {
+                    gpu.update(DOTS_PER_LINE);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_frame);
+criterion_main!(benches);
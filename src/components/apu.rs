@@ -0,0 +1,467 @@
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use crate::components::cpu::CLOCK_HZ;
+use crate::state::apu_state::ApuState;
+
+/// Number of CPU cycles between two clocks of the LFSR of channel 4 for each
+/// divisor code of NR43, before the shift is applied
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Number of samples produced each second by default
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// Audio Processing Unit of the GameBoy
+///
+/// Only the noise generator of channel 4 is emulated, its output is sampled
+/// at the chosen rate until generate is called.
+pub struct Apu {
+    /// NR41: length timer of channel 4 (write only)
+    nr41: u8,
+    /// NR42: volume and envelope of channel 4
+    nr42: u8,
+    /// NR43: frequency and randomness of channel 4
+    /// shift*4/width/divisor*3
+    nr43: u8,
+    /// NR44: trigger and length enable of channel 4
+    nr44: u8,
+    /// Linear Feedback Shift Register generating the noise of channel 4
+    channel4_lfsr: u16,
+    /// Has channel 4 been triggered
+    is_channel4_on: bool,
+    /// Number of CPU cycles since the last clock of the LFSR
+    channel4_cycles: u32,
+    /// Number of samples produced for each second of emulation
+    sample_rate: u32,
+    /// Are two samples (left and right) produced at a time
+    is_stereo: bool,
+    /// Position in the current sample period, in CPU cycles times the sample
+    /// rate (a sample is produced each time it reaches CLOCK_HZ)
+    sample_phase: u64,
+    /// Samples produced since the last call to generate, at most one second
+    samples: VecDeque<f32>,
+}
+
+impl Apu {
+    /// Create a new APU with every channel off
+    ///
+    /// The sample rate is clamped between 1 and the clock of the CPU, at most
+    /// one sample is produced per cycle.
+    ///
+    /// # Arguments
+    /// **sample_rate (u32)**: Number of samples produced for each second of
+    /// emulation (44100 or 48000 for example)
+    /// **stereo (bool)**: Should the left and right samples be interleaved,
+    /// otherwise a single sample is produced at a time
+    ///
+    /// # Returns
+    /// **Apu**: New APU
+    pub fn new(sample_rate: u32, stereo: bool) -> Self {
+        Self {
+            nr41: 0x00,
+            nr42: 0x00,
+            nr43: 0x00,
+            nr44: 0x00,
+            channel4_lfsr: 0x7FFF,
+            is_channel4_on: false,
+            channel4_cycles: 0,
+            sample_rate: sample_rate.clamp(1, CLOCK_HZ),
+            is_stereo: stereo,
+            sample_phase: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Change the samples produced, keeping the state of the channels
+    ///
+    /// The samples not generated yet are dropped. The sample rate is clamped
+    /// like in new.
+    ///
+    /// # Arguments
+    /// **sample_rate (u32)**: Number of samples produced for each second of
+    /// emulation
+    /// **stereo (bool)**: Should the left and right samples be interleaved
+    pub fn set_output(&mut self, sample_rate: u32, stereo: bool) {
+        self.sample_rate = sample_rate.clamp(1, CLOCK_HZ);
+        self.is_stereo = stereo;
+        self.sample_phase = 0;
+        self.samples.clear();
+    }
+
+    /// Returns the number of samples produced for each second of emulation
+    ///
+    /// # Returns
+    /// **u32**: Sample rate given to new or set_output, once clamped
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns whether the samples are produced in pairs
+    ///
+    /// # Returns
+    /// **bool**: true iff the left and right samples are interleaved
+    pub fn is_stereo(&self) -> bool {
+        self.is_stereo
+    }
+
+    /// Returns the number of samples kept until generate is called
+    ///
+    /// # Returns
+    /// **usize**: Number of samples of one second of emulation
+    fn buffer_capacity(&self) -> usize {
+        let n_channels = if self.is_stereo { 2 } else { 1 };
+        self.sample_rate as usize * n_channels
+    }
+
+    /// Returns the samples produced since the last call
+    ///
+    /// Only the last second is kept if generate is not called.
+    ///
+    /// # Returns
+    /// **`Vec<f32>`**: Samples between 0 and 1, left then right in stereo
+    pub fn generate(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    /// Read a register of the APU
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the register (0xFF20 to 0xFF23)
+    ///
+    /// # Returns
+    /// **u8**: Value of the register, the write only bits read as 1
+    pub fn read(&self, address: u16) -> u8 {
+        match address & 0x00FF {
+            0x20 => 0xFF,
+            0x21 => self.nr42,
+            0x22 => self.nr43,
+            0x23 => self.nr44 | 0xBF,
+            _ => 0xFF,
+        }
+    }
+
+    /// Change a register of the APU
+    ///
+    /// Setting the bit 7 of NR44 triggers channel 4.
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the register (0xFF20 to 0xFF23)
+    /// **value (u8)**: New value of the register
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address & 0x00FF {
+            0x20 => self.nr41 = value,
+            0x21 => self.nr42 = value,
+            0x22 => self.nr43 = value,
+            0x23 => {
+                self.nr44 = value & 0x7F;
+                if value & 0x80 == 0x80 {
+                    self.trigger_channel4();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Returns a copy of the registers and channels of the APU
+    ///
+    /// # Returns
+    /// **ApuState**: State of channel 4
+    pub fn state(&self) -> ApuState {
+        ApuState {
+            channel4_registers: [self.nr41, self.nr42, self.nr43, self.nr44],
+            channel4_lfsr: self.channel4_lfsr,
+            is_channel4_on: self.is_channel4_on,
+            channel4_cycles: self.channel4_cycles,
+        }
+    }
+
+    /// Restores the registers and channels of the APU, without triggering
+    /// them
+    ///
+    /// # Arguments
+    /// **state (&ApuState)**: State to restore, as returned by state
+    pub fn restore(&mut self, state: &ApuState) {
+        let [nr41, nr42, nr43, nr44] = state.channel4_registers;
+        self.nr41 = nr41;
+        self.nr42 = nr42;
+        self.nr43 = nr43;
+        self.nr44 = nr44 & 0x7F;
+        self.channel4_lfsr = state.channel4_lfsr;
+        self.is_channel4_on = state.is_channel4_on;
+        self.channel4_cycles = state.channel4_cycles;
+    }
+
+    /// Returns the LFSR of channel 4
+    ///
+    /// # Returns
+    /// **u16**: Current value of the 15 bits of the LFSR
+    pub fn channel4_lfsr(&self) -> u16 {
+        self.channel4_lfsr
+    }
+
+    /// Restarts channel 4 with every bit of its LFSR set
+    fn trigger_channel4(&mut self) {
+        self.is_channel4_on = true;
+        self.channel4_lfsr = 0x7FFF;
+        self.channel4_cycles = 0;
+    }
+
+    /// Shifts the LFSR of channel 4 once
+    ///
+    /// The xor of the bits 0 and 1 is shifted in the bit 14, and in the bit 6
+    /// as well when NR43 selects the 7 bits width.
+    fn clock_channel4(&mut self) {
+        let xor = (self.channel4_lfsr ^ (self.channel4_lfsr >> 1)) & 0x0001;
+        self.channel4_lfsr = (self.channel4_lfsr >> 1) | (xor << 14);
+        if self.nr43 & 0x08 == 0x08 {
+            self.channel4_lfsr = (self.channel4_lfsr & !0x0040) | (xor << 6);
+        }
+    }
+
+    /// Returns the current output of channel 4
+    ///
+    /// # Returns
+    /// **f32**: Volume of NR42 when the bit 0 of the LFSR is clear, between 0
+    /// and 1
+    fn channel4_output(&self) -> f32 {
+        if !self.is_channel4_on || self.channel4_lfsr & 0x0001 == 0x0001 {
+            return 0.0;
+        }
+        (self.nr42 >> 4) as f32 / 15.0
+    }
+
+    /// Adds the current output to the samples, dropping the oldest ones
+    /// beyond one second
+    fn push_sample(&mut self) {
+        let output = self.channel4_output();
+        let n_channels = if self.is_stereo { 2 } else { 1 };
+        for _ in 0..n_channels {
+            self.samples.push_back(output);
+        }
+        while self.samples.len() > self.buffer_capacity() {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Updates the channels of the APU and samples their output
+    ///
+    /// The output is sampled at the chosen rate, each sample taking the value
+    /// of the signal at the end of its period.
+    ///
+    /// # Arguments
+    /// **n_cycles (u32)**: Number of cpu cycles since the last update
+    pub fn update(&mut self, n_cycles: u32) {
+        let mut n_remaining_cycles = n_cycles;
+        while n_remaining_cycles > 0 {
+            let cycles_to_sample = (CLOCK_HZ as u64 - self.sample_phase)
+                .div_ceil(self.sample_rate as u64);
+            let step = n_remaining_cycles.min(cycles_to_sample as u32);
+            self.update_channel4(step);
+            n_remaining_cycles -= step;
+            self.sample_phase += step as u64 * self.sample_rate as u64;
+            if self.sample_phase >= CLOCK_HZ as u64 {
+                self.sample_phase -= CLOCK_HZ as u64;
+                self.push_sample();
+            }
+        }
+    }
+
+    /// Updates the LFSR of channel 4
+    ///
+    /// # Arguments
+    /// **n_cycles (u32)**: Number of cpu cycles since the last update
+    fn update_channel4(&mut self, n_cycles: u32) {
+        let shift = self.nr43 >> 4;
+        // The LFSR is not clocked with the shifts 14 and 15
+        if !self.is_channel4_on || shift >= 14 {
+            return;
+        }
+        let period = NOISE_DIVISORS[(self.nr43 & 0x07) as usize] << shift;
+        self.channel4_cycles += n_cycles;
+        while self.channel4_cycles >= period {
+            self.channel4_cycles -= period;
+            self.clock_channel4();
+        }
+    }
+}
+
+impl Hash for Apu {
+    /// Hashes the registers and channels of the APU, but not the samples
+    /// waiting to be played
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.nr41.hash(state);
+        self.nr42.hash(state);
+        self.nr43.hash(state);
+        self.nr44.hash(state);
+        self.channel4_lfsr.hash(state);
+        self.is_channel4_on.hash(state);
+        self.channel4_cycles.hash(state);
+    }
+}
+
+impl Default for Apu {
+    /// Create an APU producing stereo samples at 44100 Hz
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_RATE, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel4_lfsr_15_bits() {
+        let mut apu = Apu::default();
+        apu.write(0xFF22, 0x00);
+        apu.write(0xFF23, 0x80);
+        assert_eq!(apu.channel4_lfsr(), 0x7FFF);
+        let mut sequence = vec![];
+        for _ in 0..16 {
+            apu.clock_channel4();
+            sequence.push(apu.channel4_lfsr());
+        }
+        assert_eq!(sequence, vec![
+            0x3FFF, 0x1FFF, 0x0FFF, 0x07FF, 0x03FF, 0x01FF, 0x00FF, 0x007F,
+            0x003F, 0x001F, 0x000F, 0x0007, 0x0003, 0x0001, 0x4000, 0x2000,
+        ]);
+        // The sequence repeats every 32767 clocks
+        for _ in 16..32767 {
+            apu.clock_channel4();
+        }
+        assert_eq!(apu.channel4_lfsr(), 0x7FFF);
+        // Triggering the channel again restarts the sequence
+        apu.clock_channel4();
+        apu.write(0xFF23, 0x80);
+        assert_eq!(apu.channel4_lfsr(), 0x7FFF);
+    }
+
+    #[test]
+    fn test_channel4_lfsr_7_bits() {
+        let mut apu = Apu::default();
+        apu.write(0xFF22, 0x08);
+        apu.write(0xFF23, 0x80);
+        let mut sequence = vec![];
+        for _ in 0..12 {
+            apu.clock_channel4();
+            sequence.push(apu.channel4_lfsr());
+        }
+        assert_eq!(sequence, vec![
+            0x3FBF, 0x1F9F, 0x0F8F, 0x0787, 0x0383, 0x0181,
+            0x40C0, 0x2020, 0x1010, 0x0808, 0x0404, 0x0202,
+        ]);
+        // The 7 lower bits repeat every 127 clocks
+        let low_bits = apu.channel4_lfsr() & 0x7F;
+        for _ in 0..127 {
+            apu.clock_channel4();
+        }
+        assert_eq!(apu.channel4_lfsr() & 0x7F, low_bits);
+    }
+
+    #[test]
+    fn test_channel4_clock_period() {
+        let mut apu = Apu::default();
+        // Divisor 16, shift 1: one clock every 32 cycles
+        apu.write(0xFF22, 0x11);
+        apu.update(64);
+        assert_eq!(apu.channel4_lfsr(), 0x7FFF);
+        apu.write(0xFF23, 0x80);
+        apu.update(31);
+        assert_eq!(apu.channel4_lfsr(), 0x7FFF);
+        apu.update(1);
+        assert_eq!(apu.channel4_lfsr(), 0x3FFF);
+        apu.update(64);
+        assert_eq!(apu.channel4_lfsr(), 0x0FFF);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = Apu::new(48000, false);
+        assert_eq!(apu.sample_rate(), 48000);
+        assert!(!apu.is_stereo());
+        // One second of emulation, in steps of various lengths
+        let mut n_cycles = 0;
+        for step in [4, 8, 12, 20, 24].iter().cycle() {
+            if n_cycles + step > CLOCK_HZ {
+                apu.update(CLOCK_HZ - n_cycles);
+                break;
+            }
+            apu.update(*step);
+            n_cycles += step;
+        }
+        let n_samples = apu.generate().len();
+        assert!((47999..=48001).contains(&n_samples));
+        assert!(apu.generate().is_empty());
+        // Stereo produces pairs of samples
+        let mut apu = Apu::new(44100, true);
+        apu.update(CLOCK_HZ / 2);
+        assert_eq!(apu.generate().len(), 44100);
+    }
+
+    #[test]
+    fn test_sample_rate_clamped() {
+        let mut apu = Apu::new(CLOCK_HZ * 2, false);
+        assert_eq!(apu.sample_rate(), CLOCK_HZ);
+        apu.update(1000);
+        assert_eq!(apu.generate().len(), 1000);
+        assert_eq!(Apu::new(0, false).sample_rate(), 1);
+    }
+
+    #[test]
+    fn test_set_output_keeps_channel4() {
+        let mut apu = Apu::new(44100, true);
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF23, 0x80);
+        apu.update(1001);
+        let mut expected = Apu::new(44100, true);
+        expected.write(0xFF21, 0xF0);
+        expected.write(0xFF23, 0x80);
+        expected.update(1001);
+        apu.set_output(48000, false);
+        assert_eq!(apu.sample_rate(), 48000);
+        assert!(!apu.is_stereo());
+        assert!(apu.generate().is_empty());
+        apu.update(3000);
+        expected.update(3000);
+        assert_eq!(apu.channel4_lfsr(), expected.channel4_lfsr());
+        assert_ne!(apu.channel4_lfsr(), 0x7FFF);
+    }
+
+    #[test]
+    fn test_state_restore() {
+        let mut apu1 = Apu::new(44100, true);
+        apu1.write(0xFF21, 0xF0);
+        apu1.write(0xFF22, 0x08);
+        apu1.write(0xFF23, 0x80);
+        apu1.update(1003);
+        let state = apu1.state();
+        assert!(state.is_channel4_on);
+        let mut apu2 = Apu::new(44100, true);
+        apu2.restore(&state);
+        assert_eq!(apu2.state(), state);
+        for _ in 0..100 {
+            apu1.update(77);
+            apu2.update(77);
+            assert_eq!(apu2.channel4_lfsr(), apu1.channel4_lfsr());
+        }
+        assert_eq!(apu2.state(), apu1.state());
+    }
+
+    #[test]
+    fn test_samples_follow_channel4() {
+        // One sample every 128 cycles
+        let mut apu = Apu::new(32768, false);
+        apu.update(1024);
+        assert_eq!(apu.generate(), vec![0.0; 8]);
+        // Full volume, one clock every 8 cycles
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF22, 0x00);
+        apu.write(0xFF23, 0x80);
+        apu.update(112);
+        assert_eq!(apu.channel4_lfsr(), 0x0001);
+        assert!(apu.generate().is_empty());
+        // The bit 0 of the LFSR is clear at the end of the period
+        apu.update(16);
+        assert_eq!(apu.channel4_lfsr(), 0x2000);
+        assert_eq!(apu.generate(), vec![1.0]);
+    }
+}
@@ -0,0 +1,24 @@
+/// Plays the samples produced by the APU
+///
+/// Given to the CPU by the front-end, it receives the samples after every
+/// step of run and tells how many of them are still waiting to be played.
+pub trait AudioSink {
+    /// Add samples at the end of the buffer of the host
+    ///
+    /// # Arguments
+    /// **samples (&[f32])**: Samples between 0 and 1, left then right in
+    /// stereo
+    fn queue(&mut self, samples: &[f32]);
+
+    /// Returns the number of samples waiting to be played
+    ///
+    /// # Returns
+    /// **usize**: Number of samples in the buffer of the host
+    fn buffered(&self) -> usize;
+
+    /// Returns the number of samples the buffer of the host can hold
+    ///
+    /// # Returns
+    /// **usize**: Size of the buffer of the host
+    fn capacity(&self) -> usize;
+}
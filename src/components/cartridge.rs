@@ -1,7 +1,10 @@
 use std::fs::{File, metadata, create_dir};
 use std::io::Read;
 use std::io::Write;
+use std::path::Path;
+use crate::state::model::Model;
 
+#[derive(Hash)]
 /// Contains the memory of a game cartridge
 pub struct Cartridge {
     /// Rom of the cartridge containing its code
@@ -10,6 +13,15 @@ pub struct Cartridge {
     ram: Vec<u8>,
     /// Path of the save file
     save_file: String,
+    /// MBC1: lower 5 bits of the ROM bank selected for 0x4000-0x7FFF
+    rom_bank: u8,
+    /// MBC1: RAM bank, or upper 2 bits of the ROM bank
+    ram_bank: u8,
+    /// MBC1: can the RAM be read and written
+    is_ram_enabled: bool,
+    /// MBC1: is the advanced banking mode selected (RAM banking and upper
+    /// bits applied to 0x0000-0x3FFF)
+    is_advanced_banking_mode: bool,
 }
 
 impl Cartridge {
@@ -28,15 +40,234 @@ impl Cartridge {
             .expect("Cannot read the cartridge.");
         let mut rom: Vec<u8> = Vec::new();
         file.read_to_end(&mut rom).unwrap();
-        let game_name = file_path.rsplit_once('/').unwrap().1;
+        if let Some(rom_size) = Self::rom_size(&rom) {
+            if rom.len() != rom_size {
+                eprintln!(
+                    "Warning: the header of {} announces {} bytes of ROM but \
+                    the file contains {} bytes",
+                    file_path,
+                    rom_size,
+                    rom.len()
+                );
+                // The missing banks read as an open bus
+                rom.resize(rom_size, 0xFF);
+            }
+        }
+        let game_name = Path::new(file_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
         let save_file = format!("save/{}.save", game_name);
+        let ram_size = Self::ram_size(&rom);
         Self {
             rom,
-            ram: Self::ram_from_save(&save_file),
+            ram: Self::ram_from_save(&save_file, ram_size),
             save_file,
+            rom_bank: 1,
+            ram_bank: 0,
+            is_ram_enabled: false,
+            is_advanced_banking_mode: false,
+        }
+    }
+
+    /// Returns the size of the ROM of a cartridge from its header (0x0148)
+    ///
+    /// # Arguments
+    /// **rom (&[u8])**: ROM of the cartridge
+    ///
+    /// # Returns
+    /// **`Option<usize>`**: Size of the ROM in bytes (32 KiB << value), or
+    /// None if the header is missing or unknown
+    fn rom_size(rom: &[u8]) -> Option<usize> {
+        match rom.get(0x0148).copied() {
+            Some(value @ 0x00..=0x08) => Some(0x8000 << value),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of the RAM of a cartridge from its header (0x0149)
+    ///
+    /// At least 8 KiB are always allocated.
+    ///
+    /// # Arguments
+    /// **rom (&[u8])**: ROM of the cartridge
+    ///
+    /// # Returns
+    /// **usize**: Size of the RAM in bytes
+    fn ram_size(rom: &[u8]) -> usize {
+        match rom.get(0x0149).copied().unwrap_or(0x00) {
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0x2000,
+        }
+    }
+
+    /// Returns the model of GameBoy the game was made for
+    ///
+    /// Reads the CGB flag of the header (0x0143)
+    ///
+    /// # Returns
+    /// **Model**: Model of GameBoy to emulate for this cartridge
+    pub fn model(&self) -> Model {
+        Model::from_cgb_flag(self.rom.get(0x0143).copied().unwrap_or(0x00))
+    }
+
+    /// Checks the global checksum of the header (0x014E-0x014F)
+    ///
+    /// The checksum is the sum of every byte of the ROM except its own two
+    /// bytes, stored in big endian. The GameBoy never checks it, but a wrong
+    /// value hints at a bad dump.
+    ///
+    /// # Returns
+    /// **bool**: true iff the checksum matches the content of the ROM
+    pub fn verify_global_checksum(&self) -> bool {
+        if self.rom.len() < 0x0150 {
+            return false;
+        }
+        let sum = self.rom
+            .iter()
+            .enumerate()
+            .filter(|(address, _)| !matches!(address, 0x014E | 0x014F))
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+        sum == u16::from_be_bytes([self.rom[0x014E], self.rom[0x014F]])
+    }
+
+    /// Returns the name of the mapper of the cartridge
+    ///
+    /// Reads the cartridge type of the header (0x0147)
+    ///
+    /// # Returns
+    /// **&'static str**: Name of the mapper and of the extra hardware
+    pub fn mapper_name(&self) -> &'static str {
+        match self.cartridge_type() {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+
+    /// Returns true iff the mapper of the cartridge is emulated
+    ///
+    /// # Returns
+    /// **bool**: Can the cartridge be emulated correctly
+    pub fn is_supported(&self) -> bool {
+        self.is_mbc1() || matches!(self.cartridge_type(), 0x00 | 0x08 | 0x09)
+    }
+
+    /// Returns true iff the cartridge uses the MBC1 mapper
+    ///
+    /// # Returns
+    /// **bool**: Is the cartridge type MBC1, MBC1+RAM or MBC1+RAM+BATTERY
+    fn is_mbc1(&self) -> bool {
+        matches!(self.cartridge_type(), 0x01..=0x03)
+    }
+
+    /// Returns the ROM bank mapped to 0x4000-0x7FFF
+    ///
+    /// The MBC1 only checks its lower 5 bits to prevent the bank 0 from being
+    /// mapped to the high ROM window: the banks 0x00, 0x20, 0x40 and 0x60 are
+    /// replaced by the banks 0x01, 0x21, 0x41 and 0x61.
+    ///
+    /// # Returns
+    /// **u16**: Number of the ROM bank readable in the high ROM window
+    pub fn current_rom_bank(&self) -> u16 {
+        if !self.is_mbc1() {
+            return 1;
+        }
+        let lower_bits = if self.rom_bank == 0 {
+            1
+        } else {
+            self.rom_bank
+        };
+        (self.ram_bank as u16) << 5 | lower_bits as u16
+    }
+
+    /// Returns the RAM bank mapped to 0xA000-0xBFFF
+    ///
+    /// # Returns
+    /// **u8**: Number of the RAM bank currently readable
+    pub fn current_ram_bank(&self) -> u8 {
+        if self.is_mbc1() && self.is_advanced_banking_mode {
+            self.ram_bank
+        } else {
+            0
+        }
+    }
+
+    /// Returns the ROM bank mapped to 0x0000-0x3FFF
+    ///
+    /// # Returns
+    /// **u16**: Number of the ROM bank readable in the low ROM window
+    fn current_low_rom_bank(&self) -> u16 {
+        if self.is_mbc1() && self.is_advanced_banking_mode {
+            (self.ram_bank as u16) << 5
+        } else {
+            0
         }
     }
 
+    /// Returns the index in the ROM of an address of a bank
+    ///
+    /// Banks that are not in the ROM wrap around its size.
+    ///
+    /// # Arguments
+    /// **bank (u16)**: Number of the bank
+    /// **address (u16)**: Address in the bank window
+    ///
+    /// # Returns
+    /// **usize**: Index of the byte in the ROM
+    fn rom_index(&self, bank: u16, address: u16) -> usize {
+        let n_banks = (self.rom.len() / 0x4000).max(1);
+        (bank as usize % n_banks) * 0x4000 + (address as usize & 0x3FFF)
+    }
+
+    /// Returns the index in the RAM of an address of the external RAM
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address between 0xA000 and 0xBFFF
+    ///
+    /// # Returns
+    /// **usize**: Index of the byte in the RAM
+    fn ram_index(&self, address: u16) -> usize {
+        (self.current_ram_bank() as usize * 0x2000 +
+            (address - 0xA000) as usize) % self.ram.len()
+    }
+
+    /// Returns the cartridge type of the header (0x0147)
+    ///
+    /// # Returns
+    /// **u8**: Type of mapper and extra hardware of the cartridge
+    fn cartridge_type(&self) -> u8 {
+        self.rom.get(0x0147).copied().unwrap_or(0x00)
+    }
+
     /// Checks that the folder save exists
     /// Create a folder save if none exists
     fn check_folder_save() {
@@ -51,18 +282,20 @@ impl Cartridge {
     ///
     /// # Arguments
     /// **save_name (&str)**: Path of the save file
+    /// **size (usize)**: Size of the ram of the cartridge
     ///
     /// # Returns
     /// **`Vec<u8>`**: Ram of the cartridge
-    fn ram_from_save(save_name: &str) -> Vec<u8> {
+    fn ram_from_save(save_name: &str, size: usize) -> Vec<u8> {
         match File::open(save_name) {
             Ok(mut file) => {
                 let mut res: Vec<u8> = Vec::new();
                 file.read_to_end(&mut res).unwrap();
+                res.resize(size.max(res.len()), 0x00);
                 res
             },
             Err(_) => {
-                vec![0x00; 0x2000]
+                vec![0x00; size]
             }
         }
     }
@@ -82,6 +315,40 @@ impl Cartridge {
         file.write_all(&self.ram).unwrap();
     }
 
+    /// Returns every bank of the ram
+    ///
+    /// # Returns
+    /// **&[u8]**: Content of the ram of the cartridge
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Returns the registers of the mapper, for save states
+    ///
+    /// # Returns
+    /// **[u8; 4]**: ROM bank, RAM bank, RAM enable and banking mode
+    pub fn registers(&self) -> [u8; 4] {
+        [
+            self.rom_bank,
+            self.ram_bank,
+            self.is_ram_enabled as u8,
+            self.is_advanced_banking_mode as u8,
+        ]
+    }
+
+    /// Restores the ram and the registers of the mapper
+    ///
+    /// # Arguments
+    /// **ram (&[u8])**: Content of the ram, as returned by ram
+    /// **registers ([u8; 4])**: Registers, as returned by registers
+    pub fn restore(&mut self, ram: &[u8], registers: [u8; 4]) {
+        self.ram = ram.to_vec();
+        self.rom_bank = registers[0];
+        self.ram_bank = registers[1];
+        self.is_ram_enabled = registers[2] != 0;
+        self.is_advanced_banking_mode = registers[3] != 0;
+    }
+
     /// Read a byte in the rom
     ///
     /// # Arguments
@@ -90,7 +357,15 @@ impl Cartridge {
     /// # Returns
     /// **u8**: Byte of the rom at the given address
     pub fn read_rom(&self, address: u16) -> u8 {
-        self.rom[address as usize]
+        if !self.is_mbc1() {
+            return self.rom[address as usize];
+        }
+        let bank = if address < 0x4000 {
+            self.current_low_rom_bank()
+        } else {
+            self.current_rom_bank()
+        };
+        self.rom[self.rom_index(bank, address)]
     }
 
     /// Change a byte in the rom
@@ -103,7 +378,25 @@ impl Cartridge {
         address: u16,
         value: u8
     ) {
-        self.rom[address as usize] = value;
+        // Without mapper, the ROM is read-only and the writes are ignored
+        if !self.is_mbc1() {
+            return;
+        }
+        // The writes to the ROM are sent to the registers of the MBC1
+        match address {
+            0x0000..=0x1FFF => {
+                self.is_ram_enabled = value & 0x0F == 0x0A;
+            },
+            0x2000..=0x3FFF => {
+                self.rom_bank = value & 0x1F;
+            },
+            0x4000..=0x5FFF => {
+                self.ram_bank = value & 0x03;
+            },
+            _ => {
+                self.is_advanced_banking_mode = value & 0x01 == 0x01;
+            }
+        }
     }
 
     /// Read a byte in the ram of the cartridge
@@ -114,7 +407,10 @@ impl Cartridge {
     /// # Returns
     /// **u8**: Byte of the ram at the given address
     pub fn read_ram(&self, address: u16) -> u8 {
-        self.ram[(address - 0xA000) as usize]
+        if self.is_mbc1() && !self.is_ram_enabled {
+            return 0xFF;
+        }
+        self.ram[self.ram_index(address)]
     }
 
     /// Change a byte in the ram of the cartridge
@@ -127,6 +423,147 @@ impl Cartridge {
         address: u16,
         value: u8
     ) {
-        self.ram[(address - 0xA000) as usize] = value;
+        if self.is_mbc1() && !self.is_ram_enabled {
+            return;
+        }
+        let index = self.ram_index(address);
+        self.ram[index] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a cartridge without save whose header has the given type
+    ///
+    /// The ROM has 64 banks, each starting with its number, and the RAM has
+    /// 4 banks.
+    fn cartridge_with_type(cartridge_type: u8) -> Cartridge {
+        let mut rom = vec![0x00; 64 * 0x4000];
+        for bank in 0..64 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0147] = cartridge_type;
+        Cartridge {
+            rom,
+            ram: vec![0x00; 0x8000],
+            save_file: String::new(),
+            rom_bank: 1,
+            ram_bank: 0,
+            is_ram_enabled: false,
+            is_advanced_banking_mode: false,
+        }
+    }
+
+    #[test]
+    fn test_rom_size_from_header() {
+        let path = std::env::temp_dir()
+            .join("gb_emulator_rust_test_rom_size.gb");
+        // MBC1 announcing 8 banks (128 KiB) with only 2 banks in the file
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x02;
+        rom[0x4000] = 0x42;
+        std::fs::write(&path, &rom).unwrap();
+        let mut cartridge = Cartridge::new(&path.display().to_string());
+        assert_eq!(cartridge.rom.len(), 0x20000);
+        assert_eq!(cartridge.read_rom(0x4000), 0x42);
+        cartridge.write_rom(0x2000, 0x05);
+        assert_eq!(cartridge.read_rom(0x4000), 0xFF);
+        assert_eq!(cartridge.read_rom(0x7FFF), 0xFF);
+        // A file longer than announced is truncated
+        rom[0x0148] = 0x00;
+        rom.resize(0x10000, 0x00);
+        std::fs::write(&path, &rom).unwrap();
+        let cartridge = Cartridge::new(&path.display().to_string());
+        assert_eq!(cartridge.rom.len(), 0x8000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_verify_global_checksum() {
+        let mut cartridge = cartridge_with_type(0x00);
+        cartridge.rom.truncate(0x8000);
+        cartridge.rom[0x0134] = 0xFF;
+        cartridge.rom[0x4000] = 0x02;
+        // 0xFF + 0x02, the other bytes are 0x00
+        cartridge.rom[0x014E] = 0x01;
+        cartridge.rom[0x014F] = 0x01;
+        assert!(cartridge.verify_global_checksum());
+        cartridge.rom[0x014F] = 0x02;
+        assert!(!cartridge.verify_global_checksum());
+        cartridge.rom.truncate(0x0100);
+        assert!(!cartridge.verify_global_checksum());
+    }
+
+    #[test]
+    fn test_mapper_name() {
+        let cartridge = cartridge_with_type(0x00);
+        assert_eq!(cartridge.mapper_name(), "ROM ONLY");
+        assert!(cartridge.is_supported());
+        let cartridge = cartridge_with_type(0x03);
+        assert_eq!(cartridge.mapper_name(), "MBC1+RAM+BATTERY");
+        assert!(cartridge.is_supported());
+        let cartridge = cartridge_with_type(0x10);
+        assert_eq!(cartridge.mapper_name(), "MBC3+TIMER+RAM+BATTERY");
+        assert!(!cartridge.is_supported());
+        let cartridge = cartridge_with_type(0x09);
+        assert_eq!(cartridge.mapper_name(), "ROM+RAM+BATTERY");
+        assert!(cartridge.is_supported());
+        assert_eq!(cartridge_with_type(0x04).mapper_name(), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_current_banks() {
+        let mut cartridge = cartridge_with_type(0x03);
+        assert_eq!(cartridge.current_rom_bank(), 1);
+        cartridge.write_rom(0x2000, 0x05);
+        assert_eq!(cartridge.current_rom_bank(), 5);
+        assert_eq!(cartridge.read_rom(0x4000), 5);
+        assert_eq!(cartridge.read_rom(0x0000), 0);
+        // The bank 0 is remapped to the bank 1
+        cartridge.write_rom(0x2000, 0x00);
+        assert_eq!(cartridge.current_rom_bank(), 1);
+        assert_eq!(cartridge.read_rom(0x4000), 1);
+        // RAM banking
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_rom(0x6000, 0x01);
+        cartridge.write_rom(0x4000, 0x02);
+        assert_eq!(cartridge.current_ram_bank(), 2);
+        cartridge.write_ram(0xA000, 0x12);
+        cartridge.write_rom(0x4000, 0x00);
+        assert_eq!(cartridge.current_ram_bank(), 0);
+        assert_eq!(cartridge.read_ram(0xA000), 0x00);
+        cartridge.write_rom(0x4000, 0x02);
+        assert_eq!(cartridge.read_ram(0xA000), 0x12);
+        // Disabled RAM
+        cartridge.write_rom(0x0000, 0x00);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rom_only_ignores_writes() {
+        let mut cartridge = cartridge_with_type(0x00);
+        let rom = cartridge.rom.clone();
+        cartridge.write_rom(0x2000, 0x12);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_rom(0x7FFF, 0x34);
+        assert_eq!(cartridge.rom, rom);
+        assert_eq!(cartridge.read_rom(0x2000), 0x00);
+        assert_eq!(cartridge.read_rom(0x4000), 0x01);
+    }
+
+    #[test]
+    fn test_mbc1_bank_0_remap() {
+        let mut cartridge = cartridge_with_type(0x01);
+        cartridge.write_rom(0x2000, 0x00);
+        assert_eq!(cartridge.read_rom(0x4000), 0x01);
+        // Bank 0x20
+        cartridge.write_rom(0x4000, 0x01);
+        assert_eq!(cartridge.current_rom_bank(), 0x21);
+        assert_eq!(cartridge.read_rom(0x4000), 0x21);
+        cartridge.write_rom(0x2000, 0x02);
+        assert_eq!(cartridge.read_rom(0x4000), 0x22);
     }
 }
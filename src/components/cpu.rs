@@ -1,17 +1,138 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroU32;
+use std::ops::RangeInclusive;
 use std::time::{Duration, SystemTime};
 use std::thread::sleep;
+use crate::components::apu::Apu;
+use crate::components::audio_sink::AudioSink;
+use crate::components::gpu::Gpu;
+use crate::components::memory_bus::MemoryBus;
 use crate::components::mmu::Mmu;
+#[cfg(feature = "recording")]
+use crate::components::recorder::Recorder;
+use crate::state::filter::Filter;
+use crate::state::flags::Flags;
+use crate::state::interrupts::Interrupts;
+use crate::state::key_state::KeyState;
+use crate::state::palette::Palette;
+use crate::state::ram_init::RamInit;
 use crate::state::register::Registers;
+use crate::state::registers_snapshot::RegistersSnapshot;
+use crate::state::save_state::SaveState;
+use crate::state::state_hasher::StateHasher;
+use crate::state::stop_reason::StopReason;
+use crate::state::sync_mode::{Pacing, SyncMode};
+
+/// Prints the given message iff the CPU traces the executed instructions
+macro_rules! trace {
+    ($cpu: expr, $($arg: tt)*) => {
+        if $cpu.is_tracing {
+            println!($($arg)*);
+        }
+    }
+}
+
+/// This macro creates the public accessors of a register of the CPU
+macro_rules! register_accessors {
+    ($register: ident, $type: ty) => {
+        register_accessors!($register, $type, !0);
+    };
+    ($register: ident, $type: ty, $mask: expr) => {
+        paste::item! {
+            #[doc = concat!(
+                "Returns the value of the register ",
+                stringify!($register)
+            )]
+            ///
+            /// # Returns
+            #[doc = concat!(
+                "**", stringify!($type), "**: Value of the register"
+            )]
+            pub fn [< reg_ $register >](&self) -> $type {
+                self.registers.$register
+            }
+
+            #[doc = concat!(
+                "Modify the value of the register ",
+                stringify!($register)
+            )]
+            ///
+            /// # Arguments
+            #[doc = concat!(
+                "**value (", stringify!($type), ")**: New value of the register"
+            )]
+            pub fn [< set_reg_ $register >](&mut self, value: $type) {
+                self.registers.$register = value & $mask;
+            }
+        }
+    };
+    ($register1: ident $register2: ident) => {
+        register_accessors!($register1 $register2, !0);
+    };
+    ($register1: ident $register2: ident, $mask: expr) => {
+        paste::item! {
+            #[doc = concat!(
+                "Returns the value of the 16 bit register ",
+                stringify!($register1),
+                stringify!($register2)
+            )]
+            ///
+            /// # Returns
+            /// **u16**: Value of the 16 bit register
+            pub fn [< reg_ $register1 $register2 >](&self) -> u16 {
+                self.registers.[< get_ $register1 $register2 >]()
+            }
+
+            #[doc = concat!(
+                "Modify the value of the 16 bit register ",
+                stringify!($register1),
+                stringify!($register2)
+            )]
+            ///
+            /// # Arguments
+            /// **value (u16)**: New value of the 16 bit register
+            pub fn [< set_reg_ $register1 $register2 >](&mut self, value: u16) {
+                self.registers.[< set_ $register1 $register2 >](
+                    value & $mask
+                );
+            }
+        }
+    };
+}
+
+/// Number of CPU cycles needed by the GPU to draw one frame
+pub const CYCLES_PER_FRAME: u32 = 70224;
+/// Frequency of the clock of the CPU (4.194304 MHz)
+pub const CLOCK_HZ: u32 = 4_194_304;
+/// Duration of one CPU cycle in nanoseconds at normal speed (~238.4ns)
+pub const NANOS_PER_CYCLE: f64 = 1_000_000_000.0 / CLOCK_HZ as f64;
+/// Number of executed instructions kept for the post-mortem dumps
+const RECENT_INSTRUCTIONS_CAPACITY: usize = 16;
+/// Number of bytes from the top of the stack shown in the post-mortem dumps
+const STACK_DUMP_SIZE: u16 = 8;
+/// Environment variable disabling the frame pacing of run when set to 1
+const NO_SLEEP_VARIABLE: &str = "GB_NO_SLEEP";
 
 /// The CPU of the gameboy
-pub struct CPU {
+///
+/// It reads and writes through a MemoryBus, the Mmu of the GameBoy unless
+/// another one is given to with_bus.
+pub struct CPU<B: MemoryBus = Mmu> {
     /// The registers used by the CPU to store values
     registers: Registers,
     /// The memory management unit allows the CPU to communicate with the
     /// memory
-    mmu: Mmu,
+    mmu: B,
     /// Stops the CPU until an interruption is pending
     is_halted: bool,
+    /// Stops the CPU until a button is pressed
+    is_stopped: bool,
+    /// Was HALT executed with IME reset and an interruption pending, in which
+    /// case the next byte is read twice
+    is_halt_bug: bool,
     /// Enable interruptions
     /// If 1, enable interruptions ; if 2, enable interruptions after next
     /// instruction
@@ -24,126 +145,1384 @@ pub struct CPU {
     ime: bool,
     /// Has the user asked for the program to stop
     should_stop: bool,
+    /// Is the CPU embedded in the event loop of another program, in which
+    /// case it never sleeps and lets the host pace the frames
+    is_embedded: bool,
+    /// Frequency of the clock of the CPU at normal speed
+    clock_hz: u32,
+    /// Should every executed instruction be printed
+    is_tracing: bool,
+    /// Should the known buggy states panic instead of being ignored
+    is_strict: bool,
+    /// What the frame limiter paces the emulation to
+    sync_mode: SyncMode,
+    /// Plays the samples of the APU while run is running
+    audio_sink: Option<Box<dyn AudioSink>>,
+    /// Number of cycles executed since the game started
+    total_cycles: u64,
+    /// Number of cycles executed in double speed mode since the game started
+    double_speed_cycles: u64,
+    /// Address and opcode of the last executed instructions, from the oldest
+    recent_instructions: VecDeque<(u16, u8)>,
+    /// Opcodes before which run_until_break stops
+    break_opcodes: [bool; 256],
+    /// Opcodes prefixed by 0xCB before which run_until_break stops
+    break_cb_opcodes: [bool; 256],
+    /// Number of executions in a row of the same instruction after which
+    /// run_until_break stops, if the detection of infinite loops is on
+    infinite_loop_threshold: Option<NonZeroU32>,
+    /// Writes the completed frames as PNG files while recording
+    #[cfg(feature = "recording")]
+    recorder: Option<Recorder>,
+    /// Frame count of the GPU when the last frame was recorded
+    #[cfg(feature = "recording")]
+    last_recorded_frame: u64,
+}
+
+impl CPU {
+    /// Create the CPU of the gameboy
+    ///
+    /// # Returns
+    /// 
+    /// **CPU**: New instance of CPU
+    pub fn new(cartridge_path: &str) -> Self {
+        Self::with_mmu(Mmu::new(cartridge_path), false)
+    }
+
+    /// Create the CPU of the gameboy to be embedded in another event loop
+    ///
+    /// The CPU does not open any window and never sleeps: the host is
+    /// expected to call run_frame at its own pace.
+    ///
+    /// # Returns
+    ///
+    /// **CPU**: New instance of CPU
+    pub fn new_embedded(cartridge_path: &str) -> Self {
+        Self::with_mmu(Mmu::headless(cartridge_path), true)
+    }
+
+    /// Make the CPU work indefinitively
+    ///
+    /// With GB_NO_SLEEP=1, the CPU never sleeps and runs as fast as possible.
+    /// Otherwise, the steps are paced according to the sync mode.
+    pub fn run(&mut self) {
+        let is_pacing = !Self::is_no_sleep(
+            std::env::var(NO_SLEEP_VARIABLE).ok().as_deref()
+        );
+        while !self.should_stop {
+            let time = SystemTime::now();
+            let time_used = self.execute_step();
+            if is_pacing {
+                let elapsed = time.elapsed().unwrap();
+                sleep(self.pace_step(time_used, elapsed));
+            }
+        }
+        self.shutdown();
+    }
+
+    /// Reads the value of GB_NO_SLEEP
+    ///
+    /// # Arguments
+    /// **value (`Option<&str>`)**: Value of the variable, None if it is not
+    /// set
+    ///
+    /// # Returns
+    /// **bool**: true iff the frame pacing should be disabled
+    fn is_no_sleep(value: Option<&str>) -> bool {
+        value == Some("1")
+    }
+
+    /// Gives the samples of a step to the audio sink and returns how long
+    /// the CPU should sleep in the chosen sync mode
+    ///
+    /// In audio mode, the CPU does not sleep while the buffer of the sink
+    /// may run out, and sleeps the whole duration of the step while the
+    /// samples are produced faster than they are played. Without a sink,
+    /// the audio mode paces the emulation like the video mode.
+    ///
+    /// # Arguments
+    /// **n_cycles (u32)**: Number of cycles used by the step
+    /// **elapsed (Duration)**: Time already spent executing the step
+    ///
+    /// # Returns
+    /// **Duration**: Time to sleep
+    fn pace_step(&mut self, n_cycles: u32, elapsed: Duration) -> Duration {
+        let Some(sink) = self.audio_sink.as_mut() else {
+            return self.sleep_duration(n_cycles, elapsed);
+        };
+        sink.queue(&self.mmu.audio_samples());
+        let pacing = Pacing::from_audio_buffer(
+            sink.buffered(),
+            sink.capacity()
+        );
+        match (self.sync_mode, pacing) {
+            (SyncMode::Audio, Pacing::RunFaster) => Duration::ZERO,
+            (SyncMode::Audio, Pacing::Wait) => {
+                self.sleep_duration(n_cycles, Duration::ZERO)
+            },
+            _ => self.sleep_duration(n_cycles, elapsed),
+        }
+    }
+
+    /// Stops the emulation, saving the RAM of the cartridge and ending the
+    /// recording if there is one
+    ///
+    /// Called when run stops, front-ends driving run_frame should call it
+    /// before exiting.
+    pub fn shutdown(&mut self) {
+        self.should_stop = true;
+        #[cfg(feature = "recording")]
+        self.stop_recording();
+        self.mmu.close();
+    }
+
+    /// Runs without ever sleeping until the next VBlank interruption is
+    /// requested, to step one frame at a time
+    ///
+    /// The breakpoints are respected. If the LCD is off, the CPU stops after
+    /// the duration of two frames.
+    ///
+    /// # Returns
+    /// **StopReason**: Why the CPU stopped (VBlank at the end of the frame)
+    pub fn run_to_vblank(&mut self) -> StopReason {
+        let frame_count = self.frame_count();
+        self.run_debugger(
+            2 * CYCLES_PER_FRAME as u64,
+            move |cpu| cpu.frame_count() != frame_count
+        )
+    }
+
+    /// Runs without ever sleeping until the serial port receives a text
+    ///
+    /// Test ROMs usually signal their completion by printing a given text on
+    /// the serial port.
+    ///
+    /// # Arguments
+    /// **needle (&str)**: Text expected in the serial output
+    /// **max_cycles (u64)**: Number of cycles after which the CPU gives up
+    ///
+    /// # Returns
+    /// **bool**: true iff the serial output contains the text before the
+    /// timeout
+    pub fn run_until_serial_contains(
+        &mut self,
+        needle: &str,
+        max_cycles: u64
+    ) -> bool {
+        let needle = needle.as_bytes();
+        let contains_needle = |output: &[u8]| needle.is_empty() ||
+            output.windows(needle.len()).any(|window| window == needle);
+        let mut n_cycles = 0;
+        let mut checked_length = 0;
+        while n_cycles < max_cycles && !self.should_stop {
+            n_cycles += self.execute_step() as u64;
+            let output = self.mmu.serial_output();
+            if output.len() != checked_length {
+                checked_length = output.len();
+                if contains_needle(output) {
+                    return true;
+                }
+            }
+        }
+        contains_needle(self.mmu.serial_output())
+    }
+
+    /// Change what buttons are pressed, for a CPU without window
+    ///
+    /// # Arguments
+    /// **keys (KeyState)**: Buttons pressed from now on
+    pub fn set_buttons(&mut self, keys: KeyState) {
+        self.mmu.set_buttons(keys);
+    }
+
+    /// Change what buttons are pressed by a player
+    ///
+    /// Only the buttons of the player 0 (the keyboard) are read by the game,
+    /// the other players are kept for the link cable. Ignored for the
+    /// players other than 0 and 1.
+    ///
+    /// # Arguments
+    /// **player (usize)**: Index of the player (0 or 1)
+    /// **keys (KeyState)**: Buttons pressed from now on
+    pub fn set_buttons_for(&mut self, player: usize, keys: KeyState) {
+        self.mmu.set_buttons_for(player, keys);
+    }
+
+    /// Returns the buttons pressed by a player
+    ///
+    /// # Arguments
+    /// **player (usize)**: Index of the player (0 or 1)
+    ///
+    /// # Returns
+    /// **`Option<&KeyState>`**: Buttons pressed by this player, None for the
+    /// players other than 0 and 1
+    pub fn buttons_for(&self, player: usize) -> Option<&KeyState> {
+        self.mmu.gpu().key_state_for(player)
+    }
+
+    /// Returns the text sent by the game through the serial port
+    ///
+    /// # Returns
+    /// **String**: Bytes sent through the serial port read as text
+    pub fn serial_output(&self) -> String {
+        String::from_utf8_lossy(self.mmu.serial_output()).into_owned()
+    }
+
+    /// Starts logging the writes to a region of the memory, for example the
+    /// VRAM to see how a game draws
+    ///
+    /// # Arguments
+    /// **range (`RangeInclusive<u16>`)**: Addresses whose writes are logged
+    pub fn log_writes(&mut self, range: RangeInclusive<u16>) {
+        self.mmu.log_writes(range);
+    }
+
+    /// Returns the writes logged since the last call
+    ///
+    /// The writes made while an interruption is dispatched are attributed to
+    /// the last instruction executed.
+    ///
+    /// # Returns
+    /// **`Vec<(u16, u16, u8)>`**: Address of the instruction, address and
+    /// value of each write to the region given to log_writes, in order
+    pub fn take_write_log(&mut self) -> Vec<(u16, u16, u8)> {
+        self.mmu.take_write_log()
+    }
+
+    /// Checks the global checksum of the header of the cartridge
+    ///
+    /// The GameBoy ignores it, but it helps validating a dump.
+    ///
+    /// # Returns
+    /// **bool**: true iff the checksum matches the content of the ROM
+    pub fn verify_global_checksum(&self) -> bool {
+        self.mmu.verify_global_checksum()
+    }
+
+    /// Returns the number of frames completed by the PPU
+    ///
+    /// Unlike the number of cycles, it does not advance while the LCD is off,
+    /// which makes it suited to frame-based input scripts.
+    ///
+    /// # Returns
+    /// **u64**: Number of times the PPU entered the VBlank
+    pub fn frame_count(&self) -> u64 {
+        self.mmu.gpu().frame_count()
+    }
+
+    /// Starts writing every completed frame as a PNG file in a directory
+    ///
+    /// # Arguments
+    /// **directory (&str)**: Directory where the frames are written, created
+    /// if it does not exist
+    ///
+    /// # Returns
+    /// **`io::Result<()>`**: Error encountered while creating the directory,
+    /// if any
+    #[cfg(feature = "recording")]
+    pub fn start_recording(&mut self, directory: &str) -> std::io::Result<()> {
+        self.recorder = Some(Recorder::new(std::path::Path::new(directory))?);
+        self.last_recorded_frame = self.mmu.gpu().frame_count();
+        Ok(())
+    }
+
+    /// Ejects the current game and starts a new one
+    ///
+    /// The RAM of the current cartridge is saved, then the CPU and every
+    /// memory area are reset. The window and the settings of the emulator are
+    /// kept.
+    ///
+    /// # Arguments
+    /// **cartridge_path (&str)**: Path of the file containing the ROM of the
+    /// new game
+    pub fn load_cartridge(&mut self, cartridge_path: &str) {
+        self.mmu.load_cartridge(cartridge_path);
+        self.registers = Registers::new();
+        self.is_halted = false;
+        self.is_halt_bug = false;
+        self.is_stopped = false;
+        self.ei = 0;
+        self.di = 0;
+        self.ime = true;
+        self.should_stop = false;
+        self.total_cycles = 0;
+        self.double_speed_cycles = 0;
+        self.recent_instructions.clear();
+    }
+
+    /// Copy the state of the whole gameboy
+    ///
+    /// # Returns
+    /// **SaveState**: Copy of the state, that can be given to load_state
+    pub fn save_state(&self) -> SaveState {
+        let mut state = self.mmu.save_state();
+        state.registers = self.registers.clone();
+        state.is_halted = self.is_halted;
+        state.is_stopped = self.is_stopped;
+        state.is_halt_bug = self.is_halt_bug;
+        state.ei = self.ei;
+        state.di = self.di;
+        state.ime = self.ime;
+        state
+    }
+
+    /// Restore the state of the whole gameboy
+    ///
+    /// # Arguments
+    /// **state (&SaveState)**: State to restore, as returned by save_state
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.mmu.load_state(state);
+        self.registers = state.registers.clone();
+        self.is_halted = state.is_halted;
+        self.is_stopped = state.is_stopped;
+        self.is_halt_bug = state.is_halt_bug;
+        self.ei = state.ei;
+        self.di = state.di;
+        self.ime = state.ime;
+    }
+
+    /// Copy the state of the whole gameboy as JSON
+    ///
+    /// Unlike save_state, the result can be read and edited by a human.
+    ///
+    /// # Returns
+    /// **String**: State, that can be given to load_state_json
+    #[cfg(feature = "json")]
+    pub fn save_state_json(&self) -> String {
+        serde_json::to_string_pretty(&self.save_state())
+            .expect("A save state is always serializable")
+    }
+
+    /// Restore the state of the whole gameboy from JSON
+    ///
+    /// # Arguments
+    /// **json (&str)**: State to restore, as returned by save_state_json
+    ///
+    /// # Returns
+    /// **`Result<(), String>`**: Error if the JSON is not a valid state or
+    /// does not fit the current cartridge, in which case the gameboy is not
+    /// modified
+    #[cfg(feature = "json")]
+    pub fn load_state_json(&mut self, json: &str) -> Result<(), String> {
+        let state: SaveState = serde_json::from_str(json)
+            .map_err(|error| error.to_string())?;
+        self.mmu.validate_state(&state)?;
+        self.load_state(&state);
+        Ok(())
+    }
+
+    /// Enables or disables the strict debug mode
+    ///
+    /// By default, the emulator is lenient: an illegal opcode is ignored and
+    /// the program keeps running. In strict mode, executing an illegal
+    /// opcode, executing code from the prohibited area 0xFEA0-0xFEFF or
+    /// writing to a mapper that is not emulated panics, to surface these
+    /// problems while debugging.
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the known buggy states panic
+    pub fn set_strict_debug(&mut self, value: bool) {
+        self.is_strict = value;
+        self.mmu.set_strict_debug(value);
+    }
+
+    /// Executes one step and returns everything needed to compare it to a
+    /// single-step test vector
+    ///
+    /// # Returns
+    /// **(RegistersSnapshot, RegistersSnapshot, `Vec<(u16, u8)>`)**:
+    /// Registers before and after the step, and the address and value of
+    /// each byte read or written during the step, in order
+    #[cfg(any(test, feature = "test-vectors"))]
+    pub fn trace_step(
+        &mut self
+    ) -> (RegistersSnapshot, RegistersSnapshot, Vec<(u16, u8)>) {
+        let before = self.registers();
+        self.mmu.start_access_log();
+        self.execute_step();
+        let accesses = self.mmu.take_access_log();
+        (before, self.registers(), accesses)
+    }
+
+    /// Returns a hash of the whole state of the gameboy
+    ///
+    /// The registers, the interruption state and every memory area are
+    /// hashed with a stable hasher, so that two runs can be compared quickly.
+    ///
+    /// # Returns
+    /// **u64**: Hash of the state of the gameboy
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        self.registers.hash(&mut hasher);
+        self.is_halted.hash(&mut hasher);
+        self.ei.hash(&mut hasher);
+        self.di.hash(&mut hasher);
+        self.ime.hash(&mut hasher);
+        self.mmu.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Gives access to the GPU, for example to inspect its registers
+    ///
+    /// # Returns
+    /// **&Gpu**: GPU of the gameboy
+    pub fn gpu(&self) -> &Gpu {
+        self.mmu.gpu()
+    }
+
+    /// Gives access to the APU, for example to inspect its channels
+    ///
+    /// # Returns
+    /// **&Apu**: APU of the gameboy
+    pub fn apu(&self) -> &Apu {
+        self.mmu.apu()
+    }
+
+    /// Change the samples produced by the APU (44100 Hz stereo by default)
+    ///
+    /// # Arguments
+    /// **sample_rate (u32)**: Number of samples for each second of emulation
+    /// **stereo (bool)**: Should the left and right samples be interleaved,
+    /// otherwise a single sample is produced at a time
+    pub fn set_audio_output(&mut self, sample_rate: u32, stereo: bool) {
+        self.mmu.set_audio_output(sample_rate, stereo);
+    }
+
+    /// Returns the samples produced by the APU since the last call
+    ///
+    /// Only the last second is kept when they are not taken.
+    ///
+    /// # Returns
+    /// **`Vec<f32>`**: Samples between 0 and 1, left then right in stereo
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.mmu.audio_samples()
+    }
+
+    /// Plays the samples of the APU while run is running
+    ///
+    /// The samples are given to the sink after every step instead of being
+    /// kept for audio_samples.
+    ///
+    /// # Arguments
+    /// **sink (`Box<dyn AudioSink>`)**: Audio output of the host
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio_sink = Some(sink);
+    }
+
+    /// Change the maximum number of objects drawn on a single line
+    ///
+    /// The hardware limit is 10, it can be raised to see every object while
+    /// debugging.
+    ///
+    /// # Arguments
+    /// **value (usize)**: New maximum number of objects per line
+    pub fn set_max_sprites_per_line(&mut self, value: usize) {
+        self.mmu.set_max_sprites_per_line(value);
+    }
+
+    /// Replaces the colors displayed by those of a .pal file
+    ///
+    /// The file contains four colors written as RRGGBB, one per line from
+    /// the lightest to the darkest shade.
+    ///
+    /// # Arguments
+    /// **path (&str)**: Path of the .pal file
+    ///
+    /// # Returns
+    /// **`Result<(), String>`**: Message explaining why the file is invalid,
+    /// if it is
+    pub fn load_palette_file(&mut self, path: &str) -> Result<(), String> {
+        self.mmu.load_palette_file(std::path::Path::new(path))
+    }
+
+    /// Replaces the colors displayed for the shades of the GameBoy
+    ///
+    /// # Arguments
+    /// **palette (Palette)**: New colors of the shades, from the lightest to
+    /// the darkest
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.mmu.set_palette(palette);
+    }
+
+    /// Change the post-processing applied to the image shown in the window
+    ///
+    /// The frames returned by framebuffer and capture_frame are not filtered.
+    ///
+    /// # Arguments
+    /// **filter (Filter)**: Scanlines, LCD grid or none (default)
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.mmu.set_filter(filter);
+    }
+
+    /// Gives the color of each pixel with a function instead of the palette
+    ///
+    /// The function is called for every pixel, so it can change the colors
+    /// from one frame to the next (fade to black...).
+    ///
+    /// # Arguments
+    /// **color_mapper (impl FnMut(u8) -> u32)**: Function returning the
+    /// 0xRRGGBB color of a shade (00 to 11 for white to black)
+    pub fn set_color_mapper(
+        &mut self,
+        color_mapper: impl FnMut(u8) -> u32 + 'static
+    ) {
+        self.mmu.set_color_mapper(color_mapper);
+    }
+
+    /// Gives the color of each pixel with the palette again
+    pub fn clear_color_mapper(&mut self) {
+        self.mmu.clear_color_mapper();
+    }
+
+    /// Change how the priority between two overlapping objects is decided
+    ///
+    /// By default, it depends on the model the cartridge was made for.
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the objects with a smaller x be drawn on top
+    /// (true for DMG), otherwise the OAM order is used (CGB)
+    pub fn set_oam_x_priority(&mut self, value: bool) {
+        self.mmu.set_oam_x_priority(value);
+    }
+
+    /// Change the value read from the addresses mapped to nothing
+    ///
+    /// # Arguments
+    /// **value (u8)**: New open bus value (0xFF by default)
+    pub fn set_open_bus(&mut self, value: u8) {
+        self.mmu.set_open_bus(value);
+    }
+
+    /// Change the content of the WRAM, the VRAM and the HRAM at power up
+    ///
+    /// # Arguments
+    /// **pattern (RamInit)**: Values of the RAM, applied immediately and at
+    /// each load of a cartridge (Zero by default)
+    pub fn set_ram_init_pattern(&mut self, pattern: RamInit) {
+        self.mmu.set_ram_init_pattern(pattern);
+    }
+
+    /// Change what the accesses to the prohibited area 0xFEA0~0xFEFF do
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the area mirror the start of the OAM (hardware
+    /// quirk) instead of being open bus (default)
+    pub fn set_oam_gap_corruption(&mut self, value: bool) {
+        self.mmu.set_oam_gap_corruption(value);
+    }
+
+    /// Returns true iff the PPU completed a frame since the last capture
+    ///
+    /// Front-ends can skip updating their window while it is false.
+    ///
+    /// # Returns
+    /// **bool**: Is a new frame ready to be presented
+    pub fn frame_ready(&self) -> bool {
+        self.mmu.gpu().frame_ready()
+    }
+
+    /// Returns the last frame drawn and marks it as presented
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn capture_frame(&mut self) -> Vec<u32> {
+        self.mmu.capture_frame()
+    }
+
+    /// Returns the last completed frame
+    ///
+    /// Unlike capture_frame, it does not change while a frame is being drawn.
+    ///
+    /// # Returns
+    /// **&[u32]**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn framebuffer(&self) -> &[u32] {
+        self.mmu.gpu().framebuffer()
+    }
+
+    /// Returns the last completed frame in shades of gray
+    ///
+    /// It is four times smaller than the colored frame, which makes it handy
+    /// to hash or compare frames.
+    ///
+    /// # Returns
+    /// **`Vec<u8>`**: Luminance of each pixel of the displayed colors, line
+    /// by line (160 x 144)
+    pub fn frame_grayscale(&self) -> Vec<u8> {
+        self.mmu
+            .gpu()
+            .framebuffer()
+            .iter()
+            .map(|color| Palette::luminance(*color))
+            .collect()
+    }
+
+    /// Writes the last frame if it was completed since the last recording
+    ///
+    /// The recording stops if the frame cannot be written.
+    #[cfg(feature = "recording")]
+    fn record_frame(&mut self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        let frame_count = self.mmu.gpu().frame_count();
+        if frame_count == self.last_recorded_frame {
+            return;
+        }
+        self.last_recorded_frame = frame_count;
+        if let Err(e) = recorder.record(self.mmu.gpu().framebuffer()) {
+            eprintln!("Recording stopped: {}", e);
+            self.recorder = None;
+        }
+    }
 }
 
-impl CPU {
-    /// Create the CPU of the gameboy
+impl<B: MemoryBus + 'static> CPU<B> {
+    /// Create a CPU communicating with the given memory instead of the Mmu
+    ///
+    /// The CPU does not open any window and never sleeps, like with
+    /// new_embedded.
+    ///
+    /// # Arguments
+    /// **bus (B)**: Memory used by the CPU
+    ///
+    /// # Returns
+    ///
+    /// **CPU**: New instance of CPU
+    pub fn with_bus(bus: B) -> Self {
+        Self::with_mmu(bus, true)
+    }
+
+    /// Create the CPU of the gameboy communicating with the given memory
+    ///
+    /// # Arguments
+    /// **mmu (B)**: Memory used by the CPU
+    /// **is_embedded (bool)**: Should the CPU let the host pace the frames
+    ///
+    /// # Returns
+    ///
+    /// **CPU**: New instance of CPU
+    fn with_mmu(mmu: B, is_embedded: bool) -> Self {
+        CPU{
+            registers: Registers::new(),
+            mmu,
+            is_halted: false,
+            is_halt_bug: false,
+            is_stopped: false,
+            ei: 0,
+            di: 0,
+            ime: true,
+            should_stop: false,
+            is_embedded,
+            clock_hz: CLOCK_HZ,
+            is_tracing: false,
+            is_strict: false,
+            sync_mode: SyncMode::default(),
+            audio_sink: None,
+            total_cycles: 0,
+            double_speed_cycles: 0,
+            recent_instructions: VecDeque::with_capacity(
+                RECENT_INSTRUCTIONS_CAPACITY
+            ),
+            break_opcodes: [false; 256],
+            break_cb_opcodes: [false; 256],
+            infinite_loop_threshold: None,
+            #[cfg(feature = "recording")]
+            recorder: None,
+            #[cfg(feature = "recording")]
+            last_recorded_frame: 0,
+        }
+    }
+
+    /// Gets an immediate value as a byte in the instructions of the code
+    ///
+    /// # Retuns
+    /// **u8**: Byte read in the code of the program
+    fn fetchbyte(&mut self) -> u8 {
+        let res = self.mmu.read_byte(self.registers.pc);
+        trace!(
+            self,
+            "Reading a byte at pc = {:#04x}: res = {:#02x}",
+            self.registers.pc,
+            res
+        );
+        // After the HALT bug, the program counter fails to be incremented once
+        if self.is_halt_bug {
+            self.is_halt_bug = false;
+        } else {
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+        }
+        res
+    }
+
+    /// Gets an immediate value as a word in the instructions of the code
+    ///
+    /// # Retuns
+    /// **u16**: Word read in the code of the program
+    fn fetchword(&mut self) -> u16 {
+        let res = self.read_word(self.registers.pc);
+        trace!(
+            self,
+            "Reading a word at pc = {:#04x}: res = {:#04x}",
+            self.registers.pc,
+            res
+        );
+        self.registers.pc = self.registers.pc.wrapping_add(2);
+        res
+    }
+
+    /// Sends a stop message to the MMU
+    ///
+    /// Switch the speed of the Memory Management Unit if it was prepared,
+    /// stops the CPU until a button is pressed otherwise
+    fn send_stop(&mut self) {
+        self.is_stopped = self.gameboy_mut()
+            .is_none_or(|cpu| cpu.mmu.receive_stop());
+    }
+
+    /// Stops the gameboy until an interruption is triggered
+    ///
+    /// The IME is evaluated once the pending DI or EI took effect. If it is
+    /// reset while an interruption is already pending, the CPU does not halt
+    /// and reads the next byte twice instead (HALT bug).
+    fn halt(&mut self) {
+        let ime = if self.di != 0 {
+            false
+        } else {
+            self.ime || self.ei != 0
+        };
+        if !ime && self.has_pending_interruption() {
+            self.is_halt_bug = true;
+        } else {
+            self.is_halted = true;
+        }
+    }
+
+    /// Returns true iff an enabled interruption was requested
+    ///
+    /// # Returns
+    /// **bool**: Is an interruption both requested (IF) and enabled (IE)
+    fn has_pending_interruption(&self) -> bool {
+        self.interrupt_flag() & self.interrupt_enable() & 0x1F != 0
+    }
+
+    /// Returns the interruptions requested (IF)
+    ///
+    /// The Mmu is read directly so that the access is not logged.
+    ///
+    /// # Returns
+    /// **u8**: Value of the interrupt flag
+    fn interrupt_flag(&self) -> u8 {
+        match self.gameboy() {
+            Some(cpu) => cpu.mmu.interrupt_flag,
+            None => self.mmu.read_byte(0xFF0F),
+        }
+    }
+
+    /// Modify the interruptions requested (IF)
+    ///
+    /// # Arguments
+    /// **value (u8)**: New value of the interrupt flag
+    fn set_interrupt_flag(&mut self, value: u8) {
+        match self.gameboy_mut() {
+            Some(cpu) => cpu.mmu.interrupt_flag = value,
+            None => self.mmu.write_byte(0xFF0F, value),
+        }
+    }
+
+    /// Returns the interruptions enabled (IE)
+    ///
+    /// # Returns
+    /// **u8**: Value of the interrupt enable register
+    fn interrupt_enable(&self) -> u8 {
+        match self.gameboy() {
+            Some(cpu) => cpu.mmu.ie,
+            None => self.mmu.read_byte(0xFFFF),
+        }
+    }
+
+    /// Is the CPU running at twice its normal speed
+    ///
+    /// Only the Mmu of the GameBoy Color has a double speed mode.
+    ///
+    /// # Returns
+    /// **bool**: true iff the double speed mode is on
+    fn is_double_speed(&self) -> bool {
+        self.gameboy().is_some_and(|cpu| cpu.mmu.is_double_speed())
+    }
+
+    /// Returns this CPU if it communicates with the Mmu of the GameBoy
+    ///
+    /// The hardware that is not reached through the memory (speed switch,
+    /// joypad, screen) only exists behind the Mmu.
+    ///
+    /// # Returns
+    /// **Option<&CPU>**: This CPU, None if it uses another memory
+    fn gameboy(&self) -> Option<&CPU> {
+        (self as &dyn Any).downcast_ref::<CPU>()
+    }
+
+    /// Returns this CPU if it communicates with the Mmu of the GameBoy
+    ///
+    /// # Returns
+    /// **Option<&mut CPU>**: This CPU, None if it uses another memory
+    fn gameboy_mut(&mut self) -> Option<&mut CPU> {
+        (self as &mut dyn Any).downcast_mut::<CPU>()
+    }
+
+    /// Read a word in the memory, the high byte first
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the word to read
+    ///
+    /// # Returns
+    /// **u16**: Value of the word at this address
+    fn read_word(&self, address: u16) -> u16 {
+        ((self.mmu.read_byte(address.wrapping_add(1)) as u16) << 8) |
+            (self.mmu.read_byte(address) as u16)
+    }
+
+    /// Change a word in the memory, the high byte first
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the word to modify
+    /// **value (u16)**: New value to put at this address
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.mmu.write_byte(address.wrapping_add(1), (value >> 8) as u8);
+        self.mmu.write_byte(address, (value & 0x00FF) as u8);
+    }
+
+    /// Pops a value from the stack
+    ///
+    /// # Returns
+    /// **u16**: value popped from the stack
+    fn pop(&mut self) -> u16 {
+        let res = self.read_word(
+            self.registers.sp
+        );
+        self.registers.sp = self.registers.sp.wrapping_add(2);
+        res
+    }
+
+    /// Pushes the given value in the stack
+    ///
+    /// # Arguments
+    /// **value (u16)**: value to push in the stack
+    fn push(&mut self, value: u16) {
+        self.registers.sp = self.registers.sp.wrapping_sub(2);
+        self.write_word(
+            self.registers.sp,
+            value
+        );
+    }
+
+    /// Jumps to the given value after pushing the value of the program counter
+    /// in the stack
+    ///
+    /// # Arguments
+    /// **value (u16)**: new value of the program counter
+    fn rst(&mut self, value: u16) {
+        self.push(self.registers.pc);
+        self.registers.pc = value;
+    }
+
+    /// Jumps by the value of the next immediate in the program read as a real
+    fn jr(&mut self) {
+        // Les conversions permettent d'assurer que fetchbyte est considéré
+        // comme signé, mais pas pc, que l'opérations puisse avoir lieu, et
+        // que le résutat ait le bon format. Le saut est relatif à l'adresse
+        // qui suit l'opérande, qui doit donc être lue en premier
+        let offset = self.fetchbyte() as i8 as i32;
+        self.registers.pc = (
+            (self.registers.pc as u32 as i32) + offset
+        ) as u16;
+    }
+
+    /// Make the CPU work for the duration of one frame
+    ///
+    /// # Returns
+    /// **u32**: Number of cycles used during the frame
+    pub fn run_frame(&mut self) -> u32 {
+        let mut n_cycles = 0;
+        while n_cycles < CYCLES_PER_FRAME && !self.should_stop {
+            let time = SystemTime::now();
+            let time_used = self.execute_step();
+            sleep(self.sleep_duration(time_used, time.elapsed().unwrap()));
+            n_cycles += time_used;
+        }
+        n_cycles
+    }
+
+    /// Make the CPU work without ever sleeping for a number of cycles
+    ///
+    /// # Arguments
+    /// **max_cycles (u64)**: Number of cycles after which the CPU stops
+    ///
+    /// # Returns
+    /// **u64**: Number of cycles actually used
+    pub fn run_headless(&mut self, max_cycles: u64) -> u64 {
+        let mut n_cycles = 0;
+        while n_cycles < max_cycles && !self.should_stop {
+            n_cycles += self.execute_step() as u64;
+        }
+        n_cycles
+    }
+
+    /// Stops run_until_break before each instruction with the given opcode
+    ///
+    /// # Arguments
+    /// **opcode (u8)**: Opcode of the instructions
+    pub fn break_on_opcode(&mut self, opcode: u8) {
+        self.break_opcodes[opcode as usize] = true;
+    }
+
+    /// Stops run_until_break before each instruction with the given opcode
+    /// after the 0xCB prefix
+    ///
+    /// # Arguments
+    /// **opcode (u8)**: Opcode of the instructions, following 0xCB
+    pub fn break_on_cb_opcode(&mut self, opcode: u8) {
+        self.break_cb_opcodes[opcode as usize] = true;
+    }
+
+    /// Removes every breakpoint set on an opcode
+    pub fn clear_opcode_breaks(&mut self) {
+        self.break_opcodes = [false; 256];
+        self.break_cb_opcodes = [false; 256];
+    }
+
+    /// Stops run_until_break when the same instruction is executed again and
+    /// again, like a JR -2 waiting for an interruption that never comes
+    ///
+    /// An interruption handled or a HALT resets the count.
+    ///
+    /// # Arguments
+    /// **threshold (`Option<NonZeroU32>`)**: Number of executions in a row
+    /// of the instruction, None to disable the detection (the default)
+    pub fn set_infinite_loop_threshold(
+        &mut self,
+        threshold: Option<NonZeroU32>
+    ) {
+        self.infinite_loop_threshold = threshold;
+    }
+
+    /// Is the next instruction one of the opcodes to break on
+    ///
+    /// # Returns
+    /// **bool**: True iff the instruction at PC is about to be executed and
+    /// has a breakpoint on its opcode
+    fn is_at_opcode_break(&self) -> bool {
+        if self.is_halted || self.is_stopped {
+            return false;
+        }
+        let pc = self.reg_pc();
+        let opcode = self.mmu.read_byte(pc);
+        if opcode == 0xCB {
+            let cb_opcode = self.mmu.read_byte(pc.wrapping_add(1));
+            self.break_opcodes[0xCB] || self.break_cb_opcodes[cb_opcode as usize]
+        } else {
+            self.break_opcodes[opcode as usize]
+        }
+    }
+
+    /// Runs without ever sleeping until a breakpoint is reached
+    ///
+    /// The instruction at PC when called is always executed, so that calling
+    /// it again resumes after a breakpoint.
+    ///
+    /// # Arguments
+    /// **max_cycles (u64)**: Number of cycles after which the CPU stops
+    ///
+    /// # Returns
+    /// **StopReason**: Why the CPU stopped
+    pub fn run_until_break(&mut self, max_cycles: u64) -> StopReason {
+        self.run_debugger(max_cycles, |_| false)
+    }
+
+    /// Runs without ever sleeping until a breakpoint, an infinite loop or a
+    /// VBlank is reached
+    ///
+    /// The instruction at PC when called is always executed.
+    ///
+    /// # Arguments
+    /// **max_cycles (u64)**: Number of cycles after which the CPU stops
+    /// **is_vblank (impl Fn(&CPU) -> bool)**: Should the CPU stop because
+    /// of a VBlank after the last step
     ///
     /// # Returns
-    /// 
-    /// **CPU**: New instance of CPU
-    pub fn new(cartridge_path: &str) -> Self {
-        CPU{
-            registers: Registers::new(),
-            mmu: Mmu::new(cartridge_path),
-            is_halted: false,
-            ei: 0,
-            di: 0,
-            ime: true,
-            should_stop: false,
+    /// **StopReason**: Why the CPU stopped
+    fn run_debugger(
+        &mut self,
+        max_cycles: u64,
+        is_vblank: impl Fn(&Self) -> bool
+    ) -> StopReason {
+        let mut n_cycles = 0;
+        let mut n_repeats = 0;
+        while n_cycles < max_cycles {
+            if self.should_stop {
+                return StopReason::Exit;
+            }
+            if n_cycles > 0 && self.is_at_opcode_break() {
+                return StopReason::OpcodeBreak;
+            }
+            if self.infinite_loop_threshold
+                .is_some_and(|threshold| n_repeats >= threshold.get())
+            {
+                return StopReason::InfiniteLoop(self.registers.pc);
+            }
+            let pc = self.registers.pc;
+            n_cycles += self.execute_step() as u64;
+            // Handling an interruption jumps to its handler, so only the
+            // instructions jumping to themselves are counted
+            if self.registers.pc == pc && !self.is_halted && !self.is_stopped {
+                n_repeats += 1;
+            } else {
+                n_repeats = 0;
+            }
+            if is_vblank(self) {
+                return StopReason::VBlank;
+            }
         }
+        StopReason::CycleLimit
     }
 
-    /// Gets an immediate value as a byte in the instructions of the code
+    /// Returns the interruptions both requested (IF) and enabled (IE)
     ///
-    /// # Retuns
-    /// **u8**: Byte read in the code of the program
-    fn fetchbyte(&mut self) -> u8 {
-        let res = self.mmu.read_byte(self.registers.pc);
-        println!(
-            "Reading a byte at pc = {:#04x}: res = {:#02x}",
-            self.registers.pc,
-            res
-        );
-        self.registers.pc = self.registers.pc.wrapping_add(1);
-        res
+    /// They are handled as soon as IME is set, the VBlank first.
+    ///
+    /// # Returns
+    /// **Interrupts**: Interruptions waiting to be handled
+    pub fn pending_interrupts(&self) -> Interrupts {
+        Interrupts::from_bits(
+            self.interrupt_flag() & self.interrupt_enable() & 0x1F
+        )
     }
 
-    /// Gets an immediate value as a word in the instructions of the code
+    /// Requests interruptions as if the peripherals had
     ///
-    /// # Retuns
-    /// **u16**: Word read in the code of the program
-    fn fetchword(&mut self) -> u16 {
-        let res = self.mmu.read_word(self.registers.pc);
-        println!(
-            "Reading a word at pc = {:#04x}: res = {:#04x}",
-            self.registers.pc,
-            res
-        );
-        self.registers.pc = self.registers.pc.wrapping_add(2);
-        res
+    /// The bits are set in IF, the interruptions are handled once IME and
+    /// their bit of IE are set.
+    ///
+    /// # Arguments
+    /// **which (Interrupts)**: Interruptions to request
+    pub fn request_interrupt(&mut self, which: Interrupts) {
+        let interrupt_flag = self.interrupt_flag();
+        self.set_interrupt_flag(interrupt_flag | which.bits());
     }
 
-    /// Sends a stop message to the MMU
+    /// Returns how long the CPU should sleep after a step to run at the speed
+    /// of the GameBoy
     ///
-    /// Switch the speed of the Memory Management Unit
-    fn send_stop(&mut self) {
-        self.mmu.receive_stop();
+    /// An embedded CPU never sleeps.
+    ///
+    /// # Arguments
+    /// **n_cycles (u32)**: Number of cycles used by the step
+    /// **elapsed (Duration)**: Time already spent executing the step
+    ///
+    /// # Returns
+    /// **Duration**: Time to sleep
+    fn sleep_duration(&self, n_cycles: u32, elapsed: Duration) -> Duration {
+        if self.is_embedded {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(
+            (self.nanos_per_cycle() * n_cycles as f64) as u64
+        ).saturating_sub(elapsed)
     }
 
-    /// Stops the gameboy until an interruption is triggered
-    fn halt(&mut self) {
-        self.is_halted = true;
+    /// Chooses what the frame limiter of run paces the emulation to
+    ///
+    /// The audio mode follows the buffer of the audio sink, it needs one to
+    /// be set with set_audio_sink.
+    ///
+    /// # Arguments
+    /// **sync_mode (SyncMode)**: Audio buffer fill level or wall-clock time
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
     }
 
-    /// Pops a value from the stack
+    /// Returns what the frame limiter paces the emulation to
     ///
     /// # Returns
-    /// **u16**: value popped from the stack
-    fn pop(&mut self) -> u16 {
-        let res = self.mmu.read_word(
-            self.registers.sp
-        );
-        self.registers.sp = self.registers.sp.wrapping_add(2);
-        res
+    /// **SyncMode**: Audio buffer fill level or wall-clock time
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
     }
 
-    /// Pushes the given value in the stack
+    /// Change the frequency of the clock of the CPU, to run the game faster
+    /// or slower
     ///
     /// # Arguments
-    /// **value (u16)**: value to push in the stack
-    fn push(&mut self, value: u16) {
-        self.registers.sp = self.registers.sp.wrapping_sub(2);
-        self.mmu.write_word(
-            self.registers.sp,
-            value
+    /// **hz (NonZeroU32)**: New frequency of the clock at normal speed
+    pub fn set_clock_hz(&mut self, hz: NonZeroU32) {
+        self.clock_hz = hz.get();
+    }
+
+    /// Returns the duration of one CPU cycle
+    ///
+    /// One cycle lasts half as long in double speed mode.
+    ///
+    /// # Returns
+    /// **f64**: Duration of one cycle in nanoseconds
+    fn nanos_per_cycle(&self) -> f64 {
+        let nanos = 1_000_000_000.0 / self.clock_hz as f64;
+        if self.is_double_speed() {
+            nanos / 2.0
+        } else {
+            nanos
+        }
+    }
+
+    /// Returns the number of cycles executed since the game started
+    ///
+    /// # Returns
+    /// **u64**: Number of cycles executed
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Returns the time elapsed on the GameBoy since the game started
+    ///
+    /// The cycles executed in double speed mode last half as long.
+    ///
+    /// # Returns
+    /// **f64**: Emulated time in seconds
+    pub fn emulated_seconds(&self) -> f64 {
+        let normal_speed_cycles = self.total_cycles - self.double_speed_cycles;
+        (normal_speed_cycles as f64 + self.double_speed_cycles as f64 / 2.0) /
+            CLOCK_HZ as f64
+    }
+
+    /// Returns the duration of one frame, for hosts pacing run_frame
+    ///
+    /// # Returns
+    /// **f64**: Duration of one frame in nanoseconds
+    pub fn nanos_per_frame(&self) -> f64 {
+        self.nanos_per_cycle() * CYCLES_PER_FRAME as f64
+    }
+
+    /// Stops writing the completed frames
+    ///
+    /// # Returns
+    /// **usize**: Number of frames written since the recording started
+    #[cfg(feature = "recording")]
+    pub fn stop_recording(&mut self) -> usize {
+        self.recorder
+            .take()
+            .map_or(0, |recorder| recorder.n_frames())
+    }
+
+    /// Returns a copy of the registers of the CPU
+    ///
+    /// # Returns
+    /// **RegistersSnapshot**: Current value of every register
+    pub fn registers(&self) -> RegistersSnapshot {
+        RegistersSnapshot::from(&self.registers)
+    }
+
+    /// Remembers an executed instruction, forgetting the oldest one if
+    /// enough are kept
+    ///
+    /// # Arguments
+    /// **pc (u16)**: Address of the instruction
+    /// **opcode (u8)**: Opcode of the instruction
+    fn record_instruction(&mut self, pc: u16, opcode: u8) {
+        if self.recent_instructions.len() == RECENT_INSTRUCTIONS_CAPACITY {
+            self.recent_instructions.pop_front();
+        }
+        self.recent_instructions.push_back((pc, opcode));
+        if let Some(cpu) = self.gameboy_mut() {
+            cpu.mmu.set_instruction_address(pc);
+        }
+    }
+
+    /// Returns the last executed instructions
+    ///
+    /// Only the last 16 instructions are kept.
+    ///
+    /// # Returns
+    /// **`&VecDeque<(u16, u8)>`**: Address and opcode of each instruction,
+    /// from the oldest to the most recent
+    pub fn recent_instructions(&self) -> &VecDeque<(u16, u8)> {
+        &self.recent_instructions
+    }
+
+    /// Reads the words at the top of the stack, without popping them
+    ///
+    /// The addresses wrap around after 0xFFFF.
+    ///
+    /// # Arguments
+    /// **depth (usize)**: Number of words to read from SP
+    ///
+    /// # Returns
+    /// **`Vec<u16>`**: Words from the top of the stack (the last pushed)
+    /// downwards
+    pub fn stack_dump(&self, depth: usize) -> Vec<u16> {
+        (0..depth)
+            .map(|i| {
+                let offset = (i as u16).wrapping_mul(2);
+                let address = self.registers.sp.wrapping_add(offset);
+                u16::from_le_bytes([
+                    self.mmu.read_byte(address),
+                    self.mmu.read_byte(address.wrapping_add(1)),
+                ])
+            })
+            .collect()
+    }
+
+    /// Describes the state of the CPU, to understand why it stopped
+    ///
+    /// # Returns
+    /// **String**: Registers, flags, interruption state, last executed
+    /// instructions and top of the stack
+    pub fn dump_state(&self) -> String {
+        let registers = &self.registers;
+        let mut dump = String::new();
+        let _ = writeln!(
+            dump,
+            "PC: {:#06x} SP: {:#06x}",
+            registers.pc,
+            registers.sp
+        );
+        let _ = writeln!(
+            dump,
+            "A: {:#04x} F: {:#04x} B: {:#04x} C: {:#04x} D: {:#04x} E: {:#04x} \
+            H: {:#04x} L: {:#04x}",
+            registers.a,
+            registers.f,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l
+        );
+        let _ = writeln!(
+            dump,
+            "Z: {} N: {} H: {} C: {}",
+            registers.get_zero() as u8,
+            registers.get_sub() as u8,
+            registers.get_half() as u8,
+            registers.get_carry() as u8
+        );
+        let _ = writeln!(
+            dump,
+            "IME: {} HALT: {} STOP: {}",
+            self.ime,
+            self.is_halted,
+            self.is_stopped
         );
+        let _ = writeln!(dump, "Recent instructions:");
+        for (pc, opcode) in self.recent_instructions.iter() {
+            let _ = writeln!(dump, "  {:#06x}: {:#04x}", pc, opcode);
+        }
+        let _ = write!(dump, "Stack:");
+        for offset in 0..STACK_DUMP_SIZE {
+            let address = registers.sp.wrapping_add(offset);
+            let _ = write!(dump, " {:#04x}", self.mmu.read_byte(address));
+        }
+        dump.push('\n');
+        dump
     }
 
-    /// Jumps to the given value after pushing the value of the program counter
-    /// in the stack
+    register_accessors!(a, u8);
+    register_accessors!(b, u8);
+    register_accessors!(c, u8);
+    register_accessors!(d, u8);
+    register_accessors!(e, u8);
+    // The low nibble of F is always 0
+    register_accessors!(f, u8, 0xF0);
+    register_accessors!(h, u8);
+    register_accessors!(l, u8);
+    register_accessors!(pc, u16);
+    register_accessors!(sp, u16);
+    register_accessors!(a f, 0xFFF0);
+    register_accessors!(b c);
+    register_accessors!(d e);
+    register_accessors!(h l);
+
+    /// Starts the execution at another address than the entry point of the
+    /// cartridge (0x0100)
+    ///
+    /// The CPU leaves the HALT and STOP modes, so that the next step executes
+    /// the instruction at this address.
     ///
     /// # Arguments
-    /// **value (u16)**: new value of the program counter
-    fn rst(&mut self, value: u16) {
-        self.push(self.registers.pc);
-        self.registers.pc = value;
+    /// **address (u16)**: Address of the next instruction
+    pub fn set_pc(&mut self, address: u16) {
+        self.set_reg_pc(address);
+        self.is_halted = false;
+        self.is_halt_bug = false;
+        self.is_stopped = false;
     }
 
-    /// Jumps by the value of the next immediate in the program read as a real
-    fn jr(&mut self) {
-        // Les conversions permettent d'assurer que fetchbyte est considéré
-        // comme signé, mais pas pc, que l'opérations puisse avoir lieu, et
-        // que le résutat ait le bon format
-        self.registers.pc = (
-            (self.registers.pc as u32 as i32) +
-            (self.fetchbyte() as i8 as i32)
-        ) as u16;
+    /// Returns the flags of the CPU
+    ///
+    /// # Returns
+    /// **Flags**: Z, N, H and C, as found in the F register
+    pub fn flags(&self) -> Flags {
+        Flags::from(self.registers.f)
     }
 
-    /// Make the CPU work indefinitively
-    pub fn run(&mut self) {
-        while !self.should_stop {
-            let time = SystemTime::now();
-            let time_used = self.execute_step();
-            // One cycle lasts 2385ns
-            sleep(
-                Duration::from_nanos((2385 * time_used) as u64).saturating_sub(
-                    time.elapsed().unwrap()
-                )
-            );
+    /// Overwrites the flags of the CPU
+    ///
+    /// The lower nibble of the F register is always cleared.
+    ///
+    /// # Arguments
+    /// **flags (Flags)**: New value of Z, N, H and C
+    #[cfg(any(test, feature = "test-vectors"))]
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.registers.f = flags.into();
+    }
+
+    /// Overwrites every register of the CPU
+    ///
+    /// Used to set up the initial state of a single-step test vector.
+    ///
+    /// # Arguments
+    /// **snapshot (RegistersSnapshot)**: New value of every register
+    #[cfg(any(test, feature = "test-vectors"))]
+    pub fn set_registers(&mut self, snapshot: RegistersSnapshot) {
+        self.registers = Registers::from(snapshot);
+    }
+
+    /// Writes bytes in the memory of the GameBoy
+    ///
+    /// Used to set up the initial state of a single-step test vector.
+    ///
+    /// # Arguments
+    /// **bytes (&[(u16, u8)])**: Address and value of each byte to write
+    #[cfg(any(test, feature = "test-vectors"))]
+    pub fn set_memory_bytes(&mut self, bytes: &[(u16, u8)]) {
+        for (address, value) in bytes {
+            self.mmu.write_byte(*address, *value);
         }
-        self.mmu.close();
+    }
+
+    /// Choose whether every executed instruction should be printed
+    ///
+    /// Printing the instructions is by far the slowest part of a step, it is
+    /// disabled by default to run at full speed.
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the instructions be printed
+    pub fn set_tracing(&mut self, value: bool) {
+        self.is_tracing = value;
+    }
+
+    /// Returns true iff the CPU waits for an interruption after HALT
+    ///
+    /// # Returns
+    /// **bool**: Is the CPU halted
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    /// Returns true iff the CPU waits for a button press after STOP
+    ///
+    /// # Returns
+    /// **bool**: Is the CPU stopped
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped
     }
 
     /// Reads an instruction and execute it from the normal table
@@ -153,28 +1532,30 @@ impl CPU {
     /// # Returns
     /// **u32**: Number of cycles used for the step
     fn receive_op(&mut self) -> u32 {
-        println!(
-            "Execution of the operation at address {:#04x}/0x4000",
+        trace!(
+            self,
+            "Execution of the operation at address {:#04x}",
             self.registers.pc,
         );
-        assert!(self.registers.pc < 0x4000);
+        let pc = self.registers.pc;
         let op = self.fetchbyte();
+        self.record_instruction(pc, op);
         match op {
             // NOP
             0x00 => {
-                println!("NOP");
+                trace!(self, "NOP");
                 4
             },
             // LD BC, d16
             0x01 => {
-                println!("LD BC, d16");
+                trace!(self, "LD BC, d16");
                 let word = self.fetchword();
                 self.registers.set_bc(word);
                 12
             },
             // LD (BC), A
             0x02 => {
-                println!("LD (BC), A");
+                trace!(self, "LD (BC), A");
                 self.mmu.write_byte(
                     self.registers.get_bc(),
                     self.registers.a
@@ -183,7 +1564,7 @@ impl CPU {
             },
             // INC BC
             0x03 => {
-                println!("INC BC");
+                trace!(self, "INC BC");
                 self.registers.set_bc(
                     self.registers.get_bc().wrapping_add(1)
                 );
@@ -191,34 +1572,34 @@ impl CPU {
             },
             // INC B
             0x04 => {
-                println!("INC B");
+                trace!(self, "INC B");
                 self.registers.b = self.inc(self.registers.b);
                 4
             },
             // DEC B
             0x05 => {
-                println!("DEC B");
+                trace!(self, "DEC B");
                 self.registers.b = self.dec(self.registers.b);
                 4
             },
             // LD B, d8
             0x06 => {
-                println!("LD B, d8");
+                trace!(self, "LD B, d8");
                 self.registers.b = self.fetchbyte();
                 8
             },
             // RLCA
             0x07 => {
-                println!("RLCA");
+                trace!(self, "RLCA");
                 self.registers.a = self.rlc(self.registers.a);
                 self.registers.set_zero(false);
                 4
             },
             // LD (a16), SP
             0x08 => {
-                println!("LD (a16), SP");
+                trace!(self, "LD (a16), SP");
                 let word = self.fetchword();
-                self.mmu.write_word(
+                self.write_word(
                     word,
                     self.registers.sp
                 );
@@ -226,63 +1607,63 @@ impl CPU {
             },
             // ADD HL, BC
             0x09 => {
-                println!("ADD HL, BC");
+                trace!(self, "ADD HL, BC");
                 self.addhl(self.registers.get_bc());
                 8
             },
             // LD A, (BC)
             0x0A => {
-                println!("LD A, (BC)");
+                trace!(self, "LD A, (BC)");
                 self.registers.a = self.mmu.read_byte(self.registers.get_bc());
                 8
             },
             // DEC BC
             0x0B => {
-                println!("DEC BC");
+                trace!(self, "DEC BC");
                 self.registers.set_bc(self.registers.get_bc().wrapping_sub(1));
                 8
             },
             // INC C
             0x0C => {
-                println!("INC C");
+                trace!(self, "INC C");
                 self.registers.c = self.inc(self.registers.c);
                 4
             },
             // DEC C
             0x0D => {
-                println!("DEC C");
+                trace!(self, "DEC C");
                 self.registers.c = self.dec(self.registers.c);
                 4
             },
             // LD C, d8
             0x0E => {
-                println!("LD C, d8");
+                trace!(self, "LD C, d8");
                 self.registers.c = self.fetchbyte();
                 8
             },
             // RRCA
             0x0F => {
-                println!("RRCA");
+                trace!(self, "RRCA");
                 self.registers.a = self.rrc(self.registers.a);
                 self.registers.set_zero(false);
                 4
             },
             // STOP A
             0x10 => {
-                println!("STOP A");
+                trace!(self, "STOP A");
                 self.send_stop();
                 4
             },
             // LD DE, D16
             0x11 => {
-                println!("LD DE, D16");
+                trace!(self, "LD DE, D16");
                 let word = self.fetchword();
                 self.registers.set_de(word);
                 12
             },
             // LD (DE), A
             0x12 => {
-                println!("LD (DE), A");
+                trace!(self, "LD (DE), A");
                 self.mmu.write_byte(
                     self.registers.get_de(),
                     self.registers.a
@@ -291,7 +1672,7 @@ impl CPU {
             },
             // INC DE
             0x13 => {
-                println!("INC DE");
+                trace!(self, "INC DE");
                 self.registers.set_de(
                     self.registers.get_de().wrapping_add(1)
                 );
@@ -299,38 +1680,38 @@ impl CPU {
             },
             // INC D
             0x14 => {
-                println!("INC D");
+                trace!(self, "INC D");
                 self.registers.d = self.inc(self.registers.d);
                 4
             },
             // DEC D
             0x15 => {
-                println!("DEC D");
+                trace!(self, "DEC D");
                 self.registers.d = self.dec(self.registers.d);
                 4
             },
             // LD D, d8
             0x16 => {
-                println!("LD D, d8");
+                trace!(self, "LD D, d8");
                 self.registers.d = self.fetchbyte();
                 8
             },
             // RLA
             0x17 => {
-                println!("RLA");
+                trace!(self, "RLA");
                 self.registers.a = self.rl(self.registers.a);
                 self.registers.set_zero(false);
                 4
             },
             // JR r8
             0x18 => {
-                println!("JR r8");
+                trace!(self, "JR r8");
                 self.jr();
                 12
             },
             // ADD HL, DE
             0x19 => {
-                println!("ADD HL, DE");
+                trace!(self, "ADD HL, DE");
                 self.addhl(
                     self.registers.get_de()
                 );
@@ -338,7 +1719,7 @@ impl CPU {
                },
             // LD A, (DE)
             0x1A => {
-                println!("LD A, (DE)");
+                trace!(self, "LD A, (DE)");
                 self.registers.a = self.mmu.read_byte(
                     self.registers.get_de()
                 );
@@ -346,7 +1727,7 @@ impl CPU {
             },
             // DEC DE
             0x1B => {
-                println!("DEC DE");
+                trace!(self, "DEC DE");
                 self.registers.set_de(
                     self.registers.get_de().wrapping_sub(1)
                 );
@@ -354,32 +1735,32 @@ impl CPU {
             },
             // INC E
             0x1C => {
-                println!("INC E");
+                trace!(self, "INC E");
                 self.registers.e = self.inc(self.registers.e);
                 4
             },
             // DEC E
             0x1D => {
-                println!("DEC E");
+                trace!(self, "DEC E");
                 self.registers.e = self.dec(self.registers.e);
                 4
             },
             // LD D, d8
             0x1E => {
-                println!("LD D, d8");
+                trace!(self, "LD D, d8");
                 self.registers.d = self.fetchbyte();
                 8
             },
             // RRA
             0x1F => {
-                println!("RRA");
+                trace!(self, "RRA");
                 self.registers.a = self.rr(self.registers.a);
                 self.registers.set_zero(false);
                 4
             },
             // JR NZ, r8
             0x20 => {
-                println!("JR NZ, r8");
+                trace!(self, "JR NZ, r8");
                 if !self.registers.get_zero() {
                     self.jr();
                     12
@@ -390,14 +1771,14 @@ impl CPU {
             },
             // LD HL, d16
             0x21 => {
-                println!("LD HL, d16");
+                trace!(self, "LD HL, d16");
                 let word = self.fetchword();
                 self.registers.set_hl(word);
                 12
             },
             // LD (HL+), A
             0x22 => {
-                println!("LD (HL+), A");
+                trace!(self, "LD (HL+), A");
                 self.mmu.write_byte(
                     self.registers.get_hli(),
                     self.registers.a
@@ -406,7 +1787,7 @@ impl CPU {
             },
             // INC HL
             0x23 => {
-                println!("INC HL");
+                trace!(self, "INC HL");
                 self.registers.set_hl(
                     self.registers.get_hl().wrapping_add(1)
                 );
@@ -414,31 +1795,31 @@ impl CPU {
             },
             // INC H
             0x24 => {
-                println!("INC H");
+                trace!(self, "INC H");
                 self.registers.h = self.inc(self.registers.h);
                 4
             },
             // DEC H
             0x25 => {
-                println!("DEC H");
+                trace!(self, "DEC H");
                 self.registers.h = self.dec(self.registers.h);
                 4
             },
             // LD H, d8
             0x26 => {
-                println!("LD H, d8");
+                trace!(self, "LD H, d8");
                 self.registers.h = self.fetchbyte();
                 8
             },
             // DAA
             0x27 => {
-                println!("DAA");
+                trace!(self, "DAA");
                 self.daa();
                 4
             },
             // JR Z, r8
             0x28 => {
-                println!("JR Z, r8");
+                trace!(self, "JR Z, r8");
                 if self.registers.get_zero() {
                     self.jr();
                     12
@@ -449,13 +1830,13 @@ impl CPU {
             },
             // ADD HL, HL
             0x29 => {
-                println!("ADD HL, HL");
+                trace!(self, "ADD HL, HL");
                 self.addhl(self.registers.get_hl());
                 8
             },
             // LD A, (HL+)
             0x2A => {
-                println!("LD A, (HL+)");
+                trace!(self, "LD A, (HL+)");
                 self.mmu.write_byte(
                     self.registers.get_hli(),
                     self.registers.a
@@ -464,7 +1845,7 @@ impl CPU {
             },
             // DEC HL
             0x2B => {
-                println!("DEC HL");
+                trace!(self, "DEC HL");
                 self.registers.set_hl(
                     self.registers.get_hl().wrapping_sub(1)
                 );
@@ -472,25 +1853,25 @@ impl CPU {
             },
             // INC L
             0x2C => {
-                println!("INC L");
+                trace!(self, "INC L");
                 self.registers.l = self.inc(self.registers.l);
                 4
             },
             // DEC L
             0x2D => {
-                println!("DEC L");
+                trace!(self, "DEC L");
                 self.registers.l = self.dec(self.registers.l);
                 4
             },
             // LD L, d8
             0x2E => {
-                println!("LD L, d8");
+                trace!(self, "LD L, d8");
                 self.registers.l = self.fetchbyte();
                 8
             },
             // CPL
             0x2F => {
-                println!("CPL");
+                trace!(self, "CPL");
                 // self.registers.a = self.registers.a;
                 self.registers.set_half(true);
                 self.registers.set_sub(true);
@@ -498,7 +1879,7 @@ impl CPU {
             },
             // JR NC, r8
             0x30 => {
-                println!("JR NC, r8");
+                trace!(self, "JR NC, r8");
                 if !self.registers.get_carry() {
                     self.jr();
                     12
@@ -509,13 +1890,13 @@ impl CPU {
             },
             // LD SP, d16
             0x31 => {
-                println!("LD SP, d16");
+                trace!(self, "LD SP, d16");
                 self.registers.sp = self.fetchword();
                 12
             },
             // LD (HL-), A
             0x32 => {
-                println!("LD (HL-), A");
+                trace!(self, "LD (HL-), A");
                 self.mmu.write_byte(
                     self.registers.get_hld(),
                     self.registers.a
@@ -524,13 +1905,13 @@ impl CPU {
             },
             // INC SP
             0x33 => {
-                println!("INC SP");
+                trace!(self, "INC SP");
                 self.registers.sp = self.registers.sp.wrapping_add(1);
                 8
             },
             // INC (HL)
             0x34 => {
-                println!("INC (HL)");
+                trace!(self, "INC (HL)");
                 let value = self.inc(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -544,7 +1925,7 @@ impl CPU {
             },
             // DEC (HL)
             0x35 => {
-                println!("DEC (HL)");
+                trace!(self, "DEC (HL)");
                 let value = self.dec(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -558,7 +1939,7 @@ impl CPU {
             },
             // LD (HL), d8
             0x36 => {
-                println!("LD (HL), d8");
+                trace!(self, "LD (HL), d8");
                 let word = self.fetchbyte();
                 self.mmu.write_byte(
                     self.registers.get_hl(),
@@ -568,15 +1949,13 @@ impl CPU {
             },
             // SCF
             0x37 => {
-                println!("SCF");
-                self.registers.set_carry(true);
-                self.registers.set_half(false);
-                self.registers.set_sub(true);
+                trace!(self, "SCF");
+                self.scf();
                 4
             },
             // JR C, r8
             0x38 => {
-                println!("JR C, r8");
+                trace!(self, "JR C, r8");
                 if self.registers.get_carry() {
                     self.jr();
                     12
@@ -587,7 +1966,7 @@ impl CPU {
             },
             // ADD HL, SP
             0x39 => {
-                println!("ADD HL, SP");
+                trace!(self, "ADD HL, SP");
                 self.addhl(
                     self.registers.sp
                 );
@@ -595,7 +1974,7 @@ impl CPU {
             },
             // LD A, (HL-)
             0x3A => {
-                println!("LD A, (HL-)");
+                trace!(self, "LD A, (HL-)");
                 self.registers.a = self.mmu.read_byte(
                     self.registers.get_hld()
                 );
@@ -603,13 +1982,13 @@ impl CPU {
             },
             // DEC SP
             0x3B => {
-                println!("DEC SP");
+                trace!(self, "DEC SP");
                 self.registers.sp = self.registers.sp.wrapping_sub(1);
                 8
             },
             // INC A
             0x3C => {
-                println!("INC A");
+                trace!(self, "INC A");
                 self.registers.a = self.inc(
                     self.registers.a
                 );
@@ -617,7 +1996,7 @@ impl CPU {
             },
             // DEC A
             0x3D => {
-                println!("DEC A");
+                trace!(self, "DEC A");
                 self.registers.a = self.dec(
                     self.registers.a
                 );
@@ -625,63 +2004,55 @@ impl CPU {
             },
             // LD A, d8
             0x3E => {
-                println!("LD A, d8");
+                trace!(self, "LD A, d8");
                 self.registers.a = self.fetchbyte();
                 8
             },
             // CCF
             0x3F => {
-                println!("CCF");
-                self.registers.set_carry(
-                    !self.registers.get_carry()
-                );
-                self.registers.set_half(
-                    false
-                );
-                self.registers.set_sub(
-                    false
-                );
+                trace!(self, "CCF");
+                self.ccf();
                 4
             },
             // LD B, B
             0x40 => {
-                println!("LD B, B");
+                trace!(self, "LD B, B");
                 // self.registers.b = self.registers.b;
                 4
             },
             // LD B, C
             0x41 => {
-                println!("LD B, C");
+                trace!(self, "LD B, C");
                 self.registers.b = self.registers.c;
                 4
             },
             // LD B, D
             0x42 => {
-                println!("LD B, D");
+                trace!(self, "LD B, D");
                 self.registers.b = self.registers.d;
                 4
             },
             // LD B, E
             0x43 => {
-                println!("LD B, E");
+                trace!(self, "LD B, E");
                 self.registers.b = self.registers.e;
                 4
             },
             // LD B, H
             0x44 => {
-                println!("LD B, H");
+                trace!(self, "LD B, H");
                 self.registers.b = self.registers.h;
                 4
             },
             // LD B, L
             0x45 => {
-                println!("LD B, L");
+                trace!(self, "LD B, L");
                 self.registers.b = self.registers.l;
                 4
             },
             // LD B, (HL)
             0x46 => {
-                println!("LD B, (HL)");
+                trace!(self, "LD B, (HL)");
                 self.registers.b = self.mmu.read_byte(
                     self.registers.get_hl()
                 );
@@ -689,49 +2060,49 @@ impl CPU {
             },
             // LD B, A
             0x47 => {
-                println!("LD B, A");
+                trace!(self, "LD B, A");
                 self.registers.b = self.registers.a;
                 4
             },
             // LD C, B
             0x48 => {
-                println!("LD C, B");
+                trace!(self, "LD C, B");
                 self.registers.c = self.registers.b;
                 4
             },
             // LC C, C
             0x49 => {
-                println!("LC C, C");
+                trace!(self, "LC C, C");
                 // self.registers.c = self.registers.c;
                 4
             },
             // LC C, D
             0x4A => {
-                println!("LC C, D");
+                trace!(self, "LC C, D");
                 self.registers.c = self.registers.d;
                 4
             },
             // LD C, E
             0x4B => {
-                println!("LD C, E");
+                trace!(self, "LD C, E");
                 self.registers.c = self.registers.e;
                 4
             },
             // LD C, H
             0x4C => {
-                println!("LD C, H");
+                trace!(self, "LD C, H");
                 self.registers.c = self.registers.h;
                 4
             },
             // LD C, L
             0x4D => {
-                println!("LD C, L");
+                trace!(self, "LD C, L");
                 self.registers.c = self.registers.l;
                 4
             },
             // LD C, (HL)
             0x4E => {
-                println!("LD C, (HL)");
+                trace!(self, "LD C, (HL)");
                 self.registers.c = self.mmu.read_byte(
                     self.registers.get_hl()
                 );
@@ -739,49 +2110,49 @@ impl CPU {
             },
             // LD C, A
             0x4F => {
-                println!("LD C, A");
+                trace!(self, "LD C, A");
                 self.registers.c = self.registers.a;
                 4
             },
             // LD D, B
             0x50 => {
-                println!("LD D, B");
+                trace!(self, "LD D, B");
                 self.registers.d = self.registers.b;
                 4
             },
             // LD D, C
             0x51 => {
-                println!("LD D, C");
+                trace!(self, "LD D, C");
                 self.registers.d = self.registers.c;
                 4
             },
             // LD D, D
             0x52 => {
-                println!("LD D, D");
+                trace!(self, "LD D, D");
                 // self.registers.d = self.registers.d;
                 4
             },
             // LD D, E
             0x53 => {
-                println!("LD D, E");
+                trace!(self, "LD D, E");
                 self.registers.d = self.registers.e;
                 4
             },
             // LD D, H
             0x54 => {
-                println!("LD D, H");
+                trace!(self, "LD D, H");
                 self.registers.d = self.registers.h;
                 4
             },
             // LD D, L
             0x55 => {
-                println!("LD D, L");
+                trace!(self, "LD D, L");
                 self.registers.d = self.registers.l;
                 4
             },
             // LD D, (HL)
             0x56 => {
-                println!("LD D, (HL)");
+                trace!(self, "LD D, (HL)");
                 self.registers.d = self.mmu.read_byte(
                     self.registers.get_hl()
                 );
@@ -789,50 +2160,50 @@ impl CPU {
             },
             // LD D, A
             0x57 => {
-                println!("LD D, A");
+                trace!(self, "LD D, A");
                 self.registers.d = self.registers.a;
                 4
             },
             // LD E, B
             0x58 => {
-                println!("LD E, B");
+                trace!(self, "LD E, B");
                 self.registers.e = self.registers.b;
                 4
             },
             // LC E, C
             0x59 => {
-                println!("LC E, C");
+                trace!(self, "LC E, C");
                 self.registers.e = self.registers.c;
-                println!("STOP A");
+                trace!(self, "STOP A");
                 4
             },
             // LC E, D
             0x5A => {
-                println!("LC E, D");
+                trace!(self, "LC E, D");
                 self.registers.e = self.registers.d;
                 4
             },
             // LD E, E
             0x5B => {
-                println!("LD E, E");
+                trace!(self, "LD E, E");
                 // self.registers.e = self.registers.e;
                 4
             },
             // LD E, H
             0x5C => {
-                println!("LD E, H");
+                trace!(self, "LD E, H");
                 self.registers.e = self.registers.h;
                 4
             },
             // LD E, L
             0x5D => {
-                println!("LD E, L");
+                trace!(self, "LD E, L");
                 self.registers.e = self.registers.l;
                 4
             },
             // LD E, (HL)
             0x5E => {
-                println!("LD E, (HL)");
+                trace!(self, "LD E, (HL)");
                 self.registers.e = self.mmu.read_byte(
                     self.registers.get_hl()
                 );
@@ -840,49 +2211,49 @@ impl CPU {
             },
             // LD E, A
             0x5F => {
-                println!("LD E, A");
+                trace!(self, "LD E, A");
                 self.registers.e = self.registers.a;
                 4
             },
             // LD H, B
             0x60 => {
-                println!("LD H, B");
+                trace!(self, "LD H, B");
                 self.registers.h = self.registers.b;
                 4
             },
             // LD H, C
             0x61 => {
-                println!("LD H, C");
+                trace!(self, "LD H, C");
                 self.registers.h = self.registers.c;
                 4
             },
             // LD H, D
             0x62 => {
-                println!("LD H, D");
+                trace!(self, "LD H, D");
                 self.registers.h = self.registers.d;
                 4
             },
             // LD H, E
             0x63 => {
-                println!("LD H, E");
+                trace!(self, "LD H, E");
                 self.registers.h = self.registers.e;
                 4
             },
             // LD H, H
             0x64 => {
-                println!("LD H, H");
+                trace!(self, "LD H, H");
                 // self.registers.h = self.registers.h;
                 4
             },
             // LD H, L
             0x65 => {
-                println!("LD H, L");
+                trace!(self, "LD H, L");
                 self.registers.h = self.registers.l;
                 4
             },
             // LD H, (HL)
             0x66 => {
-                println!("LD H, (HL)");
+                trace!(self, "LD H, (HL)");
                 self.registers.h = self.mmu.read_byte(
                     self.registers.get_hl()
                 );
@@ -890,49 +2261,49 @@ impl CPU {
             },
             // LD H, A
             0x67 => {
-                println!("LD H, A");
+                trace!(self, "LD H, A");
                 self.registers.h = self.registers.a;
                 4
             },
             // LD L, B
             0x68 => {
-                println!("LD L, B");
+                trace!(self, "LD L, B");
                 self.registers.l = self.registers.b;
                 4
             },
             // LC L, C
             0x69 => {
-                println!("LC L, C");
+                trace!(self, "LC L, C");
                 self.registers.l = self.registers.c;
                 4
             },
             // LC L, D
             0x6A => {
-                println!("LC L, D");
+                trace!(self, "LC L, D");
                 self.registers.l = self.registers.d;
                 4
             },
             // LD L, E
             0x6B => {
-                println!("LD L, E");
+                trace!(self, "LD L, E");
                 self.registers.l = self.registers.e;
                 4
             },
             // LD L, H
             0x6C => {
-                println!("LD L, H");
+                trace!(self, "LD L, H");
                 self.registers.l = self.registers.h;
                 4
             },
             // LD L, L
             0x6D => {
-                println!("LD L, L");
+                trace!(self, "LD L, L");
                 // self.registers.l = self.registers.l;
                 4
             },
             // LD L, (HL)
             0x6E => {
-                println!("LD L, (HL)");
+                trace!(self, "LD L, (HL)");
                 self.registers.l = self.mmu.read_byte(
                     self.registers.get_hl()
                 );
@@ -940,13 +2311,13 @@ impl CPU {
             },
             // LD L, A
             0x6F => {
-                println!("LD L, A");
+                trace!(self, "LD L, A");
                 self.registers.l = self.registers.a;
                 4
             },
             // LD (HL), B
             0x70 => {
-                println!("LD (HL), B");
+                trace!(self, "LD (HL), B");
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.b
@@ -955,7 +2326,7 @@ impl CPU {
             },
             // LD (HL), C
             0x71 => {
-                println!("LD (HL), C");
+                trace!(self, "LD (HL), C");
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.c
@@ -964,7 +2335,7 @@ impl CPU {
             },
             // LD (HL), D
             0x72 => {
-                println!("LD (HL), D");
+                trace!(self, "LD (HL), D");
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.d
@@ -973,7 +2344,7 @@ impl CPU {
             },
             // LD (HL), E
             0x73 => {
-                println!("LD (HL), E");
+                trace!(self, "LD (HL), E");
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.e
@@ -982,7 +2353,7 @@ impl CPU {
             },
             // LD (HL), H
             0x74 => {
-                println!("LD (HL), H");
+                trace!(self, "LD (HL), H");
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.h
@@ -991,7 +2362,7 @@ impl CPU {
             },
             // LD (HL), L
             0x75 => {
-                println!("LD (HL), L");
+                trace!(self, "LD (HL), L");
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.l
@@ -1000,13 +2371,13 @@ impl CPU {
             },
             // HALT
             0x76 => {
-                println!("HALT");
+                trace!(self, "HALT");
                 self.halt();
                 4
             },
             // LD (HL), A
             0x77 => {
-                println!("LD (HL), A");
+                trace!(self, "LD (HL), A");
                 self.mmu.write_byte(
                     self.registers.get_hl(),
                     self.registers.a
@@ -1015,43 +2386,43 @@ impl CPU {
             },
             // LD A, B
             0x78 => {
-                println!("LD A, B");
+                trace!(self, "LD A, B");
                 self.registers.a = self.registers.b;
                 4
             },
             // LC A, C
             0x79 => {
-                println!("LC A, C");
+                trace!(self, "LC A, C");
                 self.registers.a = self.registers.c;
                 4
             },
             // LC A, D
             0x7A => {
-                println!("LC A, D");
+                trace!(self, "LC A, D");
                 self.registers.a = self.registers.d;
                 4
             },
             // LD A, E
             0x7B => {
-                println!("LD A, E");
+                trace!(self, "LD A, E");
                 self.registers.a = self.registers.e;
                 4
             },
             // LD A, H
             0x7C => {
-                println!("LD A, H");
+                trace!(self, "LD A, H");
                 self.registers.a = self.registers.h;
                 4
             },
             // LD A, L
             0x7D => {
-                println!("LD A, L");
+                trace!(self, "LD A, L");
                 self.registers.a = self.registers.l;
                 4
             },
             // LD A, (HL)
             0x7E => {
-                println!("LD A, (HL)");
+                trace!(self, "LD A, (HL)");
                 self.registers.a = self.mmu.read_byte(
                     self.registers.get_hl()
                 );
@@ -1059,13 +2430,13 @@ impl CPU {
             },
             // LD A, A
             0x7F => {
-                println!("LD A, A");
+                trace!(self, "LD A, A");
                 // self.registers.a = self.registers.a;
                 4
             },
             // ADD A, B
             0x80 => {
-                println!("ADD A, B");
+                trace!(self, "ADD A, B");
                 self.add(
                     self.registers.b
                 );
@@ -1073,7 +2444,7 @@ impl CPU {
             },
             // ADD A, C
             0x81 => {
-                println!("ADD A, C");
+                trace!(self, "ADD A, C");
                 self.add(
                     self.registers.c
                 );
@@ -1081,7 +2452,7 @@ impl CPU {
             },
             // ADD A, D
             0x82 => {
-                println!("ADD A, D");
+                trace!(self, "ADD A, D");
                 self.add(
                     self.registers.d
                 );
@@ -1089,7 +2460,7 @@ impl CPU {
             },
             // ADD A, E
             0x83 => {
-                println!("ADD A, E");
+                trace!(self, "ADD A, E");
                 self.add(
                     self.registers.e
                 );
@@ -1097,7 +2468,7 @@ impl CPU {
             },
             // ADD A, H
             0x84 => {
-                println!("ADD A, H");
+                trace!(self, "ADD A, H");
                 self.add(
                     self.registers.h
                 );
@@ -1105,7 +2476,7 @@ impl CPU {
             },
             // ADD A, L
             0x85 => {
-                println!("ADD A, L");
+                trace!(self, "ADD A, L");
                 self.add(
                     self.registers.l
                 );
@@ -1113,7 +2484,7 @@ impl CPU {
             },
             // ADD A, (HL)
             0x86 => {
-                println!("ADD A, (HL)");
+                trace!(self, "ADD A, (HL)");
                 self.add(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1123,7 +2494,7 @@ impl CPU {
             },
             // ADD A, A
             0x87 => {
-                println!("ADD A, A");
+                trace!(self, "ADD A, A");
                 self.add(
                     self.registers.a
                 );
@@ -1131,7 +2502,7 @@ impl CPU {
             },
             // ADC A, B
             0x88 => {
-                println!("ADC A, B");
+                trace!(self, "ADC A, B");
                 self.adc(
                     self.registers.b
                 );
@@ -1139,7 +2510,7 @@ impl CPU {
             },
             // ADC A, C
             0x89 => {
-                println!("ADC A, C");
+                trace!(self, "ADC A, C");
                 self.adc(
                     self.registers.c
                 );
@@ -1147,7 +2518,7 @@ impl CPU {
             },
             // ADC A, D
             0x8A => {
-                println!("ADC A, D");
+                trace!(self, "ADC A, D");
                 self.adc(
                     self.registers.d
                 );
@@ -1155,7 +2526,7 @@ impl CPU {
             },
             // ADC A, E
             0x8B => {
-                println!("ADC A, E");
+                trace!(self, "ADC A, E");
                 self.adc(
                     self.registers.e
                 );
@@ -1163,7 +2534,7 @@ impl CPU {
             },
             // ADC A, H
             0x8C => {
-                println!("ADC A, H");
+                trace!(self, "ADC A, H");
                 self.adc(
                     self.registers.h
                 );
@@ -1171,7 +2542,7 @@ impl CPU {
             },
             // ADC A, L
             0x8D => {
-                println!("ADC A, L");
+                trace!(self, "ADC A, L");
                 self.adc(
                     self.registers.l
                 );
@@ -1179,7 +2550,7 @@ impl CPU {
             },
             // ADC A, (HL)
             0x8E => {
-                println!("ADC A, (HL)");
+                trace!(self, "ADC A, (HL)");
                 self.adc(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1189,7 +2560,7 @@ impl CPU {
             },
             // ADC A, A
             0x8F => {
-                println!("ADC A, A");
+                trace!(self, "ADC A, A");
                 self.adc(
                     self.registers.a
                 );
@@ -1197,7 +2568,7 @@ impl CPU {
             },
             // SUB A, B
             0x90 => {
-                println!("SUB A, B");
+                trace!(self, "SUB A, B");
                 self.sub(
                     self.registers.b
                 );
@@ -1205,7 +2576,7 @@ impl CPU {
             },
             // SUB A, C
             0x91 => {
-                println!("SUB A, C");
+                trace!(self, "SUB A, C");
                 self.sub(
                     self.registers.c
                 );
@@ -1213,7 +2584,7 @@ impl CPU {
             },
             // SUB A, D
             0x92 => {
-                println!("SUB A, D");
+                trace!(self, "SUB A, D");
                 self.sub(
                     self.registers.d
                 );
@@ -1221,7 +2592,7 @@ impl CPU {
             },
             // SUB A, E
             0x93 => {
-                println!("SUB A, E");
+                trace!(self, "SUB A, E");
                 self.sub(
                     self.registers.e
                 );
@@ -1229,7 +2600,7 @@ impl CPU {
             },
             // SUB A, H
             0x94 => {
-                println!("SUB A, H");
+                trace!(self, "SUB A, H");
                 self.sub(
                     self.registers.h
                 );
@@ -1237,7 +2608,7 @@ impl CPU {
             },
             // SUB A, L
             0x95 => {
-                println!("SUB A, L");
+                trace!(self, "SUB A, L");
                 self.sub(
                     self.registers.l
                 );
@@ -1245,7 +2616,7 @@ impl CPU {
             },
             // SUB A, (HL)
             0x96 => {
-                println!("SUB A, (HL)");
+                trace!(self, "SUB A, (HL)");
                 self.sub(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1255,7 +2626,7 @@ impl CPU {
             },
             // SUB A, A
             0x97 => {
-                println!("SUB A, A");
+                trace!(self, "SUB A, A");
                 self.sub(
                     self.registers.a
                 );
@@ -1263,7 +2634,7 @@ impl CPU {
             },
             // SBC A, B
             0x98 => {
-                println!("SBC A, B");
+                trace!(self, "SBC A, B");
                 self.sbc(
                     self.registers.b
                 );
@@ -1271,7 +2642,7 @@ impl CPU {
             },
             // SBC A, C
             0x99 => {
-                println!("SBC A, C");
+                trace!(self, "SBC A, C");
                 self.sbc(
                     self.registers.c
                 );
@@ -1279,7 +2650,7 @@ impl CPU {
             },
             // SBC A, D
             0x9A => {
-                println!("SBC A, D");
+                trace!(self, "SBC A, D");
                 self.sbc(
                     self.registers.d
                 );
@@ -1287,7 +2658,7 @@ impl CPU {
             },
             // SBC A, E
             0x9B => {
-                println!("SBC A, E");
+                trace!(self, "SBC A, E");
                 self.sbc(
                     self.registers.e
                 );
@@ -1295,7 +2666,7 @@ impl CPU {
             },
             // SBC A, H
             0x9C => {
-                println!("SBC A, H");
+                trace!(self, "SBC A, H");
                 self.sbc(
                     self.registers.h
                 );
@@ -1303,7 +2674,7 @@ impl CPU {
             },
             // SBC A, L
             0x9D => {
-                println!("SBC A, L");
+                trace!(self, "SBC A, L");
                 self.sbc(
                     self.registers.l
                 );
@@ -1311,7 +2682,7 @@ impl CPU {
             },
             // SBC A, (HL)
             0x9E => {
-                println!("SBC A, (HL)");
+                trace!(self, "SBC A, (HL)");
                 self.sbc(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1321,7 +2692,7 @@ impl CPU {
             },
             // SBC A, A
             0x9F => {
-                println!("SBC A, A");
+                trace!(self, "SBC A, A");
                 self.sbc(
                     self.registers.a
                 );
@@ -1329,7 +2700,7 @@ impl CPU {
             },
             // AND A, B
             0xA0 => {
-                println!("AND A, B");
+                trace!(self, "AND A, B");
                 self.and(
                     self.registers.b
                 );
@@ -1337,7 +2708,7 @@ impl CPU {
             },
             // AND A, C
             0xA1 => {
-                println!("AND A, C");
+                trace!(self, "AND A, C");
                 self.and(
                     self.registers.c
                 );
@@ -1345,7 +2716,7 @@ impl CPU {
             },
             // AND A, D
             0xA2 => {
-                println!("AND A, D");
+                trace!(self, "AND A, D");
                 self.and(
                     self.registers.d
                 );
@@ -1353,7 +2724,7 @@ impl CPU {
             },
             // AND A, E
             0xA3 => {
-                println!("AND A, E");
+                trace!(self, "AND A, E");
                 self.and(
                     self.registers.e
                 );
@@ -1361,7 +2732,7 @@ impl CPU {
             },
             // AND A, H
             0xA4 => {
-                println!("AND A, H");
+                trace!(self, "AND A, H");
                 self.and(
                     self.registers.h
                 );
@@ -1369,7 +2740,7 @@ impl CPU {
             },
             // AND A, L
             0xA5 => {
-                println!("AND A, L");
+                trace!(self, "AND A, L");
                 self.and(
                     self.registers.l
                 );
@@ -1377,7 +2748,7 @@ impl CPU {
             },
             // AND A, (HL)
             0xA6 => {
-                println!("AND A, (HL)");
+                trace!(self, "AND A, (HL)");
                 self.and(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1387,7 +2758,7 @@ impl CPU {
             },
             // AND A, A
             0xA7 => {
-                println!("AND A, A");
+                trace!(self, "AND A, A");
                 self.and(
                     self.registers.a
                 );
@@ -1395,7 +2766,7 @@ impl CPU {
             },
             // XOR A, B
             0xA8 => {
-                println!("XOR A, B");
+                trace!(self, "XOR A, B");
                 self.xor(
                     self.registers.b
                 );
@@ -1403,7 +2774,7 @@ impl CPU {
             },
             // XOR A, C
             0xA9 => {
-                println!("XOR A, C");
+                trace!(self, "XOR A, C");
                 self.xor(
                     self.registers.c
                 );
@@ -1411,7 +2782,7 @@ impl CPU {
             },
             // XOR A, D
             0xAA => {
-                println!("XOR A, D");
+                trace!(self, "XOR A, D");
                 self.xor(
                     self.registers.d
                 );
@@ -1419,7 +2790,7 @@ impl CPU {
             },
             // XOR A, E
             0xAB => {
-                println!("XOR A, E");
+                trace!(self, "XOR A, E");
                 self.xor(
                     self.registers.e
                 );
@@ -1427,7 +2798,7 @@ impl CPU {
             },
             // XOR A, H
             0xAC => {
-                println!("XOR A, H");
+                trace!(self, "XOR A, H");
                 self.xor(
                     self.registers.h
                 );
@@ -1435,7 +2806,7 @@ impl CPU {
             },
             // XOR A, L
             0xAD => {
-                println!("XOR A, L");
+                trace!(self, "XOR A, L");
                 self.xor(
                     self.registers.l
                 );
@@ -1443,7 +2814,7 @@ impl CPU {
             },
             // XOR A, (HL)
             0xAE => {
-                println!("XOR A, (HL)");
+                trace!(self, "XOR A, (HL)");
                 self.xor(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1453,7 +2824,7 @@ impl CPU {
             },
             // XOR A, A
             0xAF => {
-                println!("XOR A, A");
+                trace!(self, "XOR A, A");
                 self.xor(
                     self.registers.a
                 );
@@ -1461,7 +2832,7 @@ impl CPU {
             },
             // OR A, B
             0xB0 => {
-                println!("OR A, B");
+                trace!(self, "OR A, B");
                 self.or(
                     self.registers.b
                 );
@@ -1469,7 +2840,7 @@ impl CPU {
             },
             // OR A, C
             0xB1 => {
-                println!("OR A, C");
+                trace!(self, "OR A, C");
                 self.or(
                     self.registers.c
                 );
@@ -1477,7 +2848,7 @@ impl CPU {
             },
             // OR A, D
             0xB2 => {
-                println!("OR A, D");
+                trace!(self, "OR A, D");
                 self.or(
                     self.registers.d
                 );
@@ -1485,7 +2856,7 @@ impl CPU {
             },
             // OR A, E
             0xB3 => {
-                println!("OR A, E");
+                trace!(self, "OR A, E");
                 self.or(
                     self.registers.e
                 );
@@ -1493,7 +2864,7 @@ impl CPU {
             },
             // OR A, H
             0xB4 => {
-                println!("OR A, H");
+                trace!(self, "OR A, H");
                 self.or(
                     self.registers.h
                 );
@@ -1501,7 +2872,7 @@ impl CPU {
             },
             // OR A, L
             0xB5 => {
-                println!("OR A, L");
+                trace!(self, "OR A, L");
                 self.or(
                     self.registers.l
                 );
@@ -1509,7 +2880,7 @@ impl CPU {
             },
             // OR A, (HL)
             0xB6 => {
-                println!("OR A, (HL)");
+                trace!(self, "OR A, (HL)");
                 self.or(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1519,7 +2890,7 @@ impl CPU {
             },
             // OR A, A
             0xB7 => {
-                println!("OR A, A");
+                trace!(self, "OR A, A");
                 self.or(
                     self.registers.a
                 );
@@ -1527,7 +2898,7 @@ impl CPU {
             },
             // CP A, B
             0xB8 => {
-                println!("CP A, B");
+                trace!(self, "CP A, B");
                 self.cp(
                     self.registers.b
                 );
@@ -1535,7 +2906,7 @@ impl CPU {
             },
             // CP A, C
             0xB9 => {
-                println!("CP A, C");
+                trace!(self, "CP A, C");
                 self.cp(
                     self.registers.c
                 );
@@ -1543,7 +2914,7 @@ impl CPU {
             },
             // CP A, D
             0xBA => {
-                println!("CP A, D");
+                trace!(self, "CP A, D");
                 self.cp(
                     self.registers.d
                 );
@@ -1551,7 +2922,7 @@ impl CPU {
             },
             // CP A, E
             0xBB => {
-                println!("CP A, E");
+                trace!(self, "CP A, E");
                 self.cp(
                     self.registers.e
                 );
@@ -1559,7 +2930,7 @@ impl CPU {
             },
             // CP A, H
             0xBC => {
-                println!("CP A, H");
+                trace!(self, "CP A, H");
                 self.cp(
                     self.registers.h
                 );
@@ -1567,7 +2938,7 @@ impl CPU {
             },
             // CP A, L
             0xBD => {
-                println!("CP A, L");
+                trace!(self, "CP A, L");
                 self.cp(
                     self.registers.l
                 );
@@ -1575,7 +2946,7 @@ impl CPU {
             },
             // CP A, (HL)
             0xBE => {
-                println!("CP A, (HL)");
+                trace!(self, "CP A, (HL)");
                 self.cp(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -1585,7 +2956,7 @@ impl CPU {
             },
             // CP A, A
             0xBF => {
-                println!("CP A, A");
+                trace!(self, "CP A, A");
                 self.cp(
                     self.registers.a
                 );
@@ -1593,9 +2964,9 @@ impl CPU {
             },
             // RET NZ
             0xC0 => {
-                println!("RET NZ");
+                trace!(self, "RET NZ");
                 if !self.registers.get_zero() {
-                    self.registers.pc = self.fetchword();
+                    self.registers.pc = self.pop();
                     20
                 } else {
                     8
@@ -1603,14 +2974,14 @@ impl CPU {
             },
             // POP BC
             0xC1 => {
-                println!("POP BC");
+                trace!(self, "POP BC");
                 let value = self.pop();
                 self.registers.set_bc(value);
                 12
             },
             // JP NZ, a16
             0xC2 => {
-                println!("JP NZ, a16");
+                trace!(self, "JP NZ, a16");
                 if !self.registers.get_zero() {
                     self.registers.pc = self.fetchword();
                     16
@@ -1621,13 +2992,13 @@ impl CPU {
             },
             // JP a16
             0xC3 => {
-                println!("JP a16");
+                trace!(self, "JP a16");
                 self.registers.pc = self.fetchword();
                 16
             },
             // CALL NZ, a16
             0xC4 => {
-                println!("CALL NZ, a16");
+                trace!(self, "CALL NZ, a16");
                 if !self.registers.get_zero() {
                     self.push(
                         self.registers.pc + 2
@@ -1641,7 +3012,7 @@ impl CPU {
             },
             // PUSH BC
             0xC5 => {
-                println!("PUSH BC");
+                trace!(self, "PUSH BC");
                 self.push(
                     self.registers.get_bc()
                 );
@@ -1649,22 +3020,22 @@ impl CPU {
             },
             // ADD A, d8
             0xC6 => {
-                println!("ADD A, d8");
+                trace!(self, "ADD A, d8");
                 let value = self.fetchbyte();
                 self.add(value);
                 8
             },
             // RST 00H
             0xC7 => {
-                println!("RST 00H");
+                trace!(self, "RST 00H");
                 self.rst(0x0000);
                 16
             },
             // RET Z
             0xC8 => {
-                println!("RET Z");
+                trace!(self, "RET Z");
                 if self.registers.get_zero() {
-                    self.registers.pc = self.fetchword();
+                    self.registers.pc = self.pop();
                     20
                 } else {
                     8
@@ -1672,13 +3043,13 @@ impl CPU {
             },
             // RET
             0xC9 => {
-                println!("RET");
-                self.registers.pc = self.fetchword();
+                trace!(self, "RET");
+                self.registers.pc = self.pop();
                 16
             },
             // JP Z, a16
             0xCA => {
-                println!("JP Z, a16");
+                trace!(self, "JP Z, a16");
                 if self.registers.get_zero() {
                     self.registers.pc = self.fetchword();
                     16
@@ -1689,12 +3060,12 @@ impl CPU {
             },
             // PREFIX CB
             0xCB => {
-                println!("PREFIX CB");
+                trace!(self, "PREFIX CB");
                 self.call_cb()
             },
             // CALL Z, a16
             0xCC => {
-                println!("CALL Z, a16");
+                trace!(self, "CALL Z, a16");
                 if self.registers.get_zero() {
                     self.push(
                         self.registers.pc + 2
@@ -1708,7 +3079,7 @@ impl CPU {
             },
             // CALL a16
             0xCD => {
-                println!("CALL a16");
+                trace!(self, "CALL a16");
                 self.push(
                     self.registers.pc + 2
                 );
@@ -1717,22 +3088,22 @@ impl CPU {
             },
             // ADC A, d8
             0xCE => {
-                println!("ADC A, d8");
+                trace!(self, "ADC A, d8");
                 let value = self.fetchbyte();
                 self.adc(value);
                 8
             },
             // RST 08H
             0xCF => {
-                println!("RST 08H");
+                trace!(self, "RST 08H");
                 self.rst(0x0080);
                 16
             },
             // RET NC
             0xD0 => {
-                println!("RET NC");
+                trace!(self, "RET NC");
                 if !self.registers.get_carry() {
-                    self.registers.pc = self.fetchword();
+                    self.registers.pc = self.pop();
                     20
                 } else {
                     8
@@ -1740,14 +3111,14 @@ impl CPU {
             },
             // POP DE
             0xD1 => {
-                println!("POP DE");
+                trace!(self, "POP DE");
                 let value = self.pop();
                 self.registers.set_de(value);
                 12
             },
             // JP NC, a16
             0xD2 => {
-                println!("JP NC, a16");
+                trace!(self, "JP NC, a16");
                 if !self.registers.get_carry() {
                     self.registers.pc = self.fetchword();
                     16
@@ -1758,7 +3129,7 @@ impl CPU {
             },
             // CALL NC, a16
             0xD4 => {
-                println!("CALL NC, a16");
+                trace!(self, "CALL NC, a16");
                 if !self.registers.get_carry() {
                     self.push(
                         self.registers.pc + 2
@@ -1772,7 +3143,7 @@ impl CPU {
             },
             // PUSH DE
             0xD5 => {
-                println!("PUSH DE");
+                trace!(self, "PUSH DE");
                 self.push(
                     self.registers.get_de()
                 );
@@ -1780,22 +3151,22 @@ impl CPU {
             },
             // SUB d8
             0xD6 => {
-                println!("SUB d8");
+                trace!(self, "SUB d8");
                 let value = self.fetchbyte();
                 self.sub(value);
                 8
             },
             // RST 10H
             0xD7 => {
-                println!("RST 10H");
+                trace!(self, "RST 10H");
                 self.rst(0x0010);
                 16
             },
             // RET C
             0xD8 => {
-                println!("RET C");
+                trace!(self, "RET C");
                 if self.registers.get_carry() {
-                    self.registers.pc = self.fetchword();
+                    self.registers.pc = self.pop();
                     20
                 } else {
                     8
@@ -1803,15 +3174,17 @@ impl CPU {
             },
             // RETI
             0xD9 => {
-                println!("RETI");
+                trace!(self, "RETI");
                 self.registers.pc = self.pop();
-                self.ei = 1;
+                // Unlike EI, RETI enables the interruptions right away
+                self.ime = true;
+                self.ei = 0;
                 self.di = 0;
                 16
             },
             // JP C, a16
             0xDA => {
-                println!("JP C, a16");
+                trace!(self, "JP C, a16");
                 if self.registers.get_carry() {
                     self.registers.pc = self.fetchword();
                     16
@@ -1822,7 +3195,7 @@ impl CPU {
             },
             // CALL C, a16
             0xDC => {
-                println!("CALL C, a16");
+                trace!(self, "CALL C, a16");
                 if self.registers.get_carry() {
                     self.push(
                         self.registers.pc + 2
@@ -1836,20 +3209,20 @@ impl CPU {
             },
             // SBC A, d8
             0xDE => {
-                println!("SBC A, d8");
+                trace!(self, "SBC A, d8");
                 let value = self.fetchbyte();
                 self.sbc(value);
                 8
             },
             // RST 18H
             0xDF => {
-                println!("RST 18H");
+                trace!(self, "RST 18H");
                 self.rst(0x0018);
                 16
             },
             // LDH (a8), A
             0xE0 => {
-                println!("LDH (a8), A");
+                trace!(self, "LDH (a8), A");
                 let value = self.fetchbyte();
                 self.mmu.write_byte(
                     0xFF00 | value as u16,
@@ -1859,14 +3232,14 @@ impl CPU {
             },
             // POP HL
             0xE1 => {
-                println!("POP HL");
+                trace!(self, "POP HL");
                 let value = self.pop();
                 self.registers.set_hl(value);
                 12
             },
             // LDH (C), A
             0xE2 => {
-                println!("LDH (C), A");
+                trace!(self, "LDH (C), A");
                 self.mmu.write_byte(
                     0xFF00 | self.registers.c as u16,
                     self.registers.a
@@ -1875,7 +3248,7 @@ impl CPU {
             },
             // PUSH HL
             0xE5 => {
-                println!("PUSH HL");
+                trace!(self, "PUSH HL");
                 self.push(
                     self.registers.get_hl()
                 );
@@ -1883,32 +3256,32 @@ impl CPU {
             },
             // AND d8
             0xE6 => {
-                println!("AND d8");
+                trace!(self, "AND d8");
                 let value = self.fetchbyte();
                 self.and(value);
                 8
             },
             // RST 20H
             0xE7 => {
-                println!("RST 20H");
+                trace!(self, "RST 20H");
                 self.rst(0x0020);
                 16
             },
             // ADD SP, r8
             0xE8 => {
-                println!("ADD SP, r8");
+                trace!(self, "ADD SP, r8");
                 self.registers.sp = self.addr8(self.registers.sp);
                 16
             },
             // JP (HL)
             0xE9 => {
-                println!("JP (HL)");
+                trace!(self, "JP (HL)");
                 self.registers.pc = self.registers.get_hl();
                 4
             },
             // LD (a16), A
             0xEA => {
-                println!("LD (a16), A");
+                trace!(self, "LD (a16), A");
                 let value = self.fetchword();
                 self.mmu.write_byte(
                     value,
@@ -1918,20 +3291,20 @@ impl CPU {
             },
             // XOR d8
             0xEE => {
-                println!("XOR d8");
+                trace!(self, "XOR d8");
                 let value = self.fetchbyte();
                 self.xor(value);
                 8
             },
             // RST 28H
             0xEF => {
-                println!("RST 28H");
+                trace!(self, "RST 28H");
                 self.rst(0x0028);
                 16
             },
             // LDH A, (a8)
             0xF0 => {
-                println!("LDH A, (a8)");
+                trace!(self, "LDH A, (a8)");
                 let value = self.fetchbyte();
                 self.registers.a = self.mmu.read_byte(
                     0xFF00 | value as u16
@@ -1940,7 +3313,7 @@ impl CPU {
             },
             // POP AF
             0xF1 => {
-                println!("POP AF");
+                trace!(self, "POP AF");
                 let value = self.pop();
                 self.registers.set_af(
                     value & 0xFFF0
@@ -1949,7 +3322,7 @@ impl CPU {
             },
             // LD A, (C)
             0xF2 => {
-                println!("LD A, (C)");
+                trace!(self, "LD A, (C)");
                 self.registers.a = self.mmu.read_byte(
                     0xFF00 | self.registers.c as u16
                 );
@@ -1957,7 +3330,7 @@ impl CPU {
             },
             // DI
             0xF3 => {
-                println!("DI");
+                trace!(self, "DI");
                 self.di = 2;
                 // Cancel any scheduled effects of the ei instruction
                 self.ei = 0;
@@ -1965,7 +3338,7 @@ impl CPU {
             },
             // PUSH AF
             0xF5 => {
-                println!("PUSH AF");
+                trace!(self, "PUSH AF");
                 self.push(
                     self.registers.get_af()
                 );
@@ -1973,60 +3346,64 @@ impl CPU {
             },
             // OR d8
             0xF6 => {
-                println!("OR d8");
+                trace!(self, "OR d8");
                 let value = self.fetchbyte();
                 self.or(value);
                 8
             },
             // RST 30H
             0xF7 => {
-                println!("RST 30H");
+                trace!(self, "RST 30H");
                 self.rst(0x0030);
                 16
             },
             // LD HL, SP+r8
             0xF8 => {
-                println!("LD HL, SP+r8");
+                trace!(self, "LD HL, SP+r8");
                 let value = self.addr8(self.registers.sp);
                 self.registers.set_hl(value);
                 12
             },
             // LD SP, HL
             0xF9 => {
-                println!("LD SP, HL");
+                trace!(self, "LD SP, HL");
                 self.registers.sp = self.registers.get_hl();
                 8
             },
             // LD A, (a16)
             0xFA => {
-                println!("LD A, (a16)");
+                trace!(self, "LD A, (a16)");
                 let value = self.fetchword();
                 self.registers.a = self.mmu.read_byte(value);
                 12
             },
             // EI
             0xFB => {
-                println!("EI");
+                trace!(self, "EI");
                 self.ei = 2;
                 4
             },
             // CP A, d8
             0xFE => {
-                println!("CP A, d8");
+                trace!(self, "CP A, d8");
                 let value = self.fetchbyte();
                 self.cp(value);
                 8
             },
             // RST 38H
             0xFF => {
-                println!("RST 38H");
+                trace!(self, "RST 38H");
                 self.rst(0x0038);
                 16
             },
             // Si code non trouvé
             _ => {
-                println!("Si code non trouvé");
-                panic!("OpCode not found");
+                trace!(self, "Si code non trouvé");
+                if self.is_strict {
+                    panic!("Illegal opcode {:#04x}\n{}", op, self.dump_state());
+                }
+                // Ignored like a NOP
+                4
             }
         }
     }
@@ -2035,6 +3412,13 @@ impl CPU {
     ///
     /// <https://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html>
     ///
+    /// The returned cost is the one of the whole instruction, including the
+    /// 4 cycles used to fetch the 0xCB prefix: 8 cycles on a register, 16 on
+    /// (HL) and 12 for BIT n, (HL).
+    ///
+    /// Every one of the 256 opcodes is defined: the match has no default arm
+    /// so that the compiler rejects a missing one.
+    ///
     /// # Returns
     /// **u32**: Number of cycles used for the step
     fn call_cb(&mut self) -> u32 {
@@ -2042,7 +3426,7 @@ impl CPU {
         match op {
             // RLC B
             0x00 => {
-                println!("RLC B");
+                trace!(self, "RLC B");
                 self.registers.b = self.rlc(
                     self.registers.b
                 );
@@ -2050,7 +3434,7 @@ impl CPU {
             },
             // RLC C
             0x01 => {
-                println!("RLC C");
+                trace!(self, "RLC C");
                 self.registers.c = self.rlc(
                     self.registers.c
                 );
@@ -2058,7 +3442,7 @@ impl CPU {
             },
             // RLC D
             0x02 => {
-                println!("RLC D");
+                trace!(self, "RLC D");
                 self.registers.d = self.rlc(
                     self.registers.d
                 );
@@ -2066,7 +3450,7 @@ impl CPU {
             },
             // RLC E
             0x03 => {
-                println!("RLC E");
+                trace!(self, "RLC E");
                 self.registers.e = self.rlc(
                     self.registers.e
                 );
@@ -2074,7 +3458,7 @@ impl CPU {
             },
             // RLC H
             0x04 => {
-                println!("RLC H");
+                trace!(self, "RLC H");
                 self.registers.h = self.rlc(
                     self.registers.h
                 );
@@ -2082,7 +3466,7 @@ impl CPU {
             },
             // RLC L
             0x05 => {
-                println!("RLC L");
+                trace!(self, "RLC L");
                 self.registers.l = self.rlc(
                     self.registers.l
                 );
@@ -2090,7 +3474,7 @@ impl CPU {
             },
             // RLC (HL)
             0x06 => {
-                println!("RLC (HL)");
+                trace!(self, "RLC (HL)");
                 let value = self.rlc(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2104,7 +3488,7 @@ impl CPU {
             },
             // RLC A
             0x07 => {
-                println!("RLC A");
+                trace!(self, "RLC A");
                 self.registers.a = self.rlc(
                     self.registers.a
                 );
@@ -2112,7 +3496,7 @@ impl CPU {
             },
             // RRC B
             0x08 => {
-                println!("RRC B");
+                trace!(self, "RRC B");
                 self.registers.b = self.rrc(
                     self.registers.b
                 );
@@ -2120,7 +3504,7 @@ impl CPU {
             },
             // RRC C
             0x09 => {
-                println!("RRC C");
+                trace!(self, "RRC C");
                 self.registers.c = self.rrc(
                     self.registers.c
                 );
@@ -2128,7 +3512,7 @@ impl CPU {
             },
             // RRC D
             0x0A => {
-                println!("RRC D");
+                trace!(self, "RRC D");
                 self.registers.d = self.rrc(
                     self.registers.d
                 );
@@ -2136,7 +3520,7 @@ impl CPU {
             },
             // RRC E
             0x0B => {
-                println!("RRC E");
+                trace!(self, "RRC E");
                 self.registers.e = self.rrc(
                     self.registers.e
                 );
@@ -2144,7 +3528,7 @@ impl CPU {
             },
             // RRC H
             0x0C => {
-                println!("RRC H");
+                trace!(self, "RRC H");
                 self.registers.h = self.rrc(
                     self.registers.h
                 );
@@ -2152,7 +3536,7 @@ impl CPU {
             },
             // RRC L
             0x0D => {
-                println!("RRC L");
+                trace!(self, "RRC L");
                 self.registers.l = self.rrc(
                     self.registers.l
                 );
@@ -2160,7 +3544,7 @@ impl CPU {
             },
             // RRC (HL)
             0x0E => {
-                println!("RRC (HL)");
+                trace!(self, "RRC (HL)");
                 let value = self.rrc(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2174,7 +3558,7 @@ impl CPU {
             },
             // RRC A
             0x0F => {
-                println!("RRC A");
+                trace!(self, "RRC A");
                 self.registers.a = self.rrc(
                     self.registers.a
                 );
@@ -2182,7 +3566,7 @@ impl CPU {
             },
             // RL B
             0x10 => {
-                println!("RL B");
+                trace!(self, "RL B");
                 self.registers.b = self.rl(
                     self.registers.b
                 );
@@ -2190,7 +3574,7 @@ impl CPU {
             },
             // RL C
             0x11 => {
-                println!("RL C");
+                trace!(self, "RL C");
                 self.registers.c = self.rl(
                     self.registers.c
                 );
@@ -2198,7 +3582,7 @@ impl CPU {
             },
             // RL D
             0x12 => {
-                println!("RL D");
+                trace!(self, "RL D");
                 self.registers.d = self.rl(
                     self.registers.d
                 );
@@ -2206,7 +3590,7 @@ impl CPU {
             },
             // RC E
             0x13 => {
-                println!("RC E");
+                trace!(self, "RC E");
                 self.registers.e = self.rl(
                     self.registers.e
                 );
@@ -2214,7 +3598,7 @@ impl CPU {
             },
             // RC H
             0x14 => {
-                println!("RC H");
+                trace!(self, "RC H");
                 self.registers.h = self.rl(
                     self.registers.h
                 );
@@ -2222,7 +3606,7 @@ impl CPU {
             },
             // RL L
             0x15 => {
-                println!("RL L");
+                trace!(self, "RL L");
                 self.registers.l = self.rl(
                     self.registers.l
                 );
@@ -2230,7 +3614,7 @@ impl CPU {
             },
             // RL (HL)
             0x16 => {
-                println!("RL (HL)");
+                trace!(self, "RL (HL)");
                 let value = self.rl(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2244,7 +3628,7 @@ impl CPU {
             },
             // RL A
             0x17 => {
-                println!("RL A");
+                trace!(self, "RL A");
                 self.registers.a = self.rl(
                     self.registers.a
                 );
@@ -2252,7 +3636,7 @@ impl CPU {
             },
             // RR B
             0x18 => {
-                println!("RR B");
+                trace!(self, "RR B");
                 self.registers.b = self.rr(
                     self.registers.b
                 );
@@ -2260,7 +3644,7 @@ impl CPU {
             },
             // RR C
             0x19 => {
-                println!("RR C");
+                trace!(self, "RR C");
                 self.registers.c = self.rr(
                     self.registers.c
                 );
@@ -2268,7 +3652,7 @@ impl CPU {
             },
             // RR D
             0x1A => {
-                println!("RR D");
+                trace!(self, "RR D");
                 self.registers.d = self.rr(
                     self.registers.d
                 );
@@ -2276,7 +3660,7 @@ impl CPU {
             },
             // RR E
             0x1B => {
-                println!("RR E");
+                trace!(self, "RR E");
                 self.registers.e = self.rr(
                     self.registers.e
                 );
@@ -2284,7 +3668,7 @@ impl CPU {
             },
             // RR H
             0x1C => {
-                println!("RR H");
+                trace!(self, "RR H");
                 self.registers.h = self.rr(
                     self.registers.h
                 );
@@ -2292,7 +3676,7 @@ impl CPU {
             },
             // RR L
             0x1D => {
-                println!("RR L");
+                trace!(self, "RR L");
                 self.registers.l = self.rr(
                     self.registers.l
                 );
@@ -2300,7 +3684,7 @@ impl CPU {
             },
             // RR (HL)
             0x1E => {
-                println!("RR (HL)");
+                trace!(self, "RR (HL)");
                 let value = self.rr(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2314,7 +3698,7 @@ impl CPU {
             },
             // RR A
             0x1F => {
-                println!("RR A");
+                trace!(self, "RR A");
                 self.registers.a = self.rr(
                     self.registers.a
                 );
@@ -2322,7 +3706,7 @@ impl CPU {
             },
             // SLA B
             0x20 => {
-                println!("SLA B");
+                trace!(self, "SLA B");
                 self.registers.b = self.sla(
                     self.registers.b
                 );
@@ -2330,7 +3714,7 @@ impl CPU {
             },
             // SLA C
             0x21 => {
-                println!("SLA C");
+                trace!(self, "SLA C");
                 self.registers.c = self.sla(
                     self.registers.c
                 );
@@ -2338,7 +3722,7 @@ impl CPU {
             },
             // SLA D
             0x22 => {
-                println!("SLA D");
+                trace!(self, "SLA D");
                 self.registers.d = self.sla(
                     self.registers.d
                 );
@@ -2346,7 +3730,7 @@ impl CPU {
             },
             // RC E
             0x23 => {
-                println!("RC E");
+                trace!(self, "RC E");
                 self.registers.e = self.sla(
                     self.registers.e
                 );
@@ -2354,7 +3738,7 @@ impl CPU {
             },
             // RC H
             0x24 => {
-                println!("RC H");
+                trace!(self, "RC H");
                 self.registers.h = self.sla(
                     self.registers.h
                 );
@@ -2362,7 +3746,7 @@ impl CPU {
             },
             // SLA L
             0x25 => {
-                println!("SLA L");
+                trace!(self, "SLA L");
                 self.registers.l = self.sla(
                     self.registers.l
                 );
@@ -2370,7 +3754,7 @@ impl CPU {
             },
             // SLA (HL)
             0x26 => {
-                println!("SLA (HL)");
+                trace!(self, "SLA (HL)");
                 let value = self.sla(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2384,7 +3768,7 @@ impl CPU {
             },
             // SLA A
             0x27 => {
-                println!("SLA A");
+                trace!(self, "SLA A");
                 self.registers.a = self.sla(
                     self.registers.a
                 );
@@ -2392,7 +3776,7 @@ impl CPU {
             },
             // SRA B
             0x28 => {
-                println!("SRA B");
+                trace!(self, "SRA B");
                 self.registers.b = self.sra(
                     self.registers.b
                 );
@@ -2400,7 +3784,7 @@ impl CPU {
             },
             // SRA C
             0x29 => {
-                println!("SRA C");
+                trace!(self, "SRA C");
                 self.registers.c = self.sra(
                     self.registers.c
                 );
@@ -2408,7 +3792,7 @@ impl CPU {
             },
             // SRA D
             0x2A => {
-                println!("SRA D");
+                trace!(self, "SRA D");
                 self.registers.d = self.sra(
                     self.registers.d
                 );
@@ -2416,7 +3800,7 @@ impl CPU {
             },
             // SRA E
             0x2B => {
-                println!("SRA E");
+                trace!(self, "SRA E");
                 self.registers.e = self.sra(
                     self.registers.e
                 );
@@ -2424,7 +3808,7 @@ impl CPU {
             },
             // SRA H
             0x2C => {
-                println!("SRA H");
+                trace!(self, "SRA H");
                 self.registers.h = self.sra(
                     self.registers.h
                 );
@@ -2432,7 +3816,7 @@ impl CPU {
             },
             // SRA L
             0x2D => {
-                println!("SRA L");
+                trace!(self, "SRA L");
                 self.registers.l = self.sra(
                     self.registers.l
                 );
@@ -2440,7 +3824,7 @@ impl CPU {
             },
             // SRA (HL)
             0x2E => {
-                println!("SRA (HL)");
+                trace!(self, "SRA (HL)");
                 let value = self.sra(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2454,7 +3838,7 @@ impl CPU {
             },
             // SRA A
             0x2F => {
-                println!("SRA A");
+                trace!(self, "SRA A");
                 self.registers.a = self.sra(
                     self.registers.a
                 );
@@ -2462,7 +3846,7 @@ impl CPU {
             },
             // SWAP B
             0x30 => {
-                println!("SWAP B");
+                trace!(self, "SWAP B");
                 self.registers.b = self.swap(
                     self.registers.b
                 );
@@ -2470,7 +3854,7 @@ impl CPU {
             },
             // SWAP C
             0x31 => {
-                println!("SWAP C");
+                trace!(self, "SWAP C");
                 self.registers.c = self.swap(
                     self.registers.c
                 );
@@ -2478,7 +3862,7 @@ impl CPU {
             },
             // SWAP D
             0x32 => {
-                println!("SWAP D");
+                trace!(self, "SWAP D");
                 self.registers.d = self.swap(
                     self.registers.d
                 );
@@ -2486,7 +3870,7 @@ impl CPU {
             },
             // RC E
             0x33 => {
-                println!("RC E");
+                trace!(self, "RC E");
                 self.registers.e = self.swap(
                     self.registers.e
                 );
@@ -2494,7 +3878,7 @@ impl CPU {
             },
             // RC H
             0x34 => {
-                println!("RC H");
+                trace!(self, "RC H");
                 self.registers.h = self.swap(
                     self.registers.h
                 );
@@ -2502,7 +3886,7 @@ impl CPU {
             },
             // SWAP L
             0x35 => {
-                println!("SWAP L");
+                trace!(self, "SWAP L");
                 self.registers.l = self.swap(
                     self.registers.l
                 );
@@ -2510,7 +3894,7 @@ impl CPU {
             },
             // SWAP (HL)
             0x36 => {
-                println!("SWAP (HL)");
+                trace!(self, "SWAP (HL)");
                 let value = self.swap(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2524,7 +3908,7 @@ impl CPU {
             },
             // SWAP A
             0x37 => {
-                println!("SWAP A");
+                trace!(self, "SWAP A");
                 self.registers.a = self.swap(
                     self.registers.a
                 );
@@ -2532,7 +3916,7 @@ impl CPU {
             },
             // SRL B
             0x38 => {
-                println!("SRL B");
+                trace!(self, "SRL B");
                 self.registers.b = self.srl(
                     self.registers.b
                 );
@@ -2540,7 +3924,7 @@ impl CPU {
             },
             // SRL C
             0x39 => {
-                println!("SRL C");
+                trace!(self, "SRL C");
                 self.registers.c = self.srl(
                     self.registers.c
                 );
@@ -2548,7 +3932,7 @@ impl CPU {
             },
             // SRL D
             0x3A => {
-                println!("SRL D");
+                trace!(self, "SRL D");
                 self.registers.d = self.srl(
                     self.registers.d
                 );
@@ -2556,7 +3940,7 @@ impl CPU {
             },
             // SRL E
             0x3B => {
-                println!("SRL E");
+                trace!(self, "SRL E");
                 self.registers.e = self.srl(
                     self.registers.e
                 );
@@ -2564,7 +3948,7 @@ impl CPU {
             },
             // SRL H
             0x3C => {
-                println!("SRL H");
+                trace!(self, "SRL H");
                 self.registers.h = self.srl(
                     self.registers.h
                 );
@@ -2572,7 +3956,7 @@ impl CPU {
             },
             // SRL L
             0x3D => {
-                println!("SRL L");
+                trace!(self, "SRL L");
                 self.registers.l = self.srl(
                     self.registers.l
                 );
@@ -2580,7 +3964,7 @@ impl CPU {
             },
             // SRL (HL)
             0x3E => {
-                println!("SRL (HL)");
+                trace!(self, "SRL (HL)");
                 let value = self.srl(
                     self.mmu.read_byte(
                         self.registers.get_hl()
@@ -2594,7 +3978,7 @@ impl CPU {
             },
             // SRL A
             0x3F => {
-                println!("SRL A");
+                trace!(self, "SRL A");
                 self.registers.a = self.srl(
                     self.registers.a
                 );
@@ -2602,467 +3986,467 @@ impl CPU {
             },
             // BIT 0, B
             0x40 => {
-                println!("BIT 0, B");
+                trace!(self, "BIT 0, B");
                 self.bit(0, self.registers.b);
                 8
             },
             // BIT 0, C
             0x41 => {
-                println!("BIT 0, C");
+                trace!(self, "BIT 0, C");
                 self.bit(0, self.registers.c);
                 8
             },
             // BIT 0, D
             0x42 => {
-                println!("BIT 0, D");
+                trace!(self, "BIT 0, D");
                 self.bit(0, self.registers.d);
                 8
             },
             // BIT 0, E
             0x43 => {
-                println!("BIT 0, E");
+                trace!(self, "BIT 0, E");
                 self.bit(0, self.registers.e);
                 8
             },
             // BIT 0, H
             0x44 => {
-                println!("BIT 0, H");
+                trace!(self, "BIT 0, H");
                 self.bit(0, self.registers.h);
                 8
             },
             // BIT 0, L
             0x45 => {
-                println!("BIT 0, L");
+                trace!(self, "BIT 0, L");
                 self.bit(0, self.registers.l);
                 8
             },
             // BIT 0, (HL)
             0x46 => {
-                println!("BIT 0, (HL)");
+                trace!(self, "BIT 0, (HL)");
                 self.bit(
                     0,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 0, A
             0x47 => {
-                println!("BIT 0, A");
+                trace!(self, "BIT 0, A");
                 self.bit(0, self.registers.a);
                 8
             },
             // BIT 1, B
             0x48 => {
-                println!("BIT 1, B");
+                trace!(self, "BIT 1, B");
                 self.bit(1, self.registers.b);
                 8
             },
             // BIT 1, C
             0x49 => {
-                println!("BIT 1, C");
+                trace!(self, "BIT 1, C");
                 self.bit(1, self.registers.c);
                 8
             },
             // BIT 1, D
             0x4A => {
-                println!("BIT 1, D");
+                trace!(self, "BIT 1, D");
                 self.bit(1, self.registers.d);
                 8
             },
             // BIT 1, E
             0x4B => {
-                println!("BIT 1, E");
+                trace!(self, "BIT 1, E");
                 self.bit(1, self.registers.e);
                 8
             },
             // BIT 1, H
             0x4C => {
-                println!("BIT 1, H");
+                trace!(self, "BIT 1, H");
                 self.bit(1, self.registers.h);
                 8
             },
             // BIT 1, L
             0x4D => {
-                println!("BIT 1, L");
+                trace!(self, "BIT 1, L");
                 self.bit(1, self.registers.l);
                 8
             },
             // BIT 1, (HL)
             0x4E => {
-                println!("BIT 1, (HL)");
+                trace!(self, "BIT 1, (HL)");
                 self.bit(
                     1,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 1, A
             0x4F => {
-                println!("BIT 1, A");
+                trace!(self, "BIT 1, A");
                 self.bit(1, self.registers.a);
                 8
             },
             // BIT 2, B
             0x50 => {
-                println!("BIT 2, B");
+                trace!(self, "BIT 2, B");
                 self.bit(2, self.registers.b);
                 8
             },
             // BIT 2, C
             0x51 => {
-                println!("BIT 2, C");
+                trace!(self, "BIT 2, C");
                 self.bit(2, self.registers.c);
                 8
             },
             // BIT 2, D
             0x52 => {
-                println!("BIT 2, D");
+                trace!(self, "BIT 2, D");
                 self.bit(2, self.registers.d);
                 8
             },
             // BIT 2, E
             0x53 => {
-                println!("BIT 2, E");
+                trace!(self, "BIT 2, E");
                 self.bit(2, self.registers.e);
                 8
             },
             // BIT 2, H
             0x54 => {
-                println!("BIT 2, H");
+                trace!(self, "BIT 2, H");
                 self.bit(2, self.registers.h);
                 8
             },
             // BIT 2, L
             0x55 => {
-                println!("BIT 2, L");
+                trace!(self, "BIT 2, L");
                 self.bit(2, self.registers.l);
                 8
             },
             // BIT 2, (HL)
             0x56 => {
-                println!("BIT 2, (HL)");
+                trace!(self, "BIT 2, (HL)");
                 self.bit(
                     2,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 2, A
             0x57 => {
-                println!("BIT 2, A");
+                trace!(self, "BIT 2, A");
                 self.bit(2, self.registers.a);
                 8
             },
             // BIT 3, B
             0x58 => {
-                println!("BIT 3, B");
+                trace!(self, "BIT 3, B");
                 self.bit(3, self.registers.b);
                 8
             },
             // BIT 3, C
             0x59 => {
-                println!("BIT 3, C");
+                trace!(self, "BIT 3, C");
                 self.bit(3, self.registers.c);
                 8
             },
             // BIT 3, D
             0x5A => {
-                println!("BIT 3, D");
+                trace!(self, "BIT 3, D");
                 self.bit(3, self.registers.d);
                 8
             },
             // BIT 3, E
             0x5B => {
-                println!("BIT 3, E");
+                trace!(self, "BIT 3, E");
                 self.bit(3, self.registers.e);
                 8
             },
             // BIT 3, H
             0x5C => {
-                println!("BIT 3, H");
+                trace!(self, "BIT 3, H");
                 self.bit(3, self.registers.h);
                 8
             },
             // BIT 3, L
             0x5D => {
-                println!("BIT 3, L");
+                trace!(self, "BIT 3, L");
                 self.bit(3, self.registers.l);
                 8
             },
             // BIT 3, (HL)
             0x5E => {
-                println!("BIT 3, (HL)");
+                trace!(self, "BIT 3, (HL)");
                 self.bit(
                     3,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 3, A
             0x5F => {
-                println!("BIT 3, A");
+                trace!(self, "BIT 3, A");
                 self.bit(3, self.registers.a);
                 8
             },
             // BIT 4, B
             0x60 => {
-                println!("BIT 4, B");
+                trace!(self, "BIT 4, B");
                 self.bit(4, self.registers.b);
                 8
             },
             // BIT 4, C
             0x61 => {
-                println!("BIT 4, C");
+                trace!(self, "BIT 4, C");
                 self.bit(4, self.registers.c);
                 8
             },
             // BIT 4, D
             0x62 => {
-                println!("BIT 4, D");
+                trace!(self, "BIT 4, D");
                 self.bit(4, self.registers.d);
                 8
             },
             // BIT 4, E
             0x63 => {
-                println!("BIT 4, E");
+                trace!(self, "BIT 4, E");
                 self.bit(4, self.registers.e);
                 8
             },
             // BIT 4, H
             0x64 => {
-                println!("BIT 4, H");
+                trace!(self, "BIT 4, H");
                 self.bit(4, self.registers.h);
                 8
             },
             // BIT 4, L
             0x65 => {
-                println!("BIT 4, L");
+                trace!(self, "BIT 4, L");
                 self.bit(4, self.registers.l);
                 8
             },
             // BIT 4, (HL)
             0x66 => {
-                println!("BIT 4, (HL)");
+                trace!(self, "BIT 4, (HL)");
                 self.bit(
                     4,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 4, A
             0x67 => {
-                println!("BIT 4, A");
+                trace!(self, "BIT 4, A");
                 self.bit(4, self.registers.a);
                 8
             },
             // BIT 5, B
             0x68 => {
-                println!("BIT 5, B");
+                trace!(self, "BIT 5, B");
                 self.bit(5, self.registers.b);
                 8
             },
             // BIT 5, C
             0x69 => {
-                println!("BIT 5, C");
+                trace!(self, "BIT 5, C");
                 self.bit(5, self.registers.c);
                 8
             },
             // BIT 5, D
             0x6A => {
-                println!("BIT 5, D");
+                trace!(self, "BIT 5, D");
                 self.bit(5, self.registers.d);
                 8
             },
             // BIT 5, E
             0x6B => {
-                println!("BIT 5, E");
+                trace!(self, "BIT 5, E");
                 self.bit(5, self.registers.e);
                 8
             },
             // BIT 5, H
             0x6C => {
-                println!("BIT 5, H");
+                trace!(self, "BIT 5, H");
                 self.bit(5, self.registers.h);
                 8
             },
             // BIT 5, L
             0x6D => {
-                println!("BIT 5, L");
+                trace!(self, "BIT 5, L");
                 self.bit(5, self.registers.l);
                 8
             },
             // BIT 5, (HL)
             0x6E => {
-                println!("BIT 5, (HL)");
+                trace!(self, "BIT 5, (HL)");
                 self.bit(
                     5,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 5, A
             0x6F => {
-                println!("BIT 5, A");
+                trace!(self, "BIT 5, A");
                 self.bit(5, self.registers.a);
                 8
             },
             // BIT 6, B
             0x70 => {
-                println!("BIT 6, B");
+                trace!(self, "BIT 6, B");
                 self.bit(6, self.registers.b);
                 8
             },
             // BIT 6, C
             0x71 => {
-                println!("BIT 6, C");
+                trace!(self, "BIT 6, C");
                 self.bit(6, self.registers.c);
                 8
             },
             // BIT 6, D
             0x72 => {
-                println!("BIT 6, D");
+                trace!(self, "BIT 6, D");
                 self.bit(6, self.registers.d);
                 8
             },
             // BIT 6, E
             0x73 => {
-                println!("BIT 6, E");
+                trace!(self, "BIT 6, E");
                 self.bit(6, self.registers.e);
                 8
             },
             // BIT 6, H
             0x74 => {
-                println!("BIT 6, H");
+                trace!(self, "BIT 6, H");
                 self.bit(6, self.registers.h);
                 8
             },
             // BIT 6, L
             0x75 => {
-                println!("BIT 6, L");
+                trace!(self, "BIT 6, L");
                 self.bit(6, self.registers.l);
                 8
             },
             // BIT 6, (HL)
             0x76 => {
-                println!("BIT 6, (HL)");
+                trace!(self, "BIT 6, (HL)");
                 self.bit(
                     6,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 6, A
             0x77 => {
-                println!("BIT 6, A");
+                trace!(self, "BIT 6, A");
                 self.bit(6, self.registers.a);
                 8
             },
             // BIT 7, B
             0x78 => {
-                println!("BIT 7, B");
+                trace!(self, "BIT 7, B");
                 self.bit(7, self.registers.b);
                 8
             },
             // BIT 7, C
             0x79 => {
-                println!("BIT 7, C");
+                trace!(self, "BIT 7, C");
                 self.bit(7, self.registers.c);
                 8
             },
             // BIT 7, D
             0x7A => {
-                println!("BIT 7, D");
+                trace!(self, "BIT 7, D");
                 self.bit(7, self.registers.d);
                 8
             },
             // BIT 7, E
             0x7B => {
-                println!("BIT 7, E");
+                trace!(self, "BIT 7, E");
                 self.bit(7, self.registers.e);
                 8
             },
             // BIT 7, H
             0x7C => {
-                println!("BIT 7, H");
+                trace!(self, "BIT 7, H");
                 self.bit(7, self.registers.h);
                 8
             },
             // BIT 7, L
             0x7D => {
-                println!("BIT 7, L");
+                trace!(self, "BIT 7, L");
                 self.bit(7, self.registers.l);
                 8
             },
             // BIT 7, (HL)
             0x7E => {
-                println!("BIT 7, (HL)");
+                trace!(self, "BIT 7, (HL)");
                 self.bit(
                     7,
                     self.mmu.read_byte(
                         self.registers.get_hl()
                     )
                 );
-                16
+                12
             },
             // BIT 7, A
             0x7F => {
-                println!("BIT 7, A");
+                trace!(self, "BIT 7, A");
                 self.bit(7, self.registers.a);
                 8
             },
             // RES 0, B
             0x80 => {
-                println!("RES 0, B");
+                trace!(self, "RES 0, B");
                 self.registers.b = self.res(0, self.registers.b);
                 8
             },
             // RES 0, C
             0x81 => {
-                println!("RES 0, C");
+                trace!(self, "RES 0, C");
                 self.registers.c = self.res(0, self.registers.c);
                 8
             },
             // RES 0, D
             0x82 => {
-                println!("RES 0, D");
+                trace!(self, "RES 0, D");
                 self.registers.d = self.res(0, self.registers.d);
                 8
             },
             // RES 0, E
             0x83 => {
-                println!("RES 0, E");
+                trace!(self, "RES 0, E");
                 self.registers.e = self.res(0, self.registers.e);
                 8
             },
             // RES 0, H
             0x84 => {
-                println!("RES 0, H");
+                trace!(self, "RES 0, H");
                 self.registers.e = self.res(0, self.registers.h);
                 8
             },
             // RES 0, L
             0x85 => {
-                println!("RES 0, L");
+                trace!(self, "RES 0, L");
                 self.registers.l = self.res(0, self.registers.l);
                 8
             },
             // RES 0, (HL)
             0x86 => {
-                println!("RES 0, (HL)");
+                trace!(self, "RES 0, (HL)");
                 let value = self.res(
                     0,
                     self.mmu.read_byte(
@@ -3077,49 +4461,49 @@ impl CPU {
             },
             // RES 0, A
             0x87 => {
-                println!("RES 0, A");
+                trace!(self, "RES 0, A");
                 self.registers.a = self.res(0, self.registers.a);
                 8
             },
             // RES 1, B
             0x88 => {
-                println!("RES 1, B");
+                trace!(self, "RES 1, B");
                 self.registers.b = self.res(1, self.registers.b);
                 8
             },
             // RES 1, C
             0x89 => {
-                println!("RES 1, C");
+                trace!(self, "RES 1, C");
                 self.registers.c = self.res(1, self.registers.c);
                 8
             },
             // RES 1, D
             0x8A => {
-                println!("RES 1, D");
+                trace!(self, "RES 1, D");
                 self.registers.d = self.res(1, self.registers.d);
                 8
             },
             // RES 1, E
             0x8B => {
-                println!("RES 1, E");
+                trace!(self, "RES 1, E");
                 self.registers.e = self.res(1, self.registers.e);
                 8
             },
             // RES 1, H
             0x8C => {
-                println!("RES 1, H");
+                trace!(self, "RES 1, H");
                 self.registers.h = self.res(1, self.registers.h);
                 8
             },
             // RES 1, L
             0x8D => {
-                println!("RES 1, L");
+                trace!(self, "RES 1, L");
                 self.registers.l = self.res(1, self.registers.l);
                 8
             },
             // RES 1, (HL)
             0x8E => {
-                println!("RES 1, (HL)");
+                trace!(self, "RES 1, (HL)");
                 let value = self.res(
                     1,
                     self.mmu.read_byte(
@@ -3134,49 +4518,49 @@ impl CPU {
             },
             // RES 1, A
             0x8F => {
-                println!("RES 1, A");
+                trace!(self, "RES 1, A");
                 self.registers.a = self.res(1, self.registers.a);
                 8
             },
             // RES 2, B
             0x90 => {
-                println!("RES 2, B");
+                trace!(self, "RES 2, B");
                 self.registers.b = self.res(2, self.registers.b);
                 8
             },
             // RES 2, C
             0x91 => {
-                println!("RES 2, C");
+                trace!(self, "RES 2, C");
                 self.registers.c = self.res(2, self.registers.c);
                 8
             },
             // RES 2, D
             0x92 => {
-                println!("RES 2, D");
+                trace!(self, "RES 2, D");
                 self.registers.d = self.res(2, self.registers.d);
                 8
             },
             // RES 2, E
             0x93 => {
-                println!("RES 2, E");
+                trace!(self, "RES 2, E");
                 self.registers.e = self.res(2, self.registers.e);
                 8
             },
             // RES 2, H
             0x94 => {
-                println!("RES 2, H");
+                trace!(self, "RES 2, H");
                 self.registers.h = self.res(2, self.registers.h);
                 8
             },
             // RES 2, L
             0x95 => {
-                println!("RES 2, L");
+                trace!(self, "RES 2, L");
                 self.registers.l = self.res(2, self.registers.l);
                 8
             },
             // RES 2, (HL)
             0x96 => {
-                println!("RES 2, (HL)");
+                trace!(self, "RES 2, (HL)");
                 let value = self.res(
                     2,
                     self.mmu.read_byte(
@@ -3191,49 +4575,49 @@ impl CPU {
             },
             // RES 2, A
             0x97 => {
-                println!("RES 2, A");
+                trace!(self, "RES 2, A");
                 self.registers.a = self.res(2, self.registers.a);
                 8
             },
             // RES 3, B
             0x98 => {
-                println!("RES 3, B");
+                trace!(self, "RES 3, B");
                 self.registers.b = self.res(3, self.registers.b);
                 8
             },
             // RES 3, C
             0x99 => {
-                println!("RES 3, C");
+                trace!(self, "RES 3, C");
                 self.registers.c = self.res(3, self.registers.c);
                 8
             },
             // RES 3, D
             0x9A => {
-                println!("RES 3, D");
+                trace!(self, "RES 3, D");
                 self.registers.d = self.res(3, self.registers.d);
                 8
             },
             // RES 3, E
             0x9B => {
-                println!("RES 3, E");
+                trace!(self, "RES 3, E");
                 self.registers.e = self.res(3, self.registers.e);
                 8
             },
             // RES 3, H
             0x9C => {
-                println!("RES 3, H");
+                trace!(self, "RES 3, H");
                 self.registers.h = self.res(3, self.registers.h);
                 8
             },
             // RES 3, L
             0x9D => {
-                println!("RES 3, L");
+                trace!(self, "RES 3, L");
                 self.registers.l = self.res(3, self.registers.l);
                 8
             },
             // RES 3, (HL)
             0x9E => {
-                println!("RES 3, (HL)");
+                trace!(self, "RES 3, (HL)");
                 let value = self.res(
                     3,
                     self.mmu.read_byte(
@@ -3248,49 +4632,49 @@ impl CPU {
             },
             // RES 3, A
             0x9F => {
-                println!("RES 3, A");
+                trace!(self, "RES 3, A");
                 self.registers.a = self.res(3, self.registers.a);
                 8
             },
             // RES 4, B
             0xA0 => {
-                println!("RES 4, B");
+                trace!(self, "RES 4, B");
                 self.registers.b = self.res(4, self.registers.b);
                 8
             },
             // RES 4, C
             0xA1 => {
-                println!("RES 4, C");
+                trace!(self, "RES 4, C");
                 self.registers.c = self.res(4, self.registers.c);
                 8
             },
             // RES 4, D
             0xA2 => {
-                println!("RES 4, D");
+                trace!(self, "RES 4, D");
                 self.registers.d = self.res(4, self.registers.d);
                 8
             },
             // RES 4, E
             0xA3 => {
-                println!("RES 4, E");
+                trace!(self, "RES 4, E");
                 self.registers.e = self.res(4, self.registers.e);
                 8
             },
             // RES 4, H
             0xA4 => {
-                println!("RES 4, H");
+                trace!(self, "RES 4, H");
                 self.registers.h = self.res(4, self.registers.h);
                 8
             },
             // RES 4, L
             0xA5 => {
-                println!("RES 4, L");
+                trace!(self, "RES 4, L");
                 self.registers.l = self.res(4, self.registers.l);
                 8
             },
             // RES 4, (HL)
             0xA6 => {
-                println!("RES 4, (HL)");
+                trace!(self, "RES 4, (HL)");
                 let value = self.res(
                     4,
                     self.mmu.read_byte(
@@ -3305,49 +4689,49 @@ impl CPU {
             },
             // RES 4, A
             0xA7 => {
-                println!("RES 4, A");
+                trace!(self, "RES 4, A");
                 self.registers.a = self.res(4, self.registers.a);
                 8
             },
             // RES 5, B
             0xA8 => {
-                println!("RES 5, B");
+                trace!(self, "RES 5, B");
                 self.registers.b = self.res(5, self.registers.b);
                 8
             },
             // RES 5, C
             0xA9 => {
-                println!("RES 5, C");
+                trace!(self, "RES 5, C");
                 self.registers.c = self.res(5, self.registers.c);
                 8
             },
             // RES 5, D
             0xAA => {
-                println!("RES 5, D");
+                trace!(self, "RES 5, D");
                 self.registers.d = self.res(5, self.registers.d);
                 8
             },
             // RES 5, E
             0xAB => {
-                println!("RES 5, E");
+                trace!(self, "RES 5, E");
                 self.registers.e = self.res(5, self.registers.e);
                 8
             },
             // RES 5, H
             0xAC => {
-                println!("RES 5, H");
+                trace!(self, "RES 5, H");
                 self.registers.h = self.res(5, self.registers.h);
                 8
             },
             // RES 5, L
             0xAD => {
-                println!("RES 5, L");
+                trace!(self, "RES 5, L");
                 self.registers.l = self.res(5, self.registers.l);
                 8
             },
             // RES 5, (HL)
             0xAE => {
-                println!("RES 5, (HL)");
+                trace!(self, "RES 5, (HL)");
                 let value = self.res(
                     5,
                     self.mmu.read_byte(
@@ -3362,49 +4746,49 @@ impl CPU {
             },
             // RES 5, A
             0xAF => {
-                println!("RES 5, A");
+                trace!(self, "RES 5, A");
                 self.registers.a = self.res(5, self.registers.a);
                 8
             },
             // RES 6, B
             0xB0 => {
-                println!("RES 6, B");
+                trace!(self, "RES 6, B");
                 self.registers.b = self.res(6, self.registers.b);
                 8
             },
             // RES 6, C
             0xB1 => {
-                println!("RES 6, C");
+                trace!(self, "RES 6, C");
                 self.registers.c = self.res(6, self.registers.c);
                 8
             },
             // RES 6, D
             0xB2 => {
-                println!("RES 6, D");
+                trace!(self, "RES 6, D");
                 self.registers.d = self.res(6, self.registers.d);
                 8
             },
             // RES 6, E
             0xB3 => {
-                println!("RES 6, E");
+                trace!(self, "RES 6, E");
                 self.registers.e = self.res(6, self.registers.e);
                 8
             },
             // RES 6, H
             0xB4 => {
-                println!("RES 6, H");
+                trace!(self, "RES 6, H");
                 self.registers.h = self.res(6, self.registers.h);
                 8
             },
             // RES 6, L
             0xB5 => {
-                println!("RES 6, L");
+                trace!(self, "RES 6, L");
                 self.registers.l = self.res(6, self.registers.l);
                 8
             },
             // RES 6, (HL)
             0xB6 => {
-                println!("RES 6, (HL)");
+                trace!(self, "RES 6, (HL)");
                 let value = self.res(
                     6,
                     self.mmu.read_byte(
@@ -3419,49 +4803,49 @@ impl CPU {
             },
             // RES 6, A
             0xB7 => {
-                println!("RES 6, A");
+                trace!(self, "RES 6, A");
                 self.registers.a = self.res(6, self.registers.a);
                 8
             },
             // RES 7, B
             0xB8 => {
-                println!("RES 7, B");
+                trace!(self, "RES 7, B");
                 self.registers.b = self.res(7, self.registers.b);
                 8
             },
             // RES 7, C
             0xB9 => {
-                println!("RES 7, C");
+                trace!(self, "RES 7, C");
                 self.registers.c = self.res(7, self.registers.c);
                 8
             },
             // RES 7, D
             0xBA => {
-                println!("RES 7, D");
+                trace!(self, "RES 7, D");
                 self.registers.d = self.res(7, self.registers.d);
                 8
             },
             // RES 7, E
             0xBB => {
-                println!("RES 7, E");
+                trace!(self, "RES 7, E");
                 self.registers.e = self.res(7, self.registers.e);
                 8
             },
             // RES 7, H
             0xBC => {
-                println!("RES 7, H");
+                trace!(self, "RES 7, H");
                 self.registers.h = self.res(7, self.registers.h);
                 8
             },
             // RES 7, L
             0xBD => {
-                println!("RES 7, L");
+                trace!(self, "RES 7, L");
                 self.registers.l = self.res(7, self.registers.l);
                 8
             },
             // RES 7, (HL)
             0xBE => {
-                println!("RES 7, (HL)");
+                trace!(self, "RES 7, (HL)");
                 let value = self.res(
                     7,
                     self.mmu.read_byte(
@@ -3476,49 +4860,49 @@ impl CPU {
             },
             // RES 7, A
             0xBF => {
-                println!("RES 7, A");
+                trace!(self, "RES 7, A");
                 self.registers.a = self.res(7, self.registers.a);
                 8
             },
             // SET 0, B
             0xC0 => {
-                println!("SET 0, B");
+                trace!(self, "SET 0, B");
                 self.registers.b = self.set(0, self.registers.b);
                 8
             },
             // SET 0, C
             0xC1 => {
-                println!("SET 0, C");
+                trace!(self, "SET 0, C");
                 self.registers.c = self.set(0, self.registers.c);
                 8
             },
             // SET 0, D
             0xC2 => {
-                println!("SET 0, D");
+                trace!(self, "SET 0, D");
                 self.registers.d = self.set(0, self.registers.d);
                 8
             },
             // SET 0, E
             0xC3 => {
-                println!("SET 0, E");
+                trace!(self, "SET 0, E");
                 self.registers.e = self.set(0, self.registers.e);
                 8
             },
             // SET 0, H
             0xC4 => {
-                println!("SET 0, H");
+                trace!(self, "SET 0, H");
                 self.registers.h = self.set(0, self.registers.h);
                 8
             },
             // SET 0, L
             0xC5 => {
-                println!("SET 0, L");
+                trace!(self, "SET 0, L");
                 self.registers.l = self.set(0, self.registers.l);
                 8
             },
             // SET 0, (HL)
             0xC6 => {
-                println!("SET 0, (HL)");
+                trace!(self, "SET 0, (HL)");
                 let value = self.set(
                     0,
                     self.mmu.read_byte(
@@ -3533,49 +4917,49 @@ impl CPU {
             },
             // SET 0, A
             0xC7 => {
-                println!("SET 0, A");
+                trace!(self, "SET 0, A");
                 self.registers.a = self.set(0, self.registers.a);
                 8
             },
             // SET 1, B
             0xC8 => {
-                println!("SET 1, B");
+                trace!(self, "SET 1, B");
                 self.registers.b = self.set(1, self.registers.b);
                 8
             },
             // SET 1, C
             0xC9 => {
-                println!("SET 1, C");
+                trace!(self, "SET 1, C");
                 self.registers.c = self.set(1, self.registers.c);
                 8
             },
             // SET 1, D
             0xCA => {
-                println!("SET 1, D");
+                trace!(self, "SET 1, D");
                 self.registers.d = self.set(1, self.registers.d);
                 8
             },
             // SET 1, E
             0xCB => {
-                println!("SET 1, E");
+                trace!(self, "SET 1, E");
                 self.registers.e = self.set(1, self.registers.e);
                 8
             },
             // SET 1, H
             0xCC => {
-                println!("SET 1, H");
+                trace!(self, "SET 1, H");
                 self.registers.h = self.set(1, self.registers.h);
                 8
             },
             // SET 1, L
             0xCD => {
-                println!("SET 1, L");
+                trace!(self, "SET 1, L");
                 self.registers.l = self.set(1, self.registers.l);
                 8
             },
             // SET 1, (HL)
             0xCE => {
-                println!("SET 1, (HL)");
+                trace!(self, "SET 1, (HL)");
                 let value = self.set(
                     1,
                     self.mmu.read_byte(
@@ -3590,49 +4974,49 @@ impl CPU {
             },
             // SET 1, A
             0xCF => {
-                println!("SET 1, A");
+                trace!(self, "SET 1, A");
                 self.registers.a = self.set(1, self.registers.a);
                 8
             },
             // SET 2, B
             0xD0 => {
-                println!("SET 2, B");
+                trace!(self, "SET 2, B");
                 self.registers.b = self.set(2, self.registers.b);
                 8
             },
             // SET 2, C
             0xD1 => {
-                println!("SET 2, C");
+                trace!(self, "SET 2, C");
                 self.registers.c = self.set(2, self.registers.c);
                 8
             },
             // SET 2, D
             0xD2 => {
-                println!("SET 2, D");
+                trace!(self, "SET 2, D");
                 self.registers.d = self.set(2, self.registers.d);
                 8
             },
             // SET 2, E
             0xD3 => {
-                println!("SET 2, E");
+                trace!(self, "SET 2, E");
                 self.registers.e = self.set(2, self.registers.e);
                 8
             },
             // SET 2, H
             0xD4 => {
-                println!("SET 2, H");
+                trace!(self, "SET 2, H");
                 self.registers.h = self.set(2, self.registers.h);
                 8
             },
             // SET 2, L
             0xD5 => {
-                println!("SET 2, L");
+                trace!(self, "SET 2, L");
                 self.registers.l = self.set(2, self.registers.l);
                 8
             },
             // SET 2, (HL)
             0xD6 => {
-                println!("SET 2, (HL)");
+                trace!(self, "SET 2, (HL)");
                 let value = self.set(
                     2,
                     self.mmu.read_byte(
@@ -3647,49 +5031,49 @@ impl CPU {
             },
             // SET 2, A
             0xD7 => {
-                println!("SET 2, A");
+                trace!(self, "SET 2, A");
                 self.registers.a = self.set(2, self.registers.a);
                 8
             },
             // SET 3, B
             0xD8 => {
-                println!("SET 3, B");
+                trace!(self, "SET 3, B");
                 self.registers.b = self.set(3, self.registers.b);
                 8
             },
             // SET 3, C
             0xD9 => {
-                println!("SET 3, C");
+                trace!(self, "SET 3, C");
                 self.registers.c = self.set(3, self.registers.c);
                 8
             },
             // SET 3, D
             0xDA => {
-                println!("SET 3, D");
+                trace!(self, "SET 3, D");
                 self.registers.d = self.set(3, self.registers.d);
                 8
             },
             // SET 3, E
             0xDB => {
-                println!("SET 3, E");
+                trace!(self, "SET 3, E");
                 self.registers.e = self.set(3, self.registers.e);
                 8
             },
             // SET 3, H
             0xDC => {
-                println!("SET 3, H");
+                trace!(self, "SET 3, H");
                 self.registers.h = self.set(3, self.registers.h);
                 8
             },
             // SET 3, L
             0xDD => {
-                println!("SET 3, L");
+                trace!(self, "SET 3, L");
                 self.registers.l = self.set(3, self.registers.l);
                 8
             },
             // SET 3, (HL)
             0xDE => {
-                println!("SET 3, (HL)");
+                trace!(self, "SET 3, (HL)");
                 let value = self.set(
                     3,
                     self.mmu.read_byte(
@@ -3704,49 +5088,49 @@ impl CPU {
             },
             // SET 3, A
             0xDF => {
-                println!("SET 3, A");
+                trace!(self, "SET 3, A");
                 self.registers.a = self.set(3, self.registers.a);
                 8
             },
             // SET 4, B
             0xE0 => {
-                println!("SET 4, B");
+                trace!(self, "SET 4, B");
                 self.registers.b = self.set(4, self.registers.b);
                 8
             },
             // SET 4, C
             0xE1 => {
-                println!("SET 4, C");
+                trace!(self, "SET 4, C");
                 self.registers.c = self.set(4, self.registers.c);
                 8
             },
             // SET 4, D
             0xE2 => {
-                println!("SET 4, D");
+                trace!(self, "SET 4, D");
                 self.registers.d = self.set(4, self.registers.d);
                 8
             },
             // SET 4, E
             0xE3 => {
-                println!("SET 4, E");
+                trace!(self, "SET 4, E");
                 self.registers.e = self.set(4, self.registers.e);
                 8
             },
             // SET 4, H
             0xE4 => {
-                println!("SET 4, H");
+                trace!(self, "SET 4, H");
                 self.registers.h = self.set(4, self.registers.h);
                 8
             },
             // SET 4, L
             0xE5 => {
-                println!("SET 4, L");
+                trace!(self, "SET 4, L");
                 self.registers.l = self.set(4, self.registers.l);
                 8
             },
             // SET 4, (HL)
             0xE6 => {
-                println!("SET 4, (HL)");
+                trace!(self, "SET 4, (HL)");
                 let value = self.set(
                     4,
                     self.mmu.read_byte(
@@ -3761,49 +5145,49 @@ impl CPU {
             },
             // SET 4, A
             0xE7 => {
-                println!("SET 4, A");
+                trace!(self, "SET 4, A");
                 self.registers.a = self.set(4, self.registers.a);
                 8
             },
             // SET 5, B
             0xE8 => {
-                println!("SET 5, B");
+                trace!(self, "SET 5, B");
                 self.registers.b = self.set(5, self.registers.b);
                 8
             },
             // SET 5, C
             0xE9 => {
-                println!("SET 5, C");
+                trace!(self, "SET 5, C");
                 self.registers.c = self.set(5, self.registers.c);
                 8
             },
             // SET 5, D
             0xEA => {
-                println!("SET 5, D");
+                trace!(self, "SET 5, D");
                 self.registers.d = self.set(5, self.registers.d);
                 8
             },
             // SET 5, E
             0xEB => {
-                println!("SET 5, E");
+                trace!(self, "SET 5, E");
                 self.registers.e = self.set(5, self.registers.e);
                 8
             },
             // SET 5, H
             0xEC => {
-                println!("SET 5, H");
+                trace!(self, "SET 5, H");
                 self.registers.h = self.set(5, self.registers.h);
                 8
             },
             // SET 5, L
             0xED => {
-                println!("SET 5, L");
+                trace!(self, "SET 5, L");
                 self.registers.l = self.set(5, self.registers.l);
                 8
             },
             // SET 5, (HL)
             0xEE => {
-                println!("SET 5, (HL)");
+                trace!(self, "SET 5, (HL)");
                 let value = self.set(
                     5,
                     self.mmu.read_byte(
@@ -3818,49 +5202,49 @@ impl CPU {
             },
             // SET 5, A
             0xEF => {
-                println!("SET 5, A");
+                trace!(self, "SET 5, A");
                 self.registers.a = self.set(5, self.registers.a);
                 8
             },
             // SET 6, B
             0xF0 => {
-                println!("SET 6, B");
+                trace!(self, "SET 6, B");
                 self.registers.b = self.set(6, self.registers.b);
                 8
             },
             // SET 6, C
             0xF1 => {
-                println!("SET 6, C");
+                trace!(self, "SET 6, C");
                 self.registers.c = self.set(6, self.registers.c);
                 8
             },
             // SET 6, D
             0xF2 => {
-                println!("SET 6, D");
+                trace!(self, "SET 6, D");
                 self.registers.d = self.set(6, self.registers.d);
                 8
             },
             // SET 6, E
             0xF3 => {
-                println!("SET 6, E");
+                trace!(self, "SET 6, E");
                 self.registers.e = self.set(6, self.registers.e);
                 8
             },
             // SET 6, H
             0xF4 => {
-                println!("SET 6, H");
+                trace!(self, "SET 6, H");
                 self.registers.h = self.set(6, self.registers.h);
                 8
             },
             // SET 6, L
             0xF5 => {
-                println!("SET 6, L");
+                trace!(self, "SET 6, L");
                 self.registers.l = self.set(6, self.registers.l);
                 8
             },
             // SET 6, (HL)
             0xF6 => {
-                println!("SET 6, (HL)");
+                trace!(self, "SET 6, (HL)");
                 let value = self.set(
                     6,
                     self.mmu.read_byte(
@@ -3875,49 +5259,49 @@ impl CPU {
             },
             // SET 6, A
             0xF7 => {
-                println!("SET 6, A");
+                trace!(self, "SET 6, A");
                 self.registers.a = self.set(6, self.registers.a);
                 8
             },
             // SET 7, B
             0xF8 => {
-                println!("SET 7, B");
+                trace!(self, "SET 7, B");
                 self.registers.b = self.set(7, self.registers.b);
                 8
             },
             // SET 7, C
             0xF9 => {
-                println!("SET 7, C");
+                trace!(self, "SET 7, C");
                 self.registers.c = self.set(7, self.registers.c);
                 8
             },
             // SET 7, D
             0xFA => {
-                println!("SET 7, D");
+                trace!(self, "SET 7, D");
                 self.registers.d = self.set(7, self.registers.d);
                 8
             },
             // SET 7, E
             0xFB => {
-                println!("SET 7, E");
+                trace!(self, "SET 7, E");
                 self.registers.e = self.set(7, self.registers.e);
                 8
             },
             // SET 7, H
             0xFC => {
-                println!("SET 7, H");
+                trace!(self, "SET 7, H");
                 self.registers.h = self.set(7, self.registers.h);
                 8
             },
             // SET 7, L
             0xFD => {
-                println!("SET 7, L");
+                trace!(self, "SET 7, L");
                 self.registers.l = self.set(7, self.registers.l);
                 8
             },
             // SET 7, (HL)
             0xFE => {
-                println!("SET 7, (HL)");
+                trace!(self, "SET 7, (HL)");
                 let value = self.set(
                     7,
                     self.mmu.read_byte(
@@ -3932,7 +5316,7 @@ impl CPU {
             },
             // SET 7, A
             0xFF => {
-                println!("SET 7, A");
+                trace!(self, "SET 7, A");
                 self.registers.a = self.set(7, self.registers.a);
                 8
             },
@@ -3976,49 +5360,26 @@ impl CPU {
     /// **u32**: Number of cycles used to handle interruptions (0 if not
     /// handled).
     fn manage_interruptions(&mut self) -> u32 {
-        if self.ime {
-            // if io.pending_joypad_interruption
-            if 
-                self.mmu.interrupt_flag & 0x10 == 0x10 &&
-                self.mmu.ie & 0x10 == 0x10
-            {
-                self.mmu.interrupt_flag |= 0xEF;
-                self.mmu.ie |= 0xEF;
-                // 2 NOP + PUSH PC
-                self.rst(0x0060);
-                return 20;
-            }
-            // if io.pending_timer_interruption
-            if 
-                self.mmu.interrupt_flag & 0x04 == 0x04 &&
-                self.mmu.ie & 0x04 == 0x04
-            {
-                self.mmu.interrupt_flag |= 0xFB;
-                self.mmu.ie |= 0xFB;
-                // 2 NOP + PUSH PC + LD PC 0x50
-                self.rst(0x0050);
-                return 20;
-            }
-            // if gpu.pending_stat_interrupt
-            if 
-                self.mmu.interrupt_flag & 0x02 == 0x02 &&
-                self.mmu.ie & 0x02 == 0x02
-            {
-                self.mmu.interrupt_flag |= 0xFD;
-                self.mmu.ie |= 0xFD;
-                // 2 NOP + PUSH PC + LD PC 0x50
-                self.rst(0x0048);
-                return 20;
-            }
-            // if gpu.pending_vblank_interrupt
-            if 
-                self.mmu.interrupt_flag & 0x01 == 0x01 &&
-                self.mmu.ie & 0x01 == 0x01
-            {
-                self.mmu.interrupt_flag |= 0xFE;
-                self.mmu.ie |= 0xFE;
-                // 2 NOP + PUSH PC + LD PC 0x50
-                self.rst(0x0040);
+        // DI prevents the interruptions from being handled right away
+        if !self.ime || self.di != 0 {
+            return 0;
+        }
+        // From the highest to the lowest priority: VBlank, STAT, timer,
+        // serial and joypad
+        for (bit, handler) in [
+            (0x01, 0x0040),
+            (0x02, 0x0048),
+            (0x04, 0x0050),
+            (0x08, 0x0058),
+            (0x10, 0x0060),
+        ] {
+            let requested = self.interrupt_flag();
+            if requested & self.interrupt_enable() & bit == bit {
+                self.set_interrupt_flag(requested & !bit);
+                self.ime = false;
+                self.is_halted = false;
+                // 2 NOP + PUSH PC + LD PC handler
+                self.rst(handler);
                 return 20;
             }
         }
@@ -4034,16 +5395,53 @@ impl CPU {
     /// # Returns
     /// **u32**: Number of CPU cycles used for the step
     pub fn execute_step(&mut self) -> u32 {
+        let res = self.step();
+        self.total_cycles += res as u64;
+        if self.is_double_speed() {
+            self.double_speed_cycles += res as u64;
+        }
+        #[cfg(feature = "recording")]
+        if let Some(cpu) = self.gameboy_mut() {
+            cpu.record_frame();
+        }
+        res
+    }
+
+    /// Handles the interruptions or executes one instruction
+    ///
+    /// # Returns
+    /// **u32**: Number of CPU cycles used for the step
+    fn step(&mut self) -> u32 {
         self.update_ime();
         let time_interruption = self.manage_interruptions();
         if time_interruption != 0 {
             self.should_stop = self.mmu.update(time_interruption);
             return time_interruption;
         }
-        if self.is_halted {
+        if self.is_stopped {
             self.should_stop = self.mmu.update(4);
+            if let Some(cpu) = self.gameboy_mut() {
+                if cpu.mmu.is_button_pressed() {
+                    cpu.is_stopped = false;
+                    cpu.mmu.resume();
+                }
+            }
             return 4;
         }
+        if self.is_halted {
+            // A pending interruption ends the HALT even if it is not handled
+            if !self.has_pending_interruption() {
+                self.should_stop = self.mmu.update(4);
+                return 4;
+            }
+            self.is_halted = false;
+        }
+        if self.is_strict && (0xFEA0..=0xFEFF).contains(&self.registers.pc) {
+            panic!(
+                "Execution in the prohibited area at {:#06x}",
+                self.registers.pc
+            );
+        }
         let res = self.receive_op();
         self.should_stop = self.mmu.update(res);
         res
@@ -4052,9 +5450,9 @@ impl CPU {
     /// Returns the given value incremented
     ///
     /// Sets the Z flag iff the result is zero  
-    /// Sets the H flag iff the lower nybble has a carry
-    /// Always resets the N flag
-    /// Does not affect the C flag
+    /// Sets the H flag iff the lower nybble has a carry  
+    /// Always resets the N flag  
+    /// Does not affect the C flag  
     ///
     /// # Arguments
     /// **value (u8)**: Value to increment
@@ -4081,8 +5479,8 @@ impl CPU {
     ///
     /// Sets the Z flag iff the result is zero  
     /// Sets the H flag iff the lower nybble takes a borrow  
-    /// Always sets the N flag
-    /// Does not affect the C flag
+    /// Always sets the N flag  
+    /// Does not affect the C flag  
     ///
     /// # Arguments
     /// **value (u8)**: Value to decrement
@@ -4736,12 +6134,12 @@ impl CPU {
     /// it was done with two decimal numbers.  
     /// Substracts 6 to the upper or lower nybble depending of some criteria:  
     /// - was the previous operation a substraction (N flag) -> correction by
-    /// addition or substraction,  
+    ///   addition or substraction,  
     /// - did an overflow occur (C flag) -> adjust the upper nybble,  
     /// - did an overflow occur on the first nible (H flab) -> adjust the lower
-    /// nybble,  
+    ///   nybble,  
     /// - is one of the nybble's value greater than 9 (addition only) ->
-    /// correction of this nybble.
+    ///   correction of this nybble.
     ///
     /// Sets the Z flag iff the result is zero  
     /// Does not affect the N flag  
@@ -4769,52 +6167,129 @@ impl CPU {
             }
         }
         self.registers.set_half(false);
-        self.registers.set_zero(a == 0);
-        self.registers.a = a;
+        self.registers.set_zero(a == 0);
+        self.registers.a = a;
+    }
+
+    /// Sets the carry flag
+    ///
+    /// Does not affect the Z flag  
+    /// Always resets the N and H flags  
+    /// Always sets the carry flag  
+    fn scf(&mut self) {
+        self.registers.set_sub(false);
+        self.registers.set_half(false);
+        self.registers.set_carry(true);
+    }
+
+    /// Complements the carry flag
+    ///
+    /// Does not affect the Z flag  
+    /// Always resets the N and H flags  
+    /// Toggles the carry flag  
+    fn ccf(&mut self) {
+        self.registers.set_sub(false);
+        self.registers.set_half(false);
+        self.registers.set_carry(!self.registers.get_carry());
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::state::model::Model;
+
+    /// Memory made of 64 KiB of RAM, without any component behind it
+    struct FlatRam(Vec<u8>);
+
+    impl MemoryBus for FlatRam {
+        fn read_byte(&self, address: u16) -> u8 {
+            self.0[address as usize]
+        }
+
+        fn write_byte(&mut self, address: u16, value: u8) {
+            self.0[address as usize] = value;
+        }
+
+        fn update(&mut self, _n_cycles: u32) -> bool {
+            false
+        }
+    }
 
     #[test]
     fn test_pop() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.push(0x1234);
         assert_eq!(new_cpu.pop(), 0x1234);
     }
 
+    #[test]
+    fn test_with_bus() {
+        let mut ram = vec![0x00; 0x10000];
+        // LD A,0x12 ; ADD A,0x34 ; LD (0xC000),A ; SUB A,0x46
+        ram[0x0100..0x010A].copy_from_slice(
+            &[0x3E, 0x12, 0xC6, 0x34, 0xEA, 0x00, 0xC0, 0xD6, 0x46, 0x00]
+        );
+        let mut new_cpu = CPU::with_bus(FlatRam(ram));
+        new_cpu.set_pc(0x0100);
+        for _ in 0..4 {
+            new_cpu.execute_step();
+        }
+        assert_eq!(new_cpu.mmu.read_byte(0xC000), 0x46);
+        assert_eq!(new_cpu.reg_a(), 0x00);
+        assert_eq!(new_cpu.reg_pc(), 0x0109);
+        assert_eq!(
+            new_cpu.flags(),
+            Flags{z: true, n: true, h: false, c: false}
+        );
+    }
+
+    #[test]
+    fn test_fetchword() {
+        // Only compiles if fetchword returns a u16, unlike the one of the
+        // legacy src/cpu module
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_pc(0xC000);
+        new_cpu.set_memory_bytes(&[(0xC000, 0x34), (0xC001, 0x12)]);
+        let word: u16 = new_cpu.fetchword();
+        assert_eq!(word, 0x1234);
+        assert_eq!(new_cpu.reg_pc(), 0xC002);
+    }
+
     #[test]
     fn test_push() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.push(0x1234);
         assert_eq!(new_cpu.pop(), 0x1234);
     }
 
     #[test]
     fn test_rst() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.rst(0x0060);
         assert_eq!(new_cpu.registers.pc, 0x0060);
     }
 
     #[test]
     fn test_jr() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        // The ROM is read-only, the operand is written in the WRAM
+        new_cpu.registers.pc = 0xC000;
         let before = new_cpu.registers.pc;
-        // Normally the adress should already be written in cartridges/Tetris.gb
         new_cpu.mmu.write_byte(
             new_cpu.registers.pc,
             0x12
         );
         new_cpu.jr();
-        assert_eq!(new_cpu.registers.pc - before, 0x12);
+        // The jump is relative to the address following the operand
+        assert_eq!(new_cpu.registers.pc - before, 0x13);
     }
 
     #[test]
     fn test_update_ime() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.di = 2;
         // ime is not deactivated after one update
         new_cpu.update_ime();
@@ -4826,7 +6301,7 @@ mod tests {
 
     #[test]
     fn test_manage_interruptions() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         // Artificially create a joypad interruption
         new_cpu.mmu.interrupt_flag = 0x10;
         new_cpu.mmu.ie = 0x10;
@@ -4836,21 +6311,116 @@ mod tests {
         assert_eq!(new_cpu.registers.pc, 0x0060);
     }
 
+    #[test]
+    fn test_di_halt_bug() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // DI, HALT, INC A
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0xF3), (0xC001, 0x76), (0xC002, 0x3C),
+        ]);
+        new_cpu.execute_step();
+        // Timer interruption requested after DI
+        new_cpu.mmu.interrupt_flag = 0x04;
+        new_cpu.mmu.ie = 0x04;
+        new_cpu.execute_step();
+        assert!(!new_cpu.is_halted);
+        // INC A is read twice
+        new_cpu.execute_step();
+        assert_eq!(new_cpu.registers.pc, 0xC002);
+        new_cpu.execute_step();
+        assert_eq!(new_cpu.registers.pc, 0xC003);
+        assert_eq!(new_cpu.registers.a, 0x02);
+        // The interruption was not handled
+        assert!(!new_cpu.ime);
+        assert_eq!(new_cpu.mmu.interrupt_flag & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_stop_until_button_pressed() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // STOP, INC A
+        new_cpu.set_memory_bytes(&[(0xC000, 0x10), (0xC001, 0x3C)]);
+        // Select the action buttons
+        new_cpu.mmu.write_byte(0xFF00, 0x10);
+        new_cpu.execute_step();
+        assert!(new_cpu.is_stopped());
+        for _ in 0..10 {
+            assert_eq!(new_cpu.execute_step(), 4);
+        }
+        assert_eq!(new_cpu.registers.pc, 0xC001);
+        let mut keys = KeyState::new();
+        keys.is_a_pressed = true;
+        new_cpu.set_buttons(keys);
+        new_cpu.execute_step();
+        assert!(!new_cpu.is_stopped);
+        new_cpu.execute_step();
+        assert_eq!(new_cpu.registers.a, 0x01);
+    }
+
+    #[test]
+    fn test_buttons_for() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // Select the action buttons
+        new_cpu.set_memory_bytes(&[(0xFF00, 0x10)]);
+        let mut keys = KeyState::new();
+        keys.is_a_pressed = true;
+        new_cpu.set_buttons_for(1, keys);
+        new_cpu.execute_step();
+        assert!(new_cpu.buttons_for(1).unwrap().is_a_pressed);
+        assert!(!new_cpu.buttons_for(0).unwrap().is_a_pressed);
+        // The game only reads the buttons of the player 0
+        assert_eq!(new_cpu.mmu.read_byte(0xFF00) & 0x0F, 0x0F);
+        let mut keys = KeyState::new();
+        keys.is_b_pressed = true;
+        new_cpu.set_buttons(keys);
+        new_cpu.execute_step();
+        assert!(new_cpu.buttons_for(1).unwrap().is_a_pressed);
+        assert!(!new_cpu.buttons_for(1).unwrap().is_b_pressed);
+        assert_eq!(new_cpu.mmu.read_byte(0xFF00) & 0x0F, 0x0D);
+        // There is no third player
+        new_cpu.set_buttons_for(2, KeyState::new());
+        assert!(new_cpu.buttons_for(2).is_none());
+        assert!(new_cpu.buttons_for(usize::MAX).is_none());
+    }
+
     #[test]
     fn test_inc() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         assert_eq!(new_cpu.inc(0x01), 0x02);
+        new_cpu.registers.set_sub(true);
+        assert_eq!(new_cpu.inc(0x0F), 0x10);
+        assert!(new_cpu.registers.get_half());
+        assert!(!new_cpu.registers.get_sub());
     }
 
     #[test]
     fn test_dec() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         assert_eq!(new_cpu.dec(0x02), 0x01);
+        assert_eq!(new_cpu.dec(0x10), 0x0F);
+        // Borrow from the bit 4
+        assert!(new_cpu.registers.get_half());
+        assert!(new_cpu.registers.get_sub());
     }
 
     #[test]
     fn test_add() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x12;
         new_cpu.add(0x34);
         // 0x12 + 0x34 = 0x46
@@ -4859,7 +6429,7 @@ mod tests {
 
     #[test]
     fn test_adc() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x12;
         new_cpu.registers.set_carry(true);
         new_cpu.adc(0x34);
@@ -4869,7 +6439,7 @@ mod tests {
 
     #[test]
     fn test_addhl() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.set_hl(0x3412);
         new_cpu.addhl(0x369C);
         // 0x369C + 0x3412 = 0x6AAE
@@ -4878,7 +6448,9 @@ mod tests {
 
     #[test]
     fn test_addr8() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        // The ROM is read-only, the operand is written in the WRAM
+        new_cpu.registers.pc = 0xC000;
         new_cpu.mmu.write_byte(new_cpu.registers.pc, 0x34);
         // 0x369C + 0x0034 = 0x36D0
         assert_eq!(new_cpu.addr8(0x369C), 0x36D0);
@@ -4886,7 +6458,7 @@ mod tests {
 
     #[test]
     fn test_sub() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x12;
         new_cpu.sub(0x03);
         // 0x12 - 0x03 = 0x0F
@@ -4895,7 +6467,7 @@ mod tests {
 
     #[test]
     fn test_sbc() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x12;
         new_cpu.registers.set_carry(true);
         new_cpu.sbc(0x03);
@@ -4905,16 +6477,102 @@ mod tests {
 
     #[test]
     fn test_cp() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x12;
         new_cpu.cp(0x15);
         // 0x12 < 0x15
         assert!(new_cpu.registers.get_carry());
     }
 
+    #[test]
+    fn test_cp_flags() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        // A == value
+        new_cpu.registers.a = 0x42;
+        new_cpu.cp(0x42);
+        assert_eq!(new_cpu.registers.a, 0x42);
+        assert!(new_cpu.registers.get_zero());
+        assert!(new_cpu.registers.get_sub());
+        assert!(!new_cpu.registers.get_carry());
+        assert!(!new_cpu.registers.get_half());
+        // A < value, without borrow from the low nibble
+        new_cpu.cp(0x51);
+        assert_eq!(new_cpu.registers.a, 0x42);
+        assert!(!new_cpu.registers.get_zero());
+        assert!(new_cpu.registers.get_carry());
+        assert!(!new_cpu.registers.get_half());
+        // Low nibble of A < low nibble of value, A > value
+        new_cpu.cp(0x38);
+        assert_eq!(new_cpu.registers.a, 0x42);
+        assert!(!new_cpu.registers.get_carry());
+        assert!(new_cpu.registers.get_half());
+        // SUB sets the same flags but stores the result
+        new_cpu.sub(0x38);
+        assert_eq!(new_cpu.registers.a, 0x0A);
+        assert!(new_cpu.registers.get_sub());
+        assert!(!new_cpu.registers.get_carry());
+        assert!(new_cpu.registers.get_half());
+    }
+
+    #[test]
+    fn test_accumulator_rotations_clear_zero() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        // RLCA, RRCA, RLA, RRA, RLC A
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x07),
+            (0xC001, 0x0F),
+            (0xC002, 0x17),
+            (0xC003, 0x1F),
+            (0xC004, 0xCB),
+            (0xC005, 0x07),
+        ]);
+        new_cpu.set_pc(0xC000);
+        for _ in 0..4 {
+            new_cpu.registers.a = 0x00;
+            // Z, N and H set, C clear
+            new_cpu.registers.f = 0xE0;
+            new_cpu.execute_step();
+            assert_eq!(new_cpu.registers.a, 0x00);
+            assert!(!new_cpu.registers.get_zero());
+            assert!(!new_cpu.registers.get_carry());
+            assert!(!new_cpu.registers.get_sub());
+            assert!(!new_cpu.registers.get_half());
+        }
+        // The prefixed rotation sets Z on a null result
+        new_cpu.registers.a = 0x00;
+        new_cpu.registers.f = 0x00;
+        new_cpu.execute_step();
+        assert_eq!(new_cpu.registers.a, 0x00);
+        assert!(new_cpu.registers.get_zero());
+        assert!(!new_cpu.registers.get_carry());
+    }
+
+    #[test]
+    fn test_pending_interrupts() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.mmu.interrupt_flag = 0x00;
+        new_cpu.mmu.ie = 0x1F;
+        assert_eq!(new_cpu.pending_interrupts(), Interrupts::default());
+        // Requested: VBlank, timer, joypad and the unused bits
+        new_cpu.mmu.interrupt_flag = 0xF5;
+        // Enabled: VBlank, STAT, timer, serial and the unused bit 5
+        new_cpu.mmu.ie = 0x2F;
+        let pending = new_cpu.pending_interrupts();
+        assert_eq!(
+            pending,
+            Interrupts {
+                vblank: true,
+                timer: true,
+                ..Interrupts::default()
+            }
+        );
+        assert_eq!(pending.bits(), 0x05);
+        assert_eq!(Interrupts::from_bits(0xFF).bits(), 0x1F);
+    }
+
     #[test]
     fn test_and() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b01110110;
         new_cpu.and(0b01001011);
         assert_eq!(new_cpu.registers.a, 0b01000010);
@@ -4922,7 +6580,7 @@ mod tests {
 
     #[test]
     fn test_or() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b01110110;
         new_cpu.or(0b01001011);
         assert_eq!(new_cpu.registers.a, 0b01111111);
@@ -4930,7 +6588,7 @@ mod tests {
 
     #[test]
     fn test_xor() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b01110110;
         new_cpu.xor(0b01001011);
         assert_eq!(new_cpu.registers.a, 0b00111101);
@@ -4938,7 +6596,7 @@ mod tests {
 
     #[test]
     fn test_rl() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b10010011;
         assert!(!new_cpu.registers.get_carry());
         let res = new_cpu.rl(new_cpu.registers.a);
@@ -4947,7 +6605,7 @@ mod tests {
 
     #[test]
     fn test_rlc() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b10010011;
         let res = new_cpu.rlc(new_cpu.registers.a);
         assert_eq!(res, 0b00100111);
@@ -4955,7 +6613,7 @@ mod tests {
 
     #[test]
     fn test_rr() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b10010011;
         // The carry flag is initially reset
         assert!(!new_cpu.registers.get_carry());
@@ -4965,7 +6623,7 @@ mod tests {
 
     #[test]
     fn test_rrc() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b10010011;
         let res = new_cpu.rrc(new_cpu.registers.a);
         assert_eq!(res, 0b11001001);
@@ -4973,7 +6631,7 @@ mod tests {
 
     #[test]
     fn test_sla() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b10010011;
         let res = new_cpu.sla(new_cpu.registers.a);
         assert_eq!(res, 0b00100111);
@@ -4981,7 +6639,7 @@ mod tests {
 
     #[test]
     fn test_sra() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b10010010;
         let res = new_cpu.sra(new_cpu.registers.a);
         assert_eq!(res, 0b11001001);
@@ -4989,7 +6647,7 @@ mod tests {
 
     #[test]
     fn test_srl() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0b10010010;
         let res = new_cpu.srl(new_cpu.registers.a);
         assert_eq!(res, 0b01001001);
@@ -4997,7 +6655,7 @@ mod tests {
 
     #[test]
     fn test_swap() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x12;
         let res = new_cpu.swap(new_cpu.registers.a);
         assert_eq!(res, 0x21);
@@ -5005,7 +6663,7 @@ mod tests {
 
     #[test]
     fn test_bit() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         assert!(!new_cpu.registers.get_zero());
         new_cpu.registers.a = 0x02;
         new_cpu.bit(1, new_cpu.registers.a);
@@ -5014,7 +6672,7 @@ mod tests {
 
     #[test]
     fn test_res() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x04;
         let res = new_cpu.res(2, new_cpu.registers.a);
         assert_eq!(res, 0x00);
@@ -5022,7 +6680,7 @@ mod tests {
 
     #[test]
     fn test_set() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         // Initially the A registers contains the value 1
         assert_eq!(new_cpu.registers.a, 0x01);
         let res = new_cpu.set(2, new_cpu.registers.a);
@@ -5031,7 +6689,7 @@ mod tests {
 
     #[test]
     fn test_daa() {
-        let mut new_cpu = CPU::new("cartridges/Tetris.gb");
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
         new_cpu.registers.a = 0x92;
         new_cpu.registers.b = 0x36;
         new_cpu.add(new_cpu.registers.b);
@@ -5041,4 +6699,1168 @@ mod tests {
         new_cpu.daa();
         assert_eq!(new_cpu.registers.a, 0x28);
     }
+
+    #[test]
+    fn test_scf() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.registers.set_sub(true);
+        new_cpu.registers.set_half(true);
+        new_cpu.registers.set_carry(false);
+        new_cpu.scf();
+        assert!(!new_cpu.registers.get_sub());
+        assert!(!new_cpu.registers.get_half());
+        assert!(new_cpu.registers.get_carry());
+    }
+
+    #[test]
+    fn test_ccf() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.registers.set_sub(true);
+        new_cpu.registers.set_half(true);
+        new_cpu.registers.set_carry(false);
+        new_cpu.ccf();
+        assert!(!new_cpu.registers.get_sub());
+        assert!(!new_cpu.registers.get_half());
+        assert!(new_cpu.registers.get_carry());
+        new_cpu.ccf();
+        assert!(!new_cpu.registers.get_carry());
+    }
+
+    #[test]
+    fn test_run_frame_embedded() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        assert!(!new_cpu.is_tracing);
+        assert_eq!(
+            new_cpu.sleep_duration(CYCLES_PER_FRAME, Duration::ZERO),
+            Duration::ZERO
+        );
+        assert!(new_cpu.run_frame() >= CYCLES_PER_FRAME);
+    }
+
+    /// Audio output whose buffer is filled and played by the test
+    struct TestSink {
+        samples: Rc<RefCell<Vec<f32>>>,
+    }
+
+    impl AudioSink for TestSink {
+        fn queue(&mut self, samples: &[f32]) {
+            self.samples.borrow_mut().extend_from_slice(samples);
+        }
+
+        fn buffered(&self) -> usize {
+            self.samples.borrow().len()
+        }
+
+        fn capacity(&self) -> usize {
+            4096
+        }
+    }
+
+    #[test]
+    fn test_pace_step() {
+        let mut new_cpu = CPU::with_mmu(
+            Mmu::headless("cartridges/Tetris.gb"),
+            false
+        );
+        let elapsed = Duration::from_micros(100);
+        let step = new_cpu.sleep_duration(CYCLES_PER_FRAME, Duration::ZERO);
+        // Without sink, the audio mode paces like the video mode
+        new_cpu.set_sync_mode(SyncMode::Audio);
+        new_cpu.mmu.update(CYCLES_PER_FRAME);
+        assert_eq!(
+            new_cpu.pace_step(CYCLES_PER_FRAME, elapsed),
+            step - elapsed
+        );
+        assert!(!new_cpu.audio_samples().is_empty());
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        new_cpu.set_audio_sink(
+            Box::new(TestSink { samples: Rc::clone(&samples) })
+        );
+        // The samples of each step go to the sink, which is almost empty
+        new_cpu.mmu.update(CYCLES_PER_FRAME / 4);
+        assert_eq!(
+            new_cpu.pace_step(CYCLES_PER_FRAME, elapsed),
+            Duration::ZERO
+        );
+        assert!(!samples.borrow().is_empty());
+        assert!(new_cpu.audio_samples().is_empty());
+        // The samples are produced faster than the sink plays them
+        let mut n_steps = 1;
+        while new_cpu.pace_step(CYCLES_PER_FRAME, elapsed) != step {
+            new_cpu.mmu.update(CYCLES_PER_FRAME);
+            n_steps += 1;
+            assert!(n_steps < 10);
+        }
+        assert!(samples.borrow().len() * 4 > 4096 * 3);
+        // The sink plays half of its buffer
+        samples.borrow_mut().truncate(2048);
+        assert_eq!(
+            new_cpu.pace_step(CYCLES_PER_FRAME, elapsed),
+            step - elapsed
+        );
+        // The sink plays everything, the video mode ignores its buffer
+        samples.borrow_mut().clear();
+        new_cpu.set_sync_mode(SyncMode::Video);
+        new_cpu.mmu.update(CYCLES_PER_FRAME);
+        assert_eq!(
+            new_cpu.pace_step(CYCLES_PER_FRAME, elapsed),
+            step - elapsed
+        );
+        assert!(!samples.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_registers_vector() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            a: 0x0F,
+            f: 0x50,
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // INC A
+        new_cpu.set_memory_bytes(&[(0xC000, 0x3C)]);
+        new_cpu.execute_step();
+        let registers = new_cpu.registers();
+        assert_eq!(registers.a, 0x10);
+        assert_eq!(registers.pc, 0xC001);
+        // Z and N reset, H set, C unchanged
+        assert_eq!(registers.f, 0x30);
+    }
+
+    #[test]
+    fn test_cb_cycles() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            h: 0xC1,
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // RLC B, RLC (HL), BIT 0, (HL)
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0xCB), (0xC001, 0x00),
+            (0xC002, 0xCB), (0xC003, 0x06),
+            (0xC004, 0xCB), (0xC005, 0x46),
+        ]);
+        assert_eq!(new_cpu.execute_step(), 8);
+        assert_eq!(new_cpu.execute_step(), 16);
+        assert_eq!(new_cpu.execute_step(), 12);
+        assert_eq!(new_cpu.registers().pc, 0xC006);
+    }
+
+    #[test]
+    fn test_ld_c_high_ram() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            a: 0x42,
+            c: 0x80,
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // LD (C), A, LD C, 0x00, LD A, (C)
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0xE2),
+            (0xC001, 0x0E), (0xC002, 0x00),
+            (0xC003, 0xF2),
+        ]);
+        // Select the action buttons and press A
+        new_cpu.mmu.write_byte(0xFF00, 0x10);
+        let mut keys = KeyState::new();
+        keys.is_a_pressed = true;
+        new_cpu.set_buttons(keys);
+        // C = 0x80 writes in the HRAM
+        assert_eq!(new_cpu.execute_step(), 8);
+        assert_eq!(new_cpu.mmu.read_byte(0xFF80), 0x42);
+        new_cpu.execute_step();
+        // C = 0x00 reads the joypad register
+        assert_eq!(new_cpu.execute_step(), 8);
+        assert_eq!(new_cpu.registers().a, new_cpu.mmu.read_byte(0xFF00));
+        assert_eq!(new_cpu.registers().a & 0x3F, 0x1E);
+    }
+
+    #[test]
+    fn test_every_cb_opcode() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        for op in 0x00..=0xFF {
+            new_cpu.set_registers(RegistersSnapshot {
+                h: 0xC1,
+                pc: 0xC000,
+                sp: 0xFFFE,
+                ..RegistersSnapshot::default()
+            });
+            new_cpu.set_memory_bytes(&[(0xC000, 0xCB), (0xC001, op)]);
+            let expected = match (op & 0x07, op) {
+                (0x06, 0x40..=0x7F) => 12,
+                (0x06, _) => 16,
+                _ => 8,
+            };
+            assert_eq!(new_cpu.execute_step(), expected, "CB {:#04x}", op);
+            assert_eq!(new_cpu.registers().pc, 0xC002, "CB {:#04x}", op);
+        }
+    }
+
+    #[test]
+    fn test_register_accessors() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_reg_a(0x01);
+        new_cpu.set_reg_b(0x02);
+        new_cpu.set_reg_c(0x03);
+        new_cpu.set_reg_d(0x04);
+        new_cpu.set_reg_e(0x05);
+        new_cpu.set_reg_f(0x60);
+        new_cpu.set_reg_h(0x07);
+        new_cpu.set_reg_l(0x08);
+        new_cpu.set_reg_pc(0x1234);
+        new_cpu.set_reg_sp(0xFFF0);
+        assert_eq!(new_cpu.reg_a(), 0x01);
+        assert_eq!(new_cpu.reg_b(), 0x02);
+        assert_eq!(new_cpu.reg_c(), 0x03);
+        assert_eq!(new_cpu.reg_d(), 0x04);
+        assert_eq!(new_cpu.reg_e(), 0x05);
+        assert_eq!(new_cpu.reg_f(), 0x60);
+        assert_eq!(new_cpu.reg_h(), 0x07);
+        assert_eq!(new_cpu.reg_l(), 0x08);
+        assert_eq!(new_cpu.reg_pc(), 0x1234);
+        assert_eq!(new_cpu.reg_sp(), 0xFFF0);
+        assert_eq!(new_cpu.reg_af(), 0x0160);
+        assert_eq!(new_cpu.reg_bc(), 0x0203);
+        assert_eq!(new_cpu.reg_de(), 0x0405);
+        assert_eq!(new_cpu.reg_hl(), 0x0708);
+        new_cpu.set_reg_af(0xA0B0);
+        new_cpu.set_reg_bc(0xA1B1);
+        new_cpu.set_reg_de(0xA2B2);
+        new_cpu.set_reg_hl(0xA3B3);
+        assert_eq!(new_cpu.reg_af(), 0xA0B0);
+        assert_eq!(new_cpu.reg_bc(), 0xA1B1);
+        assert_eq!(new_cpu.reg_de(), 0xA2B2);
+        assert_eq!(new_cpu.reg_hl(), 0xA3B3);
+        assert_eq!(new_cpu.reg_a(), 0xA0);
+        assert_eq!(new_cpu.reg_l(), 0xB3);
+    }
+
+    #[test]
+    fn test_call_ret_interrupt() {
+        let path = std::env::temp_dir().join("gb_emulator_rust_test_flow.gb");
+        let mut rom = vec![0x00; 0x8000];
+        // Timer handler: RETI
+        rom[0x0050] = 0xD9;
+        // EI, CALL 0x0110, JR -2
+        rom[0x0100..0x0106]
+            .copy_from_slice(&[0xFB, 0xCD, 0x10, 0x01, 0x18, 0xFE]);
+        // RET
+        rom[0x0110] = 0xC9;
+        std::fs::write(&path, rom).unwrap();
+        let mut new_cpu = CPU::new_embedded(&path.display().to_string());
+        std::fs::remove_file(&path).unwrap();
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0x0100,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.ime = false;
+        // The timer overflows after 1024 cycles and requests an interruption
+        new_cpu.set_memory_bytes(&[
+            (0xFF05, 0xFF),
+            (0xFF06, 0x00),
+            (0xFF07, 0x04),
+            (0xFF0F, 0x00),
+            (0xFFFF, 0x04),
+        ]);
+        // EI
+        new_cpu.execute_step();
+        assert_eq!(new_cpu.registers().pc, 0x0101);
+        assert!(!new_cpu.ime);
+        // CALL 0x0110
+        assert_eq!(new_cpu.execute_step(), 24);
+        assert_eq!(new_cpu.registers().pc, 0x0110);
+        assert_eq!(new_cpu.registers().sp, 0xFFFC);
+        assert_eq!(new_cpu.mmu.read_word(0xFFFC), 0x0104);
+        // RET, IME is set one instruction after EI
+        assert_eq!(new_cpu.execute_step(), 16);
+        assert!(new_cpu.ime);
+        assert_eq!(new_cpu.registers().pc, 0x0104);
+        assert_eq!(new_cpu.registers().sp, 0xFFFE);
+        // JR -2 until the timer interruption is handled
+        let mut n_steps = 0;
+        while new_cpu.registers().pc != 0x0050 && n_steps < 200 {
+            new_cpu.execute_step();
+            n_steps += 1;
+        }
+        assert_eq!(new_cpu.registers().pc, 0x0050);
+        assert!(!new_cpu.ime);
+        assert_eq!(new_cpu.mmu.interrupt_flag & 0x04, 0x00);
+        assert_eq!(new_cpu.registers().sp, 0xFFFC);
+        assert_eq!(new_cpu.mmu.read_word(0xFFFC), 0x0104);
+        // RETI
+        assert_eq!(new_cpu.execute_step(), 16);
+        assert!(new_cpu.ime);
+        assert_eq!(new_cpu.registers().pc, 0x0104);
+        assert_eq!(new_cpu.registers().sp, 0xFFFE);
+    }
+
+    #[cfg(feature = "recording")]
+    #[test]
+    fn test_recording() {
+        let directory = std::env::temp_dir().join("gb_emulator_rust_test_rec");
+        let _ = std::fs::remove_dir_all(&directory);
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.start_recording(&directory.display().to_string()).unwrap();
+        let mut n_steps = 0;
+        while new_cpu.recorder.as_ref().unwrap().n_frames() < 2 &&
+            n_steps < 1_000_000 {
+            new_cpu.execute_step();
+            n_steps += 1;
+        }
+        assert_eq!(new_cpu.stop_recording(), 2);
+        let mut files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec![
+            directory.join("frame_00000.png"),
+            directory.join("frame_00001.png"),
+        ]);
+        for file in files {
+            assert_eq!(image::image_dimensions(file).unwrap(), (160, 144));
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_run_until_serial_contains() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // LD A, c, LDH (0x01), A, LD A, 0x81, LDH (0x02), A for each
+        // character, then JR -2
+        let mut program = vec![];
+        for c in "Passed".bytes() {
+            program.extend([0x3E, c, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+        }
+        program.extend([0x18, 0xFE]);
+        let bytes: Vec<(u16, u8)> = program
+            .into_iter()
+            .enumerate()
+            .map(|(i, byte)| (0xC000 + i as u16, byte))
+            .collect();
+        new_cpu.set_memory_bytes(&bytes);
+        assert!(!new_cpu.run_until_serial_contains("Pass", 100));
+        assert!(new_cpu.run_until_serial_contains("Pass", 10_000));
+        assert!(new_cpu.run_until_serial_contains("Passed", 10_000));
+        assert!(!new_cpu.run_until_serial_contains("Failed", 10_000));
+    }
+
+    /// Creates a CPU about to execute the illegal opcode 0xD3
+    fn cpu_before_illegal_opcode() -> CPU {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.set_memory_bytes(&[(0xC000, 0xD3)]);
+        new_cpu
+    }
+
+    #[test]
+    fn test_illegal_opcode_lenient() {
+        let mut new_cpu = cpu_before_illegal_opcode();
+        assert_eq!(new_cpu.execute_step(), 4);
+        assert_eq!(new_cpu.registers().pc, 0xC001);
+    }
+
+    #[test]
+    #[should_panic(expected = "Illegal opcode 0xd3")]
+    fn test_illegal_opcode_strict() {
+        let mut new_cpu = cpu_before_illegal_opcode();
+        new_cpu.set_strict_debug(true);
+        new_cpu.execute_step();
+    }
+
+    #[test]
+    fn test_f_low_nibble_masked() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_reg_f(0xFF);
+        assert_eq!(new_cpu.reg_f(), 0xF0);
+        new_cpu.set_reg_af(0x12FF);
+        assert_eq!(new_cpu.reg_af(), 0x12F0);
+        new_cpu.set_reg_a(0xFF);
+        assert_eq!(new_cpu.reg_a(), 0xFF);
+        new_cpu.set_registers(RegistersSnapshot {
+            f: 0x9F,
+            ..RegistersSnapshot::default()
+        });
+        assert_eq!(new_cpu.registers().f, 0x90);
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        let all = Flags {
+            z: true,
+            n: true,
+            h: true,
+            c: true,
+        };
+        new_cpu.set_flags(all);
+        assert_eq!(new_cpu.registers().f, 0xF0);
+        assert_eq!(new_cpu.flags(), all);
+        assert!(new_cpu.registers.get_zero());
+        assert!(new_cpu.registers.get_carry());
+        let half_carry = Flags {
+            h: true,
+            ..Flags::default()
+        };
+        new_cpu.set_flags(half_carry);
+        assert_eq!(new_cpu.registers().f, 0x20);
+        assert_eq!(new_cpu.flags(), half_carry);
+        // The lower nibble of F is not a flag
+        new_cpu.registers.f = 0x9F;
+        let zero_and_carry = Flags {
+            z: true,
+            c: true,
+            ..Flags::default()
+        };
+        assert_eq!(new_cpu.flags(), zero_and_carry);
+        new_cpu.set_flags(new_cpu.flags());
+        assert_eq!(new_cpu.registers().f, 0x90);
+    }
+
+    #[test]
+    fn test_stack_dump() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // LD BC, 0x1234; PUSH BC; LD DE, 0x5678; PUSH DE
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x01),
+            (0xC001, 0x34),
+            (0xC002, 0x12),
+            (0xC003, 0xC5),
+            (0xC004, 0x11),
+            (0xC005, 0x78),
+            (0xC006, 0x56),
+            (0xC007, 0xD5),
+        ]);
+        for _ in 0..4 {
+            new_cpu.execute_step();
+        }
+        assert_eq!(new_cpu.stack_dump(2), vec![0x5678, 0x1234]);
+        assert_eq!(new_cpu.registers().sp, 0xFFFA);
+        assert!(new_cpu.stack_dump(0).is_empty());
+        // Near the top of the memory, the reads wrap to 0x0000
+        new_cpu.registers.sp = 0xFFFF;
+        new_cpu.set_memory_bytes(&[(0xFFFF, 0x0D)]);
+        let rom_start = new_cpu.mmu.read_byte(0x0000) as u16;
+        assert_eq!(new_cpu.stack_dump(1), vec![rom_start << 8 | 0x0D]);
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xDFF0,
+            ..RegistersSnapshot::default()
+        });
+        // LD B, 0x12 then NOP
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x06),
+            (0xC001, 0x12),
+            (0xC002, 0x00),
+            (0xDFF0, 0x34),
+        ]);
+        new_cpu.execute_step();
+        new_cpu.execute_step();
+        let dump = new_cpu.dump_state();
+        assert!(dump.contains("PC: 0xc003"), "{}", dump);
+        assert!(dump.contains("B: 0x12"), "{}", dump);
+        assert!(
+            dump.contains("  0xc000: 0x06\n  0xc002: 0x00\n"),
+            "{}",
+            dump
+        );
+        assert!(dump.contains("Stack: 0x34"), "{}", dump);
+    }
+
+    #[test]
+    fn test_recent_instructions() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.ime = false;
+        // NOPs, with an INC A at 0xC010
+        new_cpu.set_memory_bytes(&[(0xC010, 0x3C)]);
+        let expected = |range: std::ops::Range<u16>| -> Vec<(u16, u8)> {
+            range
+                .map(|pc| (pc, if pc == 0xC010 { 0x3C } else { 0x00 }))
+                .collect()
+        };
+        assert!(new_cpu.recent_instructions().is_empty());
+        for _ in 0..5 {
+            new_cpu.execute_step();
+        }
+        let recent: Vec<(u16, u8)> =
+            new_cpu.recent_instructions().iter().copied().collect();
+        assert_eq!(recent, expected(0xC000..0xC005));
+        for _ in 5..20 {
+            new_cpu.execute_step();
+        }
+        let recent: Vec<(u16, u8)> =
+            new_cpu.recent_instructions().iter().copied().collect();
+        assert_eq!(recent.len(), RECENT_INSTRUCTIONS_CAPACITY);
+        assert_eq!(recent, expected(0xC004..0xC014));
+    }
+
+    #[test]
+    fn test_trace_step() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        let before = RegistersSnapshot {
+            h: 0xC1,
+            l: 0x23,
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        };
+        new_cpu.set_registers(before);
+        // INC (HL)
+        new_cpu.set_memory_bytes(&[(0xC000, 0x34), (0xC123, 0x41)]);
+        let (traced_before, after, accesses) = new_cpu.trace_step();
+        assert_eq!(traced_before, before);
+        assert_eq!(after, RegistersSnapshot { pc: 0xC001, ..before });
+        assert_eq!(accesses, vec![
+            (0xC000, 0x34),
+            (0xC123, 0x41),
+            (0xC123, 0x42),
+        ]);
+        // The accesses are only logged during the traced step
+        new_cpu.execute_step();
+        assert_eq!(new_cpu.trace_step().2.len(), 1);
+    }
+
+    #[test]
+    fn test_16_bits_inc_dec_flags() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        // INC BC, DEC BC, INC DE, DEC DE, INC HL, DEC HL, INC SP, DEC SP
+        for op in [0x03, 0x0B, 0x13, 0x1B, 0x23, 0x2B, 0x33, 0x3B] {
+            // Values that would change every flag of an 8 bit INC/DEC
+            let value = if op & 0x08 == 0x08 { 0x0000 } else { 0xFFFF };
+            for f in [0x00, 0xF0] {
+                new_cpu.set_registers(RegistersSnapshot {
+                    f,
+                    pc: 0xC000,
+                    ..RegistersSnapshot::default()
+                });
+                match op >> 4 {
+                    0x0 => new_cpu.set_reg_bc(value),
+                    0x1 => new_cpu.set_reg_de(value),
+                    0x2 => new_cpu.set_reg_hl(value),
+                    _ => new_cpu.set_reg_sp(value),
+                }
+                new_cpu.set_memory_bytes(&[(0xC000, op)]);
+                assert_eq!(new_cpu.execute_step(), 8);
+                assert_eq!(new_cpu.reg_f(), f, "{:#04x}", op);
+                let result = match op >> 4 {
+                    0x0 => new_cpu.reg_bc(),
+                    0x1 => new_cpu.reg_de(),
+                    0x2 => new_cpu.reg_hl(),
+                    _ => new_cpu.reg_sp(),
+                };
+                assert_eq!(result, !value, "{:#04x}", op);
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_count() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // JR -2 with the LCD on
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x18),
+            (0xC001, 0xFE),
+            (0xFF40, 0x91),
+        ]);
+        assert_eq!(new_cpu.frame_count(), 0);
+        new_cpu.run_headless(70224 * 5);
+        assert_eq!(new_cpu.frame_count(), 5);
+        // The counter does not advance while the LCD is off
+        new_cpu.set_memory_bytes(&[(0xFF40, 0x00)]);
+        new_cpu.run_headless(70224 * 2);
+        assert_eq!(new_cpu.frame_count(), 5);
+    }
+
+    #[test]
+    fn test_frame_grayscale() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        assert!(new_cpu.frame_grayscale().iter().all(|gray| *gray == 255));
+        // JR -2 with the LCD and the background on, every shade is black
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x18),
+            (0xC001, 0xFE),
+            (0xFF40, 0x91),
+            (0xFF47, 0xFF),
+        ]);
+        new_cpu.run_headless(70224);
+        let frame = new_cpu.frame_grayscale();
+        assert_eq!(frame.len(), 23040);
+        assert!(frame.iter().all(|gray| *gray == 0));
+    }
+
+    #[test]
+    fn test_shutdown_saves_battery_ram() {
+        let name = "gb_emulator_rust_test_shutdown.gb";
+        let path = std::env::temp_dir().join(name);
+        let save_path = std::path::Path::new("save").join(format!("{}.save", name));
+        let _ = std::fs::remove_file(&save_path);
+        // MBC1+RAM+BATTERY with 8 KiB of RAM
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        std::fs::write(&path, rom).unwrap();
+        let mut new_cpu = CPU::new_embedded(&path.display().to_string());
+        // Enable the RAM and write in it
+        new_cpu.set_memory_bytes(&[(0x0000, 0x0A), (0xA001, 0x42)]);
+        new_cpu.shutdown();
+        assert!(new_cpu.should_stop);
+        let save = std::fs::read(&save_path).unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[1], 0x42);
+        std::fs::remove_file(&save_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_break_on_opcode() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.ime = false;
+        // NOP, SWAP A, NOP, HALT
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x00),
+            (0xC001, 0xCB),
+            (0xC002, 0x37),
+            (0xC003, 0x00),
+            (0xC004, 0x76),
+            (0xFFFF, 0x00),
+        ]);
+        new_cpu.break_on_opcode(0x76);
+        new_cpu.break_on_cb_opcode(0x37);
+        assert_eq!(new_cpu.run_until_break(1000), StopReason::OpcodeBreak);
+        assert_eq!(new_cpu.reg_pc(), 0xC001);
+        assert_eq!(new_cpu.run_until_break(1000), StopReason::OpcodeBreak);
+        assert_eq!(new_cpu.reg_pc(), 0xC004);
+        assert!(!new_cpu.is_halted());
+        // Resuming executes the HALT
+        assert_eq!(new_cpu.run_until_break(1000), StopReason::CycleLimit);
+        assert!(new_cpu.is_halted());
+        new_cpu.clear_opcode_breaks();
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.is_halted = false;
+        new_cpu.run_until_break(12);
+        assert_eq!(new_cpu.reg_pc(), 0xC003);
+    }
+
+    #[test]
+    fn test_set_pc() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        assert_eq!(new_cpu.reg_pc(), 0x0100);
+        new_cpu.ime = false;
+        new_cpu.is_halted = true;
+        new_cpu.set_memory_bytes(&[(0xC000, 0x00)]);
+        new_cpu.set_pc(0xC000);
+        assert!(!new_cpu.is_halted());
+        assert_eq!(new_cpu.execute_step(), 4);
+        assert_eq!(new_cpu.reg_pc(), 0xC001);
+    }
+
+    #[test]
+    fn test_infinite_loop_detection() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // DI ; JR -2
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0xF3),
+            (0xC001, 0x18),
+            (0xC002, 0xFE),
+        ]);
+        // The detection is off by default
+        assert_eq!(new_cpu.run_until_break(10_000), StopReason::CycleLimit);
+        new_cpu.set_infinite_loop_threshold(NonZeroU32::new(100));
+        assert_eq!(
+            new_cpu.run_until_break(1_000_000),
+            StopReason::InfiniteLoop(0xC001)
+        );
+        assert_eq!(new_cpu.reg_pc(), 0xC001);
+        new_cpu.set_infinite_loop_threshold(None);
+        assert_eq!(new_cpu.run_until_break(10_000), StopReason::CycleLimit);
+    }
+
+    #[test]
+    fn test_run_to_vblank() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // JR -2 with the LCD on
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x18),
+            (0xC001, 0xFE),
+            (0xFF40, 0x91),
+            (0xFFFF, 0x00),
+        ]);
+        assert_eq!(new_cpu.run_to_vblank(), StopReason::VBlank);
+        assert_eq!(new_cpu.frame_count(), 1);
+        assert_eq!(new_cpu.mmu.read_byte(0xFF44), 144);
+        assert_eq!(new_cpu.run_to_vblank(), StopReason::VBlank);
+        assert_eq!(new_cpu.frame_count(), 2);
+        // The breakpoints are respected
+        new_cpu.break_on_opcode(0x18);
+        assert_eq!(new_cpu.run_to_vblank(), StopReason::OpcodeBreak);
+        new_cpu.clear_opcode_breaks();
+        // Without LCD, there is no VBlank
+        new_cpu.set_memory_bytes(&[(0xFF40, 0x00)]);
+        assert_eq!(new_cpu.run_to_vblank(), StopReason::CycleLimit);
+        assert_eq!(new_cpu.frame_count(), 2);
+    }
+
+    #[test]
+    fn test_is_halted() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.ime = true;
+        // HALT with only the VBlank interruption enabled
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x76),
+            (0xFF0F, 0x00),
+            (0xFFFF, 0x01),
+        ]);
+        assert!(!new_cpu.is_halted());
+        new_cpu.execute_step();
+        assert!(new_cpu.is_halted());
+        assert!(!new_cpu.is_stopped());
+        new_cpu.execute_step();
+        assert!(new_cpu.is_halted());
+        new_cpu.set_memory_bytes(&[(0xFF0F, 0x01)]);
+        assert_eq!(new_cpu.execute_step(), 20);
+        assert!(!new_cpu.is_halted());
+        assert_eq!(new_cpu.registers().pc, 0x0040);
+    }
+
+    #[test]
+    fn test_request_interrupt() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.set_memory_bytes(&[(0xFF0F, 0x00), (0xFFFF, 0x04)]);
+        let timer = Interrupts {
+            timer: true,
+            ..Interrupts::default()
+        };
+        new_cpu.request_interrupt(timer);
+        assert_eq!(new_cpu.pending_interrupts(), timer);
+        // Nothing is handled without IME
+        new_cpu.ime = false;
+        assert_eq!(new_cpu.manage_interruptions(), 0);
+        new_cpu.ime = true;
+        assert_eq!(new_cpu.manage_interruptions(), 20);
+        assert_eq!(new_cpu.registers().pc, 0x0050);
+        assert_eq!(new_cpu.pending_interrupts(), Interrupts::default());
+    }
+
+    #[test]
+    fn test_timer_wakes_halted_cpu() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        new_cpu.ime = true;
+        // HALT, timer incremented every 16 cycles and about to overflow
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x76),
+            (0xFF05, 0xF0),
+            (0xFF06, 0x00),
+            (0xFF07, 0x05),
+            (0xFF0F, 0x00),
+            (0xFFFF, 0x04),
+        ]);
+        new_cpu.execute_step();
+        assert!(new_cpu.is_halted());
+        let tima = new_cpu.mmu.read_byte(0xFF05);
+        for _ in 0..8 {
+            assert_eq!(new_cpu.execute_step(), 4);
+        }
+        assert!(new_cpu.is_halted());
+        assert_eq!(new_cpu.mmu.read_byte(0xFF05), tima + 2);
+        let mut n_steps = 0;
+        let mut n_cycles = 0;
+        while new_cpu.is_halted() {
+            assert!(n_steps < 100);
+            n_cycles = new_cpu.execute_step();
+            n_steps += 1;
+        }
+        // The interruption was serviced in the step that woke the CPU
+        assert_eq!(n_cycles, 20);
+        assert_eq!(new_cpu.registers().pc, 0x0050);
+        assert_eq!(new_cpu.mmu.read_word(0xFFFC), 0xC001);
+        assert_eq!(new_cpu.mmu.interrupt_flag & 0x04, 0x00);
+        assert!(!new_cpu.ime);
+    }
+
+    #[test]
+    fn test_log_writes() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.set_registers(RegistersSnapshot {
+            a: 0x5A,
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // LD HL, 0x8000; LD (HL+), A; LD (HL+), A
+        new_cpu.set_memory_bytes(&[
+            (0xC000, 0x21),
+            (0xC001, 0x00),
+            (0xC002, 0x80),
+            (0xC003, 0x22),
+            (0xC004, 0x22),
+        ]);
+        new_cpu.log_writes(0x8000..=0x9FFF);
+        for _ in 0..3 {
+            new_cpu.execute_step();
+        }
+        assert_eq!(
+            new_cpu.take_write_log(),
+            vec![(0xC003, 0x8000, 0x5A), (0xC004, 0x8001, 0x5A)]
+        );
+    }
+
+    #[test]
+    fn test_is_no_sleep() {
+        assert!(CPU::is_no_sleep(Some("1")));
+        assert!(!CPU::is_no_sleep(Some("0")));
+        assert!(!CPU::is_no_sleep(Some("")));
+        assert!(!CPU::is_no_sleep(None));
+    }
+
+    #[test]
+    fn test_interrupt_dispatch_cycles() {
+        let path = std::env::temp_dir().join("gb_emulator_rust_test_latency.gb");
+        let mut rom = vec![0x00; 0x8000];
+        // VBlank handler: INC A, LD B, 0x12
+        rom[0x0040..0x0043].copy_from_slice(&[0x3C, 0x06, 0x12]);
+        std::fs::write(&path, rom).unwrap();
+        let mut new_cpu = CPU::new_embedded(&path.display().to_string());
+        std::fs::remove_file(&path).unwrap();
+        new_cpu.ime = true;
+        new_cpu.set_memory_bytes(&[(0xFF0F, 0x01), (0xFFFF, 0x01)]);
+        let before = new_cpu.total_cycles();
+        // Dispatch: 2 NOP, PUSH PC and jump to the handler
+        assert_eq!(new_cpu.execute_step(), 20);
+        assert_eq!(new_cpu.registers().pc, 0x0040);
+        assert_eq!(new_cpu.total_cycles(), before + 20);
+        // The instructions of the handler are timed normally
+        assert_eq!(new_cpu.execute_step(), 4);
+        assert_eq!(new_cpu.execute_step(), 8);
+        assert_eq!(new_cpu.total_cycles(), before + 20 + 4 + 8);
+        assert_eq!(new_cpu.registers().pc, 0x0043);
+    }
+
+    #[test]
+    fn test_load_cartridge() {
+        let path = std::env::temp_dir().join("gb_emulator_rust_test_load.gb");
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100] = 0x3C;
+        std::fs::write(&path, rom).unwrap();
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        new_cpu.run_headless(1000);
+        new_cpu.set_audio_output(48000, false);
+        new_cpu.log_writes(0xC000..=0xC0FF);
+        new_cpu.load_cartridge(&path.display().to_string());
+        assert_eq!(new_cpu.registers().pc, 0x0100);
+        assert_eq!(new_cpu.mmu.read_byte(0x0100), 0x3C);
+        // The settings of the emulator are kept
+        assert_eq!(new_cpu.apu().sample_rate(), 48000);
+        assert!(!new_cpu.apu().is_stereo());
+        new_cpu.mmu.write_byte(0xC010, 0x12);
+        assert_eq!(new_cpu.take_write_log(), vec![(0x0000, 0xC010, 0x12)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_frame_ready() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        assert!(!new_cpu.frame_ready());
+        let mut n_frames = 0;
+        while !new_cpu.frame_ready() && n_frames < 60 {
+            new_cpu.run_frame();
+            n_frames += 1;
+        }
+        assert!(new_cpu.frame_ready());
+        assert_eq!(new_cpu.capture_frame().len(), 160 * 144);
+        assert!(!new_cpu.frame_ready());
+    }
+
+    #[test]
+    fn test_emulated_seconds() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        assert_eq!(new_cpu.emulated_seconds(), 0.0);
+        let n_cycles = new_cpu.run_headless(CLOCK_HZ as u64 / 4);
+        assert_eq!(new_cpu.total_cycles(), n_cycles);
+        assert!((new_cpu.emulated_seconds() - 0.25).abs() < 1e-5);
+        new_cpu.double_speed_cycles = new_cpu.total_cycles;
+        assert!((new_cpu.emulated_seconds() - 0.125).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_nanos_per_frame() {
+        let mut new_cpu = CPU::new_embedded("cartridges/Tetris.gb");
+        assert!((new_cpu.nanos_per_cycle() - NANOS_PER_CYCLE).abs() < 1e-9);
+        // One frame lasts 16.74ms
+        assert!((new_cpu.nanos_per_frame() - 16_742_706.0).abs() < 1.0);
+        new_cpu.mmu.set_model(Model::Cgb);
+        new_cpu.mmu.write_byte(0xFF4D, 0x01);
+        assert!(!new_cpu.mmu.receive_stop());
+        assert!((new_cpu.nanos_per_frame() - 8_371_353.0).abs() < 1.0);
+        new_cpu.mmu.write_byte(0xFF4D, 0x01);
+        new_cpu.mmu.receive_stop();
+        new_cpu.set_clock_hz(NonZeroU32::new(CLOCK_HZ * 2).unwrap());
+        assert!((new_cpu.nanos_per_frame() - 8_371_353.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_slowest_clock() {
+        let mut new_cpu = CPU::with_mmu(
+            Mmu::headless("cartridges/Tetris.gb"),
+            false
+        );
+        new_cpu.set_clock_hz(NonZeroU32::MIN);
+        assert_eq!(new_cpu.nanos_per_cycle(), 1_000_000_000.0);
+        assert_eq!(
+            new_cpu.sleep_duration(4, Duration::ZERO),
+            Duration::from_secs(4)
+        );
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu1.run_frame();
+        let state = cpu1.save_state();
+        let mut cpu2 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu2.load_state(&state);
+        for _ in 0..1000 {
+            cpu1.execute_step();
+            cpu2.execute_step();
+        }
+        assert_eq!(cpu1.registers.pc, cpu2.registers.pc);
+        assert_eq!(cpu1.mmu.read_byte(0xFF05), cpu2.mmu.read_byte(0xFF05));
+        assert_eq!(cpu1.save_state().wram, cpu2.save_state().wram);
+    }
+
+    #[test]
+    fn test_save_and_load_state_mid_line() {
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu1.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // JR -2 with the LCD on
+        cpu1.set_memory_bytes(&[
+            (0xC000, 0x18),
+            (0xC001, 0xFE),
+            (0xFF40, 0x91),
+            (0xFFFF, 0x00),
+        ]);
+        cpu1.run_headless(1234);
+        let state = cpu1.save_state();
+        assert_ne!(state.ppu.dot, 0);
+        let mut cpu2 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu2.load_state(&state);
+        assert_eq!(cpu2.gpu().ppu_state(), cpu1.gpu().ppu_state());
+        assert_eq!(cpu2.save_state().timer, state.timer);
+        for _ in 0..1000 {
+            cpu1.execute_step();
+            cpu2.execute_step();
+            assert_eq!(cpu2.gpu().ppu_state(), cpu1.gpu().ppu_state());
+            assert_eq!(
+                cpu2.mmu.read_byte(0xFF04),
+                cpu1.mmu.read_byte(0xFF04)
+            );
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_state_stop_and_halt_bug() {
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu1.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        // STOP, INC A
+        cpu1.set_memory_bytes(&[(0xC000, 0x10), (0xC001, 0x3C)]);
+        cpu1.execute_step();
+        let state = cpu1.save_state();
+        assert!(state.is_stopped);
+        assert!(state.timer.is_stopped);
+        let mut cpu2 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu2.load_state(&state);
+        assert!(cpu2.is_stopped());
+        for _ in 0..10 {
+            cpu2.execute_step();
+        }
+        assert_eq!(cpu2.registers.pc, 0xC001);
+        assert_eq!(cpu2.registers.a, 0x00);
+        // DI, HALT, INC A
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu1.set_registers(RegistersSnapshot {
+            pc: 0xC000,
+            sp: 0xFFFE,
+            ..RegistersSnapshot::default()
+        });
+        cpu1.set_memory_bytes(&[
+            (0xC000, 0xF3), (0xC001, 0x76), (0xC002, 0x3C),
+        ]);
+        cpu1.execute_step();
+        // Timer interruption requested after DI
+        cpu1.set_memory_bytes(&[(0xFF0F, 0x04), (0xFFFF, 0x04)]);
+        cpu1.execute_step();
+        let state = cpu1.save_state();
+        assert!(state.is_halt_bug);
+        cpu2.load_state(&state);
+        assert!(!cpu2.is_stopped());
+        // INC A is read twice
+        cpu2.execute_step();
+        cpu2.execute_step();
+        assert_eq!(cpu2.registers.pc, 0xC003);
+        assert_eq!(cpu2.registers.a, 0x02);
+    }
+
+    #[test]
+    fn test_save_and_load_state_vram_bank() {
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu1.mmu.set_model(Model::Cgb);
+        cpu1.set_memory_bytes(&[(0xFF4F, 0x01), (0x8000, 0x12)]);
+        let state = cpu1.save_state();
+        assert_eq!(state.vram_bank, 1);
+        let mut cpu2 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu2.mmu.set_model(Model::Cgb);
+        cpu2.load_state(&state);
+        assert_eq!(cpu2.mmu.read_byte(0xFF4F), 0xFF);
+        assert_eq!(cpu2.mmu.read_byte(0x8000), 0x12);
+        cpu1.mmu.write_byte(0x8001, 0x34);
+        cpu2.mmu.write_byte(0x8001, 0x34);
+        assert_eq!(cpu2.gpu().vram()[0x2001], 0x34);
+        assert_eq!(cpu2.gpu().vram(), cpu1.gpu().vram());
+    }
+
+    #[test]
+    fn test_save_and_load_state_apu() {
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu1.set_memory_bytes(&[(0xFF21, 0xF0), (0xFF23, 0x80)]);
+        cpu1.mmu.update(1003);
+        let state = cpu1.save_state();
+        assert!(state.apu.is_channel4_on);
+        let mut cpu2 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu2.load_state(&state);
+        cpu1.mmu.update(5000);
+        cpu2.mmu.update(5000);
+        assert_eq!(
+            cpu2.apu().channel4_lfsr(),
+            cpu1.apu().channel4_lfsr()
+        );
+        assert_eq!(cpu2.save_state().apu, cpu1.save_state().apu);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_save_and_load_state_json() {
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu1.run_frame();
+        let json = cpu1.save_state_json();
+        assert!(json.contains("\"pc\""));
+        assert!(json.contains("\"wram\""));
+        let mut cpu2 = CPU::new_embedded("cartridges/Tetris.gb");
+        cpu2.load_state_json(&json).unwrap();
+        assert_eq!(cpu2.save_state_json(), json);
+        for _ in 0..1000 {
+            cpu1.execute_step();
+            cpu2.execute_step();
+        }
+        assert_eq!(cpu1.registers.pc, cpu2.registers.pc);
+        assert_eq!(cpu1.save_state().wram, cpu2.save_state().wram);
+        assert!(cpu2.load_state_json("{\"pc\": 1}").is_err());
+        assert_eq!(cpu1.registers.pc, cpu2.registers.pc);
+        // Memory areas of the wrong size, edited by hand
+        for area in ["cartridge_ram", "vram", "wram", "oam", "lcd", "hram"] {
+            let mut value: serde_json::Value =
+                serde_json::from_str(&json).unwrap();
+            value[area] = serde_json::json!([]);
+            let before = cpu2.save_state_json();
+            let error = cpu2.load_state_json(&value.to_string()).unwrap_err();
+            assert!(error.starts_with(area));
+            assert_eq!(cpu2.save_state_json(), before);
+        }
+        // The RAM of the cartridge is still usable
+        cpu2.mmu.write_byte(0xA000, 0x12);
+        cpu2.mmu.read_byte(0xA000);
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let mut cpu1 = CPU::new_embedded("cartridges/Tetris.gb");
+        let mut cpu2 = CPU::new_embedded("cartridges/Tetris.gb");
+        assert_eq!(cpu1.state_hash(), cpu2.state_hash());
+        cpu1.run_frame();
+        cpu2.run_frame();
+        assert_eq!(cpu1.state_hash(), cpu2.state_hash());
+        cpu2.mmu.write_byte(0xC000, cpu2.mmu.read_byte(0xC000) ^ 0xFF);
+        assert_ne!(cpu1.state_hash(), cpu2.state_hash());
+    }
 }
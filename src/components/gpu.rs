@@ -1,11 +1,51 @@
+use std::hash::{Hash, Hasher};
+use std::path::Path;
 use crate::components::screen::Screen;
+use crate::state::filter::Filter;
 use crate::state::key_state::KeyState;
+use crate::state::layer::Layer;
+use crate::state::model::Model;
+use crate::state::mode_transition::ModeTransition;
+use crate::state::palette::Palette;
+use crate::state::ppu_mode::PpuMode;
+use crate::state::ppu_state::PpuState;
 use crate::state::tile_object::TileObject;
 
+/// Number of dots needed to draw one line (4 dots per CPU cycle)
+const DOTS_PER_LINE: u32 = 456;
+/// Number of lines of a frame, including the lines of the vertical blank
+const LINES_PER_FRAME: u8 = 154;
+/// Size of the tile data area of the VRAM (0x8000-0x97FF)
+const TILE_DATA_SIZE: usize = 0x1800;
+/// Width of the screen in pixels
+const SCREEN_WIDTH: usize = 160;
+/// Height of the screen in pixels
+const SCREEN_HEIGHT: usize = 144;
+/// Color of the viewport border in the debug overlay
+const DEBUG_VIEWPORT_COLOR: u32 = 0xFF0000;
+/// Color of the object bounding boxes in the debug overlay
+const DEBUG_OBJECT_COLOR: u32 = 0x00FF00;
+/// Number of tiles on each line of the rendered tileset
+const TILESET_COLUMNS: usize = 16;
+/// Width of the rendered tileset of one bank of the VRAM in pixels
+pub const TILESET_WIDTH: usize = TILESET_COLUMNS * 8;
+/// Height of the rendered tileset in pixels (384 tiles)
+pub const TILESET_HEIGHT: usize = TILE_DATA_SIZE / 16 / TILESET_COLUMNS * 8;
+/// Width and height of the image of a tile map (32 x 32 tiles)
+pub const TILEMAP_SIZE: usize = 256;
+/// Size of a bank of the VRAM (one on DMG, two on CGB)
+const VRAM_BANK_SIZE: usize = 0x2000;
+/// Number of dots of the OAM scan (mode 2) at the beginning of a line
+const OAM_SCAN_DOTS: u32 = 80;
+/// Number of dots of the drawing (mode 3), without the object penalties
+const DRAWING_DOTS: u32 = 172;
+
 /// Represents the GPU or PPU of the GameBoy
 pub struct Gpu {
-    /// VRAM of the GPU
+    /// VRAM of the GPU, one bank on DMG and two on CGB
     ram: Vec<u8>,
+    /// Bank of the VRAM mapped from 0x8000 to 0x9FFF (VBK, CGB only)
+    vram_bank: usize,
     /// OAM of the GPU
     /// Contains informations about the objects drawn on screen
     object_attribute: Vec<TileObject>,
@@ -40,12 +80,50 @@ pub struct Gpu {
     obp1: u8,
     /// Is a stat interrpution waiting to be handled by the cpu?
     pub pending_stat_interrupt: bool,
+    /// Is one of the enabled STAT conditions met (the interruption is only
+    /// requested when this line rises)
+    stat_line: bool,
     /// Is a vblank interrpution waiting to be handled by the cpu?
     pub pending_vblank_interrupt: bool,
     /// Screen used to display the information of the gpu
     screen: Screen,
-    /// Current cpu cycle to know when to refresh the screen
-    cpu_cycle: u16,
+    /// Number of dots elapsed since the beginning of the current line
+    cpu_cycle: u32,
+    /// Maximum number of objects drawn on a single line (10 on hardware)
+    max_sprites_per_line: usize,
+    /// Model of GameBoy emulated, changes the meaning of some LCDC bits
+    model: Model,
+    /// Has a frame been completed since the last capture
+    frame_ready: bool,
+    /// Number of frames completed since the GPU was created
+    frame_count: u64,
+    /// Number of horizontal blanks entered since the last call to take_hblanks
+    hblanks: u16,
+    /// Color ids of each line of the tile data (0x8000-0x97FF), decoded
+    /// again each time one of its two bytes is written
+    tile_rows: Vec<[u8; 8]>,
+    /// Was the VRAM modified without updating tile_rows
+    is_tile_cache_stale: bool,
+    /// Indices of the objects found on each visible line, in the OAM order,
+    /// built once from the OAM instead of searching them on every line
+    object_buckets: Vec<Vec<u32>>,
+    /// Was the OAM, the object size or the maximum number of objects per line
+    /// modified without updating object_buckets
+    are_object_buckets_stale: bool,
+    /// Is the background drawn when LCDC enables it
+    is_background_layer_enabled: bool,
+    /// Is the window drawn when LCDC enables it
+    is_window_layer_enabled: bool,
+    /// Are the objects drawn when LCDC enables them
+    is_objects_layer_enabled: bool,
+    /// Are the objects with a smaller x drawn on top, otherwise only the
+    /// index in the OAM matters, if forced (by default, it depends on the
+    /// model: x on DMG, OAM index on CGB)
+    oam_x_priority: Option<bool>,
+    /// Are the mode transitions printed and kept in mode_trace
+    is_mode_tracing: bool,
+    /// Mode transitions logged since the last call to take_mode_trace
+    mode_trace: Vec<ModeTransition>,
 }
 
 impl Gpu {
@@ -54,8 +132,28 @@ impl Gpu {
     /// # Returns
     /// **GPU**: New GPU
     pub fn new() -> Self {
+        Self::with_screen(Screen::new())
+    }
+
+    /// Create a new GPU drawing on a screen without any window
+    ///
+    /// # Returns
+    /// **GPU**: New GPU
+    pub fn headless() -> Self {
+        Self::with_screen(Screen::headless())
+    }
+
+    /// Create a new GPU drawing on the given screen
+    ///
+    /// # Arguments
+    /// **screen (Screen)**: Screen used to display the information of the gpu
+    ///
+    /// # Returns
+    /// **GPU**: New GPU
+    fn with_screen(screen: Screen) -> Self {
         Self {
-            ram: vec![0; 0x2000],
+            ram: vec![0; VRAM_BANK_SIZE],
+            vram_bank: 0,
             object_attribute: vec![TileObject::new(); 40],
             lcd_control: 0,
             lcd_status: 0,
@@ -69,12 +167,471 @@ impl Gpu {
             obp0: 0,
             obp1: 0,
             pending_stat_interrupt: false,
+            stat_line: false,
             pending_vblank_interrupt: false,
-            screen: Screen::new(),
+            screen,
             cpu_cycle: 0,
+            max_sprites_per_line: 10,
+            model: Model::Dmg,
+            frame_ready: false,
+            frame_count: 0,
+            hblanks: 0,
+            tile_rows: vec![[0; 8]; TILE_DATA_SIZE / 2],
+            is_tile_cache_stale: false,
+            object_buckets: vec![vec![]; SCREEN_HEIGHT],
+            are_object_buckets_stale: true,
+            is_background_layer_enabled: true,
+            is_window_layer_enabled: true,
+            is_objects_layer_enabled: true,
+            is_mode_tracing: false,
+            mode_trace: vec![],
+            oam_x_priority: None,
+        }
+    }
+
+    /// Resets the GPU to its initial state, keeping its screen
+    ///
+    /// The maximum number of objects per line, the hidden layers and the
+    /// priority of the objects are kept as well.
+    pub fn reset(&mut self) {
+        let screen = std::mem::replace(&mut self.screen, Screen::headless());
+        let mut gpu = Self::with_screen(screen);
+        gpu.max_sprites_per_line = self.max_sprites_per_line;
+        gpu.is_background_layer_enabled = self.is_background_layer_enabled;
+        gpu.is_window_layer_enabled = self.is_window_layer_enabled;
+        gpu.is_objects_layer_enabled = self.is_objects_layer_enabled;
+        gpu.oam_x_priority = self.oam_x_priority;
+        *self = gpu;
+    }
+
+    /// Returns a copy of the internal state of the PPU
+    ///
+    /// # Returns
+    /// **PpuState**: Position in the current line and STAT line
+    pub fn ppu_state(&self) -> PpuState {
+        PpuState {
+            dot: self.cpu_cycle,
+            status: self.lcd_status & 0x07,
+            stat_line: self.stat_line,
+        }
+    }
+
+    /// Restores the internal state of the PPU
+    ///
+    /// Called after the LCD registers are restored, which may restart the
+    /// frame.
+    ///
+    /// # Arguments
+    /// **state (&PpuState)**: State to restore, as returned by ppu_state
+    pub fn restore_ppu_state(&mut self, state: &PpuState) {
+        self.cpu_cycle = state.dot;
+        self.lcd_status = (self.lcd_status & 0xF8) | (state.status & 0x07);
+        self.stat_line = state.stat_line;
+    }
+
+    /// Returns the model of GameBoy emulated by the GPU
+    ///
+    /// # Returns
+    /// **Model**: Model of GameBoy emulated
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Change the model of GameBoy emulated by the GPU
+    ///
+    /// The VRAM has a second bank on CGB, which is dropped when going back to
+    /// DMG.
+    ///
+    /// # Arguments
+    /// **model (Model)**: Model of GameBoy to emulate
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        let n_banks = if model == Model::Cgb { 2 } else { 1 };
+        self.ram.resize(n_banks * VRAM_BANK_SIZE, 0);
+        self.vram_bank = 0;
+    }
+
+    /// Returns the value of VBK
+    ///
+    /// # Returns
+    /// **u8**: Bank of the VRAM in bit 0 on CGB, 0xFF on DMG
+    pub fn read_vram_bank(&self) -> u8 {
+        match self.model {
+            Model::Cgb => 0xFE | self.vram_bank as u8,
+            Model::Dmg => 0xFF,
+        }
+    }
+
+    /// Selects the bank of the VRAM read and written by the CPU
+    ///
+    /// Ignored on DMG, which only has one bank.
+    ///
+    /// # Arguments
+    /// **value (u8)**: New value of VBK, only bit 0 is used
+    pub fn write_vram_bank(&mut self, value: u8) {
+        if self.model == Model::Cgb {
+            self.vram_bank = (value & 0x01) as usize;
         }
     }
 
+    /// Enable or disable the logging of the mode transitions
+    ///
+    /// Each transition is printed with the current LY and dot count, and kept
+    /// until take_mode_trace is called, to compare the STAT timing with
+    /// references.
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the mode transitions be logged
+    pub fn set_mode_trace(&mut self, value: bool) {
+        self.is_mode_tracing = value;
+        if !value {
+            self.mode_trace.clear();
+        }
+    }
+
+    /// Returns the mode transitions logged since the last call
+    ///
+    /// # Returns
+    /// **`Vec<ModeTransition>`**: Transitions in the order they happened
+    pub fn take_mode_trace(&mut self) -> Vec<ModeTransition> {
+        std::mem::take(&mut self.mode_trace)
+    }
+
+    /// Change the maximum number of objects drawn on a single line
+    ///
+    /// The hardware only draws 10 objects per line, raising this limit allows
+    /// to see every object for debugging.
+    ///
+    /// # Arguments
+    /// **value (usize)**: New maximum number of objects per line
+    pub fn set_max_sprites_per_line(&mut self, value: usize) {
+        self.max_sprites_per_line = value;
+        self.are_object_buckets_stale = true;
+    }
+
+    /// Change how the priority between two overlapping objects is decided
+    ///
+    /// On DMG, the object with the smallest x is drawn on top, and the first
+    /// one in the OAM in case of tie. On CGB, the first one in the OAM is
+    /// always drawn on top. Until this is called, the priority follows the
+    /// model.
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the objects be sorted by x (true for DMG)
+    pub fn set_oam_x_priority(&mut self, value: bool) {
+        self.oam_x_priority = Some(value);
+    }
+
+    /// Returns how the priority between two overlapping objects is decided
+    ///
+    /// # Returns
+    /// **bool**: true iff the objects are sorted by x, which is forced by
+    /// set_oam_x_priority or the default on DMG
+    fn is_oam_x_priority(&self) -> bool {
+        self.oam_x_priority.unwrap_or(self.model == Model::Dmg)
+    }
+
+    /// Replaces the colors displayed by those of a .pal file
+    ///
+    /// # Arguments
+    /// **path (&Path)**: Path of the .pal file
+    ///
+    /// # Returns
+    /// **`Result<(), String>`**: Message explaining why the file is invalid,
+    /// if it is
+    pub fn load_palette_file(&mut self, path: &Path) -> Result<(), String> {
+        self.screen.load_palette_file(path)
+    }
+
+    /// Replaces the colors displayed for the shades of the GameBoy
+    ///
+    /// # Arguments
+    /// **palette (Palette)**: New colors of the shades
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.screen.set_palette(palette);
+    }
+
+    /// Change the post-processing applied to the image shown in the window
+    ///
+    /// # Arguments
+    /// **filter (Filter)**: Scanlines, LCD grid or none
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.screen.set_filter(filter);
+    }
+
+    /// Gives the color of each pixel with a function instead of the palette
+    ///
+    /// # Arguments
+    /// **color_mapper (impl FnMut(u8) -> u32)**: Function returning the
+    /// 0xRRGGBB color of a shade (00 to 11 for white to black)
+    pub fn set_color_mapper(
+        &mut self,
+        color_mapper: impl FnMut(u8) -> u32 + 'static
+    ) {
+        self.screen.set_color_mapper(color_mapper);
+    }
+
+    /// Gives the color of each pixel with the palette again
+    pub fn clear_color_mapper(&mut self) {
+        self.screen.clear_color_mapper();
+    }
+
+    /// Hides or shows a layer, whatever the LCDC says
+    ///
+    /// A hidden layer is never drawn, a shown layer is drawn only when the
+    /// LCDC enables it.
+    ///
+    /// # Arguments
+    /// **layer (Layer)**: Layer to hide or show
+    /// **value (bool)**: Should the layer be drawn
+    pub fn set_layer_enabled(&mut self, layer: Layer, value: bool) {
+        match layer {
+            Layer::Background => self.is_background_layer_enabled = value,
+            Layer::Window => self.is_window_layer_enabled = value,
+            Layer::Objects => self.is_objects_layer_enabled = value,
+        }
+    }
+
+    /// Returns the position of the top left corner of the background
+    ///
+    /// # Returns
+    /// **(u8, u8)**: x and y coordinates of the background viewport (SCX, SCY)
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.background_viewport_x, self.background_viewport_y)
+    }
+
+    /// Returns the position of the top left corner of the window
+    ///
+    /// # Returns
+    /// **(u8, u8)**: x and y coordinates of the window (WX - 7, WY)
+    pub fn window_position(&self) -> (u8, u8) {
+        (
+            self.window_x_position_plus_sept.wrapping_sub(7),
+            self.window_y_position
+        )
+    }
+
+    /// Returns the current mode of the PPU
+    ///
+    /// # Returns
+    /// **PpuMode**: Mode indicated in the LCD status
+    pub fn mode(&self) -> PpuMode {
+        PpuMode::from_lcd_status(self.lcd_status)
+    }
+
+    /// Moves the PPU to a line without drawing the previous ones
+    ///
+    /// Used to set up the tests of the scanline logic. LY is compared to LYC
+    /// as if the PPU had reached this line.
+    ///
+    /// # Arguments
+    /// **value (u8)**: New value of LY
+    #[cfg(any(test, feature = "test-vectors"))]
+    pub fn set_ly(&mut self, value: u8) {
+        self.lcd_y_coordinate = value;
+        self.compare_lyc();
+        self.update_stat_line();
+    }
+
+    /// Returns the objects of the OAM
+    ///
+    /// # Returns
+    /// **&[TileObject]**: The 40 objects of the OAM
+    pub fn objects(&self) -> &[TileObject] {
+        &self.object_attribute
+    }
+
+    /// Returns true iff a frame was completed since the last capture
+    ///
+    /// # Returns
+    /// **bool**: Is a new frame ready to be presented
+    pub fn frame_ready(&self) -> bool {
+        self.frame_ready
+    }
+
+    /// Returns the last frame drawn and marks it as presented
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn capture_frame(&mut self) -> Vec<u32> {
+        self.frame_ready = false;
+        self.screen.frame().to_vec()
+    }
+
+    /// Returns the number of frames completed since the GPU was created
+    ///
+    /// # Returns
+    /// **u64**: Number of times the PPU entered the VBlank
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Returns the last frame presented
+    ///
+    /// Unlike the captured frame, the framebuffer only changes when a frame
+    /// is completed.
+    ///
+    /// # Returns
+    /// **&[u32]**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn framebuffer(&self) -> &[u32] {
+        self.screen.framebuffer()
+    }
+
+    /// Returns the last frame drawn with debug information on top of it
+    ///
+    /// The border of the background viewport and the bounding box of each
+    /// object are drawn over a copy of the frame, the frame itself is left
+    /// untouched and stays ready if it was.
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn render_debug_overlay(&self) -> Vec<u32> {
+        let mut frame = self.screen.frame().to_vec();
+        draw_rectangle(
+            &mut frame,
+            0,
+            0,
+            SCREEN_WIDTH as i16,
+            SCREEN_HEIGHT as i16,
+            DEBUG_VIEWPORT_COLOR,
+        );
+        let obj_size = self.obj_size() as i16;
+        for object in self.object_attribute.iter() {
+            draw_rectangle(
+                &mut frame,
+                object.x_position as i16 - 8,
+                object.y_position as i16 - 16,
+                8,
+                obj_size,
+                DEBUG_OBJECT_COLOR,
+            );
+        }
+        frame
+    }
+
+    /// Returns the width of the rendered tileset
+    ///
+    /// # Returns
+    /// **usize**: TILESET_WIDTH for each bank of the VRAM (2 on CGB)
+    pub fn tileset_width(&self) -> usize {
+        TILESET_WIDTH * self.ram.len() / VRAM_BANK_SIZE
+    }
+
+    /// Draws every tile of the VRAM, colored with the background palette
+    ///
+    /// The tiles are drawn from 0x8000, 16 per line. On CGB, the tiles of
+    /// the bank 1 are drawn on the right of the ones of the bank 0.
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: 0xRRGGBB color of each pixel, line by line
+    /// (tileset_width x TILESET_HEIGHT)
+    pub fn render_tileset(&self) -> Vec<u32> {
+        let palette = self.screen.palette();
+        let width = self.tileset_width();
+        let mut pixels = vec![0; width * TILESET_HEIGHT];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+            let bank = x / TILESET_WIDTH;
+            let x = x % TILESET_WIDTH;
+            let tile = (y / 8) * TILESET_COLUMNS + x / 8;
+            let color_id = self.color_id_in_vram(
+                bank,
+                0x8000 + (tile as u16) * 16,
+                (y % 8) as u8,
+                (x % 8) as u8
+            );
+            let shade = (self.bg_palette_data >> (2 * color_id)) & 0x03;
+            *pixel = palette.color(shade);
+        }
+        pixels
+    }
+
+    /// Draws the whole background tile map, colored with the background
+    /// palette
+    ///
+    /// The map and the tile data selected by LCDC are used, without
+    /// scrolling.
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: 0xRRGGBB color of each pixel, line by line
+    /// (TILEMAP_SIZE x TILEMAP_SIZE)
+    pub fn render_tilemap(&self) -> Vec<u32> {
+        let palette = self.screen.palette();
+        let mut pixels = vec![0; TILEMAP_SIZE * TILEMAP_SIZE];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let (x, y) = ((i % TILEMAP_SIZE) as u8, (i / TILEMAP_SIZE) as u8);
+            let color_id = self.color_id_in_vram(
+                0,
+                self.background_tile_address(x, y),
+                y & 0x07,
+                x & 0x07
+            );
+            let shade = (self.bg_palette_data >> (2 * color_id)) & 0x03;
+            *pixel = palette.color(shade);
+        }
+        pixels
+    }
+
+    /// Draws the whole background tile map with the border of the area
+    /// shown on the screen
+    ///
+    /// The 160 x 144 border starts at (SCX, SCY) and wraps around the edges
+    /// of the map like the background does.
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: 0xRRGGBB color of each pixel, line by line
+    /// (TILEMAP_SIZE x TILEMAP_SIZE)
+    pub fn render_tilemap_with_viewport(&self) -> Vec<u32> {
+        let mut pixels = self.render_tilemap();
+        let (left, top) = (
+            self.background_viewport_x,
+            self.background_viewport_y
+        );
+        let right = left.wrapping_add(SCREEN_WIDTH as u8 - 1);
+        let bottom = top.wrapping_add(SCREEN_HEIGHT as u8 - 1);
+        let mut set_pixel = |x: u8, y: u8| {
+            let index = y as usize * TILEMAP_SIZE + x as usize;
+            pixels[index] = DEBUG_VIEWPORT_COLOR;
+        };
+        for dx in 0..SCREEN_WIDTH as u8 {
+            set_pixel(left.wrapping_add(dx), top);
+            set_pixel(left.wrapping_add(dx), bottom);
+        }
+        for dy in 0..SCREEN_HEIGHT as u8 {
+            set_pixel(left, top.wrapping_add(dy));
+            set_pixel(right, top.wrapping_add(dy));
+        }
+        pixels
+    }
+
+    /// Writes every tile of the VRAM in a PNG file
+    ///
+    /// # Arguments
+    /// **path (&Path)**: Path of the file
+    ///
+    /// # Returns
+    /// **`ImageResult<()>`**: Error encountered while writing the file, if
+    /// any
+    #[cfg(feature = "recording")]
+    pub fn save_tileset_png(&self, path: &Path) -> image::ImageResult<()> {
+        crate::components::recorder::save_png(
+            path,
+            self.tileset_width() as u32,
+            TILESET_HEIGHT as u32,
+            &self.render_tileset(),
+        )
+    }
+
+    /// Returns the number of horizontal blanks entered since the last call
+    ///
+    /// Used by the MMU to run the HBlank DMA transfers.
+    ///
+    /// # Returns
+    /// **u16**: Number of horizontal blanks entered
+    pub fn take_hblanks(&mut self) -> u16 {
+        std::mem::take(&mut self.hblanks)
+    }
+
     /// Transmit the Key State
     ///
     /// Returns informations about what key is down
@@ -82,7 +639,38 @@ impl Gpu {
     /// # Returns
     /// **KeyState**: Are key pressed for the screen?
     pub fn transmit_key(&self) -> &KeyState {
-        &self.screen.key_state
+        self.screen.key_state()
+    }
+
+    /// Change the state of the keys of the screen
+    ///
+    /// # Arguments
+    /// **key_state (KeyState)**: New state of the keys
+    pub fn set_key_state(&mut self, key_state: KeyState) {
+        self.screen.set_key_state(key_state);
+    }
+
+    /// Change the state of the keys of a player
+    ///
+    /// Ignored if the player does not exist.
+    ///
+    /// # Arguments
+    /// **player (usize)**: Index of the player (0 is the keyboard)
+    /// **key_state (KeyState)**: New state of the keys
+    pub fn set_key_state_for(&mut self, player: usize, key_state: KeyState) {
+        self.screen.set_key_state_for(player, key_state);
+    }
+
+    /// Returns the state of the keys of a player
+    ///
+    /// # Arguments
+    /// **player (usize)**: Index of the player (0 is the keyboard)
+    ///
+    /// # Returns
+    /// **`Option<&KeyState>`**: Keys pressed by this player, None if it does
+    /// not exist
+    pub fn key_state_for(&self, player: usize) -> Option<&KeyState> {
+        self.screen.key_state_for(player)
     }
 
     /// Read a value in the given address of the LCD memory are
@@ -98,8 +686,9 @@ impl Gpu {
             0x40 => {
                 self.lcd_control
             },
+            // The bit 7 is unused and always set
             0x41 => {
-                self.lcd_status
+                self.lcd_status | 0x80
             },
             0x42 => {
                 self.background_viewport_y
@@ -144,10 +733,19 @@ impl Gpu {
         match address {
             // LCD
             0x40 => {
+                if (self.lcd_control ^ value) & 0x04 == 0x04 {
+                    self.are_object_buckets_stale = true;
+                }
+                let was_enabled = self.is_enabled();
                 self.lcd_control = value;
+                if was_enabled != self.is_enabled() {
+                    self.restart_frame();
+                }
             },
             0x41 => {
-                self.lcd_status = value;
+                // The mode and the LYC flag are read only
+                self.lcd_status = (value & 0x78) | (self.lcd_status & 0x07);
+                self.stat_line = self.stat_conditions();
             },
             0x42 => {
                 self.background_viewport_y = value;
@@ -166,6 +764,8 @@ impl Gpu {
             },
             0x45 => {
                 self.lyc_compare = value;
+                self.compare_lyc();
+                self.stat_line = self.stat_conditions();
             },
             // Palettes
             0x47 => {
@@ -191,7 +791,7 @@ impl Gpu {
     /// # Returns
     /// **u8**: Value read at this address
     pub fn read_ram(&self, address: u16) -> u8 {
-        self.ram[(address - 0x8000) as usize]
+        self.ram[self.vram_bank * VRAM_BANK_SIZE + (address - 0x8000) as usize]
     }
     
     /// Write the given value in the given address of the VRAM
@@ -204,7 +804,107 @@ impl Gpu {
         address: u16,
         value: u8
     ) {
-        self.ram[(address - 0x8000) as usize] = value;
+        let offset = (address - 0x8000) as usize;
+        self.ram[self.vram_bank * VRAM_BANK_SIZE + offset] = value;
+        if self.vram_bank == 0 && offset < TILE_DATA_SIZE {
+            self.decode_tile_row(address & 0xFFFE);
+        }
+    }
+
+    /// Updates the cached color ids of a line of a tile from the bank 0 of
+    /// the VRAM
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the first byte of the line
+    fn decode_tile_row(&mut self, address: u16) {
+        let low_byte = self.ram[(address - 0x8000) as usize];
+        let high_byte = self.ram[(address + 1 - 0x8000) as usize];
+        let row = &mut self.tile_rows[((address - 0x8000) >> 1) as usize];
+        for (x_in_tile, color_id) in row.iter_mut().enumerate() {
+            *color_id =
+                (((high_byte >> (7 - x_in_tile)) & 0x01) << 1) |
+                ((low_byte >> (7 - x_in_tile)) & 0x01);
+        }
+    }
+
+    /// Returns the content of the VRAM
+    ///
+    /// # Returns
+    /// **&[u8]**: Bytes from 0x8000 to 0x9FFF, of the bank 0 then of the
+    /// bank 1 on CGB
+    pub fn vram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Gives a mutable access to the content of the VRAM
+    ///
+    /// The cached tiles are decoded again before the next line is drawn.
+    ///
+    /// # Returns
+    /// **&mut [u8]**: Bytes from 0x8000 to 0x9FFF, of the bank 0 then of the
+    /// bank 1 on CGB
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        self.is_tile_cache_stale = true;
+        &mut self.ram
+    }
+
+    /// Decodes every line of every tile again if the VRAM was modified
+    /// through vram_mut
+    fn refresh_tile_cache(&mut self) {
+        if !self.is_tile_cache_stale {
+            return;
+        }
+        self.is_tile_cache_stale = false;
+        for address in (0x8000..0x8000 + TILE_DATA_SIZE as u16).step_by(2) {
+            self.decode_tile_row(address);
+        }
+    }
+
+    /// Writes the 16 bytes of a tile in the VRAM
+    ///
+    /// # Arguments
+    /// **index (u16)**: Index of the tile from 0x8000 (0 to 383)
+    /// **data ([u8; 16])**: Two bytes per line of the tile, from top to bottom
+    pub fn set_tile(&mut self, index: u16, data: [u8; 16]) {
+        for (i, value) in data.iter().enumerate() {
+            self.write_ram(0x8000 + (index << 4) + i as u16, *value);
+        }
+    }
+
+    /// Chooses the tile displayed at a position of the background tile map
+    ///
+    /// The tile map currently selected by LCDC.3 is modified.
+    ///
+    /// # Arguments
+    /// **x (u8)**: Column of the tile in the map (0 to 31)
+    /// **y (u8)**: Line of the tile in the map (0 to 31)
+    /// **tile (u8)**: Index of the tile, as interpreted according to LCDC.4
+    pub fn set_bg_map_entry(&mut self, x: u8, y: u8, tile: u8) {
+        self.write_ram(
+            self.background_tile_map() + (y as u16 & 0x1F) * 32 +
+                (x as u16 & 0x1F),
+            tile
+        );
+    }
+
+    /// Returns the content of the OAM
+    ///
+    /// # Returns
+    /// **`Vec<u8>`**: The 4 bytes of each of the 40 objects (y, x, tile index
+    /// and flags), as read from 0xFE00 to 0xFE9F
+    pub fn oam_bytes(&self) -> Vec<u8> {
+        (0xFE00..=0xFE9F).map(|address| self.read_oam(address)).collect()
+    }
+
+    /// Restores the content of the OAM
+    ///
+    /// # Arguments
+    /// **bytes (&[u8])**: The 4 bytes of each of the 40 objects, as returned
+    /// by oam_bytes
+    pub fn load_oam_bytes(&mut self, bytes: &[u8]) {
+        for (address, value) in (0xFE00..=0xFE9F).zip(bytes) {
+            self.write_oam(address, *value);
+        }
     }
 
     /// Read a value in the given address of the OAM
@@ -213,22 +913,25 @@ impl Gpu {
     /// **address (u16)**: Address to read
     ///
     /// # Returns
-    /// **u8**: Value read at this address
+    /// **u8**: Value read at this address, 0xFF after the 40 objects
     pub fn read_oam(&self, address: u16) -> u8 {
        let entry = (address & 0x00FF) >> 2;
        let byte = (address & 0x00FF) & 0x0003;
+       let Some(object) = self.object_attribute.get(entry as usize) else {
+           return 0xFF;
+       };
        match byte {
            0 => {
-               self.object_attribute[entry as usize].y_position
+               object.y_position
            },
            1 => {
-               self.object_attribute[entry as usize].x_position
+               object.x_position
            },
            2 => {
-               self.object_attribute[entry as usize].tile_index
+               object.tile_index
            },
            3 => {
-               self.object_attribute[entry as usize].flags
+               object.flags
            },
            _ => {
                panic!("Wrong attribute in oam");
@@ -238,6 +941,8 @@ impl Gpu {
 
     /// Write the given value in the given address of the OAM
     ///
+    /// The writes after the 40 objects are ignored.
+    ///
     /// # Arguments
     /// **address (u16)**: Address to write to
     /// **value (u8)**: Value to write at this address
@@ -248,18 +953,22 @@ impl Gpu {
     ) {
        let entry = (address & 0x00FF) >> 2;
        let byte = (address & 0x00FF) & 0x0003;
+       let Some(object) = self.object_attribute.get_mut(entry as usize) else {
+           return;
+       };
+       self.are_object_buckets_stale = true;
        match byte {
            0 => {
-               self.object_attribute[entry as usize].y_position = value;
+               object.y_position = value;
            },
            1 => {
-               self.object_attribute[entry as usize].x_position = value;
+               object.x_position = value;
            },
            2 => {
-               self.object_attribute[entry as usize].tile_index = value;
+               object.tile_index = value;
            },
            3 => {
-               self.object_attribute[entry as usize].flags = value;
+               object.flags = value;
            },
            _ => {
                panic!("Wrong attribute in oam");
@@ -281,9 +990,9 @@ impl Gpu {
     /// **u16**: Beginning of the window tile map area
     fn window_tile_map(&self) -> u16 {
         if self.lcd_control & 0x40 == 0x40 {
-            0x9800
-        } else {
             0x9C00
+        } else {
+            0x9800
         }
     }
 
@@ -315,9 +1024,9 @@ impl Gpu {
     /// **u16**: Beginning address of the tile map area
     fn background_tile_map(&self) -> u16 {
         if self.lcd_control & 0x08 == 0x08 {
-            0x9800
-        } else {
             0x9C00
+        } else {
+            0x9800
         }
     }
 
@@ -343,6 +1052,9 @@ impl Gpu {
 
     /// Returns true if the window and background should be drawn on the screen
     ///
+    /// On CGB, this bit instead tells whether the background and window can
+    /// have priority over the objects.
+    ///
     /// # Retuns
     /// **bool**: True if the window and background should be drawn on the
     /// screen
@@ -368,20 +1080,67 @@ impl Gpu {
     /// Checks if lyc == ly
     ///
     /// The gameboy compare constantly the values of the addresses of LCY Y
-    /// Compare and LCD Y coordinate, and sends an interruption when they are
+    /// Compare and LCD Y coordinate, and indicates in LCD status if they are
     /// equal
-    fn lyc_equal_ly(&mut self) {
-        self.lcd_status |= 0x040;
-        if self.lcd_status & 0x40 == 0x40 {
+    fn compare_lyc(&mut self) {
+        if self.lcd_y_coordinate == self.lyc_compare {
+            self.lcd_status |= 0x04;
+        } else {
+            self.lcd_status &= !0x04;
+        }
+    }
+
+    /// Returns the state of the STAT line
+    ///
+    /// # Returns
+    /// **bool**: True iff one of the conditions enabled in LCD status is met
+    /// (lyc == ly or the current mode, except for the drawing mode)
+    fn stat_conditions(&self) -> bool {
+        let is_lyc_met = self.lcd_status & 0x44 == 0x44;
+        let is_mode_met = match self.lcd_status & 0x03 {
+            mode @ 0..=2 => {
+                let mask = 1 << (mode + 3);
+                self.lcd_status & mask == mask
+            },
+            _ => false,
+        };
+        is_lyc_met || is_mode_met
+    }
+
+    /// Updates the STAT line and sends a STAT interruption if it rises
+    ///
+    /// The line stays high while the enabled conditions change, in which case
+    /// no other interruption is sent.
+    fn update_stat_line(&mut self) {
+        let stat_line = self.stat_conditions();
+        if stat_line && !self.stat_line {
             self.send_stat_interrupt();
         }
+        self.stat_line = stat_line;
+    }
+
+    /// Moves the PPU back to the beginning of the first line, when the LCD is
+    /// switched on or off
+    ///
+    /// While the LCD is off, LY stays at 0 in mode 0 without any STAT
+    /// interruption. Once it is on again, a new frame is drawn from the first
+    /// line.
+    fn restart_frame(&mut self) {
+        self.lcd_y_coordinate = 0;
+        self.cpu_cycle = 0;
+        self.compare_lyc();
+        self.lcd_status &= 0xFC;
+        if self.is_enabled() {
+            self.update_stat_line();
+        } else {
+            self.stat_line = self.stat_conditions();
+        }
     }
 
     /// Switches the PPU mode
     ///
     /// If the new mode is different for the previous one, change the mode
-    /// indicated in LCD status, and if the interruptions are activated for
-    /// this mode, send one.
+    /// indicated in LCD status, and update the STAT line.
     ///
     /// # Arguments
     /// **mode (u8)**: New PPU mode
@@ -391,46 +1150,70 @@ impl Gpu {
             return;
         }
         self.lcd_status = (self.lcd_status & 0xFC) | (mode & 0x03);
-        let mask = 1 << (mode + 3);
-        let interruption_for_this_mode = self.lcd_status & mask == mask;
-        if interruption_for_this_mode {
-            self.send_stat_interrupt();
+        if self.is_mode_tracing {
+            // The line is drawn at once at its end, the dot logged is the
+            // one at which the GameBoy changes mode
+            let dot = match mode & 0x03 {
+                3 => OAM_SCAN_DOTS,
+                0 => OAM_SCAN_DOTS + DRAWING_DOTS,
+                _ => 0,
+            };
+            let transition = ModeTransition {
+                mode: self.mode(),
+                ly: self.lcd_y_coordinate,
+                dot,
+            };
+            println!(
+                "PPU mode {} -> {} at LY {} dot {}",
+                old_mode,
+                mode & 0x03,
+                transition.ly,
+                transition.dot
+            );
+            self.mode_trace.push(transition);
         }
+        self.update_stat_line();
     }
 
     /// Updates the screen and search for key presses
     ///
     /// # Arguments
-    /// **n_cycles (u16)**: Number of cpu cycles since last update
+    /// **n_cycles (u32)**: Number of cpu cycles since last update
     ///
     /// # Returns
     /// **bool**: true iff the Escape key was pressed
-    pub fn update(&mut self, n_cycles: u16) -> bool {
-        if ((self.cpu_cycle & 0x3FFF) + n_cycles) >= 0x4000 {
-            self.draw_lines();
+    pub fn update(&mut self, n_cycles: u32) -> bool {
+        if self.is_enabled() {
+            self.cpu_cycle += n_cycles;
+            while self.cpu_cycle >= DOTS_PER_LINE {
+                self.cpu_cycle -= DOTS_PER_LINE;
+                self.next_line();
+            }
         }
-        self.cpu_cycle = self.cpu_cycle.wrapping_add(n_cycles);
         self.screen.update_key_press()
     }
 
-    /// Draws one frame
+    /// Ends the current line and moves to the next one
     ///
-    /// One frame lasts 16.74 ms
-    fn draw_lines(&mut self) {
-        if !self.is_enabled() {
-            return;
-        }
-        self.lcd_y_coordinate = 0;
-        while self.lcd_y_coordinate < 154 {
-            //let time = SystemTime::now();
-            if self.lcd_y_coordinate == self.lyc_compare {
-                self.lyc_equal_ly();
-            }
-            self.draw_line();
-            self.lcd_y_coordinate += 1;
-            //sleep(Duration::from_micros(16740) - time.elapsed.unwrap());
+    /// One line lasts 456 dots, and one frame (154 lines) lasts 16.74 ms.
+    /// The VBlank interruption is sent and the frame is displayed once, when
+    /// entering the line 144.
+    fn next_line(&mut self) {
+        self.draw_line();
+        self.lcd_y_coordinate = (self.lcd_y_coordinate + 1) % LINES_PER_FRAME;
+        self.compare_lyc();
+        if self.lcd_y_coordinate == 144 {
+            // Mode 1
+            // Vertical Black
+            // Waiting until the next frame
+            // 10 lines of 456 dots
+            self.switch_mode_to(1);
+            self.send_vblank_interrupt();
+            self.screen.present();
+            self.frame_ready = true;
+            self.frame_count += 1;
         }
-        self.screen.update();
+        self.update_stat_line();
     }
 
     /// Draws a line on the screen
@@ -439,17 +1222,11 @@ impl Gpu {
     fn draw_line(&mut self) {
         // 4 dots per CPU cycle (4.194 MHz)
         let ly = self.lcd_y_coordinate;
-        if ly == 144 {
-            self.send_vblank_interrupt();
-        }
         if ly > 143 {
-            self.switch_mode_to(1);
-            // Mode 1
-            // Vertical Black
-            // Waiting until the next frame
-            // 456 dots
             return;
         }
+        self.refresh_tile_cache();
+        self.refresh_object_buckets();
         self.switch_mode_to(2);
         // Mode 2
         // OAM Scan
@@ -461,7 +1238,7 @@ impl Gpu {
         // Drawing pixels
         // Sending pixels to the LCD
         // 172 dots (160 pixels wide)
-        for x in 0..159 {
+        for x in 0..SCREEN_WIDTH as u8 {
             let pixel = self.draw_pixel(x, ly, &obj_in_line);
             self.screen.receive_pixel(
                 x,
@@ -474,10 +1251,13 @@ impl Gpu {
         // Horizontal blank
         // Waiting for the end of the scanline
         // 204 dots
+        self.hblanks = self.hblanks.saturating_add(1);
     }
 
     /// Returns the color id of a pixel in a tile
     ///
+    /// The tiles are read from the decoded lines cached on each VRAM write.
+    ///
     /// # Arguments
     /// **tile_address (u16)**: Address of the tile
     /// **x_in_tile (u8)**: column in the tile
@@ -494,106 +1274,135 @@ impl Gpu {
         y_in_tile: u8,
         x_in_tile: u8,
     ) -> u8{
-        let high_byte = self.ram[
-            (tile_address + y_in_tile as u16 * 2 + 1) as usize
-        ];
-        let low_byte = self.ram[
-            (tile_address + y_in_tile as u16 * 2) as usize
-        ];
-        
-        (((high_byte >> (7 - x_in_tile)) & 0x01) << 1) |
-        ((low_byte >> (7 - x_in_tile)) & 0x01)
-        
+        let row = ((tile_address - 0x8000) >> 1) as usize + y_in_tile as usize;
+        self.tile_rows[row][x_in_tile as usize]
+    }
+
+    /// Returns the color id of a pixel in a tile, decoded from the VRAM
+    ///
+    /// Unlike color_id_in_tile, the cache is not used, so the result is
+    /// right even if the VRAM was modified through vram_mut.
+    ///
+    /// # Arguments
+    /// **bank (usize)**: Bank of the VRAM containing the tile
+    /// **tile_address (u16)**: Address of the tile
+    /// **y_in_tile (u8)**: line in the tile
+    /// **x_in_tile (u8)**: column in the tile
+    ///
+    /// # Returns
+    /// **u8**: Color id of the pixel
+    fn color_id_in_vram(
+        &self,
+        bank: usize,
+        tile_address: u16,
+        y_in_tile: u8,
+        x_in_tile: u8,
+    ) -> u8 {
+        let address = bank * VRAM_BANK_SIZE +
+            (tile_address - 0x8000) as usize +
+            y_in_tile as usize * 2;
+        let bit = 7 - x_in_tile;
+        ((self.ram[address] >> bit) & 0x01) |
+            (((self.ram[address + 1] >> bit) & 0x01) << 1)
     }
 
-    /// Returns the color of a pixel of the background
+    /// Returns the color id of a pixel of the background
     ///
     /// # Arguments
     /// **x (u8)**: x coordinate of the pixel on the screen
     /// **y (u8)**: y coordinate of the pixel on the screen
     ///
     /// # Returns
-    /// **u8**: Color of the given pixel from the background
+    /// **u8**: Color id of the given pixel from the background
     fn color_background(&self, x: u8, y: u8) -> u8 {
-        let y_in_map = self.background_viewport_y + y;
-        let x_in_map = self.background_viewport_x + x;
+        let y_in_map = self.background_viewport_y.wrapping_add(y);
+        let x_in_map = self.background_viewport_x.wrapping_add(x);
+        // The color is is then found for this tile for the correct pixel. Each
+        // tile is 8x8 pixels.
+        self.color_id_in_tile(
+           self.background_tile_address(x_in_map, y_in_map),
+           y_in_map & 0x07,
+           x_in_map & 0x07
+        )
+    }
+
+    /// Returns the address of the tile drawn at a pixel of the background
+    /// tile map
+    ///
+    /// # Arguments
+    /// **x_in_map (u8)**: x coordinate of the pixel in the map
+    /// **y_in_map (u8)**: y coordinate of the pixel in the map
+    ///
+    /// # Returns
+    /// **u16**: Address of the first byte of the tile
+    fn background_tile_address(&self, x_in_map: u8, y_in_map: u8) -> u16 {
         // Position of the tile when reading line by line from left to right
         let tile_in_map =
             (x_in_map >> 3) as usize +
             (y_in_map >> 3) as usize * 32;
         // This position is the index in the background tile map which is a
         // list of byte identifying each tile of the background.
-        let tile_index = self.ram[
-            self.background_tile_map() as usize +
-            tile_in_map
-        ]; 
+        let tile_index = self.read_ram(
+            self.background_tile_map() + tile_in_map as u16
+        );
         // The id found above correspond to one of the tile of the background
         // and window tile data (each tile is 16 bytes)
-        let tile_address = if tile_index < 128 {
+        if tile_index < 128 {
             self.bg_and_window_tile_data_area() + ((tile_index as u16) << 4)
         } else {
-            0x8800 + ((tile_index as u16) << 4)
-        };
-        // The color is is then found for this tile for the correct pixel. Each
-        // tile is 8x8 pixels.
-        let color_id = self.color_id_in_tile(
-           tile_address,
-           y_in_map & 0x07,
-           x_in_map & 0x07
-        );
-        // This color id is a color of the palette of the background
-        (self.bg_palette_data >> (color_id * 2)) & 0x03
+            // Tiles 128-255 are shared by both areas (0x8800-0x8FFF)
+            0x8000 + ((tile_index as u16) << 4)
+        }
     }
 
-    /// Returns the color of a pixel of the window
+    /// Returns the color id of a pixel of the window
+    ///
+    /// The pixel is in the window iff WY <= y and WX - 7 <= x. With WX < 7,
+    /// the window starts at the left of the screen but its first WX - 7
+    /// columns are hidden, and with WX > 166 the window is out of the screen.
     ///
     /// # Arguments
     /// **x (u8)**: x coordinate of the pixel on the screen
     /// **y (u8)**: y coordinate of the pixel on the screen
     ///
     /// # Returns
-    /// **u8**: Color of the given pixel from the window or 4 if the pixel is
-    /// out of the window
+    /// **u8**: Color id of the given pixel from the window or 4 if the pixel
+    /// is out of the window
     fn color_window(&self, x: u8, y: u8) -> u8 {
-        let (y_in_map, did_overflow_y) =
-            self.window_y_position.overflowing_add(y);
-        let (x_in_map_plus_sept, did_overflow_x) =
-            self.window_x_position_plus_sept.overflowing_add(x);
-        // If the window is outside of the screen, 4 is returned
-        if x_in_map_plus_sept < 7 ||
-           did_overflow_y ||
-           did_overflow_x ||
-           x_in_map_plus_sept > 167 ||
-           y_in_map > 144 {
+        let x_plus_sept = x as u16 + 7;
+        let window_x_plus_sept = self.window_x_position_plus_sept as u16;
+        // If the pixel is outside of the window, 4 is returned
+        if y < self.window_y_position ||
+           x_plus_sept < window_x_plus_sept ||
+           window_x_plus_sept > 166 {
             return 4;
         }
-        let x_in_map = x_in_map_plus_sept - 7;
+        let y_in_map = y - self.window_y_position;
+        let x_in_map = (x_plus_sept - window_x_plus_sept) as u8;
         // Position of the tile when reading line by line from left to right
         let tile_in_map =
             (x_in_map >> 3) as usize +
             (y_in_map >> 3) as usize * 32;
         // This position is the index in the window tile map which is a
         // list of byte identifying each tile of the window.
-        let tile_index = self.ram[
-            self.window_tile_map() as usize +
-            tile_in_map
-        ]; 
+        let tile_index = self.read_ram(
+            self.window_tile_map() + tile_in_map as u16
+        );
         // The id found above correspond to one of the tile of the background
         // and window tile data (each tile is 16 bytes)
         let tile_address = if tile_index < 128 {
             self.bg_and_window_tile_data_area() + ((tile_index as u16) << 4)
         } else {
-            0x8800 + ((tile_index as u16) << 4)
+            // Tiles 128-255 are shared by both areas (0x8800-0x8FFF)
+            0x8000 + ((tile_index as u16) << 4)
         };
         // The color is is then found for this tile for the correct pixel. Each
         // tile is 8x8 pixels.
-        let color_id = self.color_id_in_tile(
+        self.color_id_in_tile(
            tile_address,
            y_in_map & 0x07,
            x_in_map & 0x07
-        );
-        // This color id is a color of the palette of the background
-        (self.bg_palette_data >> (color_id * 2)) & 0x03
+        )
     }
 
     /// Returns the color of the pixel on the screen
@@ -614,107 +1423,999 @@ impl Gpu {
         y: u8,
         obj_in_line: &[u32]
     ) -> u8 {
-        // Color of the background for this pixel
-        let color_from_background = self.color_background(x, y);
-        // Color of the window for this pixel
+        // Color id of the background for this pixel
+        let color_from_background = if self.is_background_layer_enabled {
+            self.color_background(x, y)
+        } else {
+            0
+        };
+        // Color id of the window for this pixel
         let color_from_window = self.color_window(x, y);
-        // Color of the objects for this pixel
+        // Shade of the objects for this pixel, already mapped through their
+        // palette: only their color id decides the transparency
         let mut has_priority: bool = false;
-        let mut x_position: u8 = 0xFF;
-        let mut color_from_obj: u8 = 0;
+        let mut shade_from_obj: u8 = 0;
         let mut is_transparent: bool = true;
-        // Comparison of the objects
+        let obj_size = self.obj_size();
+        // The objects are sorted from the one drawn on top
         for i in obj_in_line.iter() {
-            let object = &self.object_attribute[
-                obj_in_line[*i as usize] as usize
-            ];
+            let object = &self.object_attribute[*i as usize];
+            // The object covers the columns x_position - 8 to x_position - 1
+            let x_in_object = x.wrapping_add(8).wrapping_sub(object.x_position);
             // If the object does not contain this pixel
-            if !(
-                object.x_position <= x &&
-                object.x_position + 8 > x
-            ) {
+            if x_in_object >= 8 {
                 continue;
             }
+            // The object covers the lines from y_position - 16, the flip
+            // applies to its whole height
+            let mut y_in_object = y.wrapping_add(16)
+                .wrapping_sub(object.y_position) % obj_size;
+            if object.get_y_flip() {
+                y_in_object = obj_size - 1 - y_in_object;
+            }
+            // In 8x16 mode, the low bit of the tile index is ignored: the top
+            // half uses the even tile and the bottom half the next one
+            let tile_index = if obj_size == 16 {
+                (object.tile_index & 0xFE) | (y_in_object >> 3)
+            } else {
+                object.tile_index
+            };
             // The tile_index is the index in the object tile data where each
             // tile is 16 bytes
-            let tile_for_obj = 0x8000 + ((object.tile_index as u16) << 4);
+            let tile_for_obj = 0x8000 + ((tile_index as u16) << 4);
             let color_id = self.color_id_in_tile(
                 tile_for_obj,
-                if object.get_y_flip() {
-                    (y - object.y_position) % 8
-                } else {
-                    (15 - (y + object.y_position)) % 8
-                },
+                y_in_object & 0x07,
                 if object.get_x_flip() {
-                    x - object.x_position
+                    7 - x_in_object
                 } else {
-                    7 - (x + object.x_position)
+                    x_in_object
                 },
             );
             // If the pixel is pixel for this object
             if color_id == 0 {
                 continue;
             }
-            // If not, we are certain to have found a non-transparent pixel
+            // If not, the first non-transparent object is on top
             is_transparent = false;
-            let current_has_priority = object.get_priority();
-            // If we have already found an object with higher priority
-            if has_priority && !current_has_priority {
-                continue;
-            }
-            // Object with a smaller x position have a higher priority
-            if x_position < object.x_position {
-                continue;
-            }
-            // If the loop iteration reach this point, the object is on top
-            has_priority = current_has_priority;
-            x_position = object.x_position;
-            color_from_obj = (if object.get_dmg_palette() {
+            has_priority = object.get_priority();
+            shade_from_obj = (if object.get_dmg_palette() {
                 self.obp1
             } else {
                 self.obp0
             } >> (2 * color_id)) & 0x3;
+            break;
         }
-        // We apply the rules to know what is on front
-        if !is_transparent && self.should_draw_objects() {
-            color_from_obj
-        } else if self.should_draw_window_and_background() {
-            if self.should_draw_window() && color_from_window != 4 {
+        let should_draw_objects =
+            self.should_draw_objects() && self.is_objects_layer_enabled;
+        let is_cgb = self.model == Model::Cgb;
+        // On DMG, the background and the window are blank when LCDC bit 0 is
+        // reset
+        if !is_cgb && !self.should_draw_window_and_background() {
+            return if !is_transparent && should_draw_objects {
+                shade_from_obj
+            } else {
+                0x00
+            };
+        }
+        let color_id_from_background =
+            if self.should_draw_window() &&
+                self.is_window_layer_enabled &&
+                color_from_window != 4 {
                 color_from_window
             } else {
                 color_from_background
-            }
+            };
+        // The priority depends on the color id of the background, not on the
+        // shade BGP maps it to.
+        // On CGB, the background loses its priority when LCDC bit 0 is reset
+        let background_on_top =
+            has_priority &&
+            color_id_from_background != 0 &&
+            self.should_draw_window_and_background();
+        // We apply the rules to know what is on front
+        if !is_transparent && should_draw_objects && !background_on_top {
+            shade_from_obj
         } else {
-            0x00
+            // This color id is a color of the palette of the background
+            (self.bg_palette_data >> (color_id_from_background * 2)) & 0x03
         }
     }
 
     /// Mode 2 of drawing a line
     ///
     /// During 80 dots, the ppu search up to 10 valid objects intersecting the
-    /// current y coordinate
+    /// current y coordinate (or up to max_sprites_per_line if it was changed)
     ///
     /// # Arguments
     /// **y (u8)**: Current y coordinate (as found at 0xFF40)
     ///
     /// # Returns
     /// **`Vec<u32>`**: Collections of the indices of objects found in the
-    /// current line
+    /// current line, from the one drawn on top
     fn objects_in_line(&self, y: u8) -> Vec<u32> {
+        let mut res = match self.object_buckets.get(y as usize) {
+            Some(bucket) if !self.are_object_buckets_stale => bucket.clone(),
+            _ => self.scan_objects_in_line(y),
+        };
+        // The sort is stable, so the OAM order decides in case of tie
+        if self.is_oam_x_priority() {
+            res.sort_by_key(|i| self.object_attribute[*i as usize].x_position);
+        }
+        res
+    }
+
+    /// Searches the objects intersecting a line in the whole OAM
+    ///
+    /// # Arguments
+    /// **y (u8)**: y coordinate of the line
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: Indices of the first objects found in the line, in the
+    /// OAM order
+    fn scan_objects_in_line(&self, y: u8) -> Vec<u32> {
         let mut res: Vec<u32> = vec![];
         let obj_size = self.obj_size();
         for i in 0..40 {
-            let y_position = self
-                .object_attribute[i]
-                .y_position
-                .wrapping_sub(16);
-            if y_position <= y && y_position + obj_size > y {
+            // The object covers the lines from y_position - 16
+            let y_in_object = y
+                .wrapping_add(16)
+                .wrapping_sub(self.object_attribute[i].y_position);
+            if y_in_object < obj_size {
                 res.push(i as u32);
-                if res.len() == 10 {
-                    return res;
+                if res.len() == self.max_sprites_per_line {
+                    break;
                 }
             }
         }
         res
     }
+
+    /// Sorts the objects of the OAM by visible line if the OAM was modified
+    ///
+    /// Each object is added to the lines it covers, until they contain
+    /// max_sprites_per_line objects.
+    fn refresh_object_buckets(&mut self) {
+        if !self.are_object_buckets_stale {
+            return;
+        }
+        self.are_object_buckets_stale = false;
+        for bucket in self.object_buckets.iter_mut() {
+            bucket.clear();
+        }
+        let obj_size = self.obj_size() as i16;
+        for (i, object) in self.object_attribute.iter().enumerate() {
+            let top = object.y_position as i16 - 16;
+            for y in top.max(0)..(top + obj_size).min(SCREEN_HEIGHT as i16) {
+                let bucket = &mut self.object_buckets[y as usize];
+                if bucket.len() < self.max_sprites_per_line {
+                    bucket.push(i as u32);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the border of a rectangle on a frame, clipped to the screen
+///
+/// # Arguments
+/// **frame (&mut [u32])**: Pixels of the screen, line by line (160 x 144)
+/// **x (i16)**: x coordinate of the left column of the rectangle
+/// **y (i16)**: y coordinate of the top line of the rectangle
+/// **width (i16)**: Number of columns of the rectangle
+/// **height (i16)**: Number of lines of the rectangle
+/// **color (u32)**: 0xRRGGBB color of the border
+fn draw_rectangle(
+    frame: &mut [u32],
+    x: i16,
+    y: i16,
+    width: i16,
+    height: i16,
+    color: u32
+) {
+    for j in y..y + height {
+        for i in x..x + width {
+            let is_border =
+                j == y || j == y + height - 1 || i == x || i == x + width - 1;
+            if !is_border ||
+                !(0..SCREEN_WIDTH as i16).contains(&i) ||
+                !(0..SCREEN_HEIGHT as i16).contains(&j) {
+                continue;
+            }
+            frame[j as usize * SCREEN_WIDTH + i as usize] = color;
+        }
+    }
+}
+
+impl Hash for Gpu {
+    /// Hashes the memory and registers of the GPU, but not its screen
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ram.hash(state);
+        self.object_attribute.hash(state);
+        self.lcd_control.hash(state);
+        self.lcd_status.hash(state);
+        self.background_viewport_y.hash(state);
+        self.background_viewport_x.hash(state);
+        self.lcd_y_coordinate.hash(state);
+        self.window_y_position.hash(state);
+        self.window_x_position_plus_sept.hash(state);
+        self.lyc_compare.hash(state);
+        self.bg_palette_data.hash(state);
+        self.obp0.hash(state);
+        self.obp1.hash(state);
+        self.pending_stat_interrupt.hash(state);
+        self.stat_line.hash(state);
+        self.pending_vblank_interrupt.hash(state);
+        self.cpu_cycle.hash(state);
+        self.model.hash(state);
+        self.vram_bank.hash(state);
+    }
+}
+
+impl Default for Gpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::state_hasher::StateHasher;
+
+    #[test]
+    fn test_max_sprites_per_line() {
+        let mut gpu = Gpu::headless();
+        // 12 objects on the line 0 (y_position is the line plus 16)
+        for i in 0..12 {
+            gpu.object_attribute[i].y_position = 16;
+            gpu.object_attribute[i].x_position = 8 * i as u8;
+        }
+        assert_eq!(gpu.objects_in_line(0).len(), 10);
+        gpu.set_max_sprites_per_line(40);
+        assert_eq!(gpu.objects_in_line(0).len(), 12);
+    }
+
+    #[test]
+    fn test_scroll_and_window_position() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x42, 0x12);
+        gpu.write_lcd(0x43, 0x34);
+        gpu.write_lcd(0x4A, 0x20);
+        gpu.write_lcd(0x4B, 0x0F);
+        assert_eq!(gpu.scroll(), (0x34, 0x12));
+        // WX is the x position of the window plus 7
+        assert_eq!(gpu.window_position(), (0x08, 0x20));
+    }
+
+    /// Creates a GPU with a background of color id 3 and an object of color
+    /// id 3 behind the background at the top left corner of the screen
+    fn gpu_with_object_behind_background(model: Model) -> Gpu {
+        let mut gpu = Gpu::headless();
+        gpu.set_model(model);
+        // Tile 0 (background) and 1 (object) only use color id 3
+        for address in 0x8000..0x8020 {
+            gpu.write_ram(address, 0xFF);
+        }
+        gpu.write_oam(0xFE00, 16);
+        gpu.write_oam(0xFE01, 8);
+        gpu.write_oam(0xFE02, 1);
+        gpu.write_oam(0xFE03, 0x80);
+        // Identity palette for the background, id 3 is shade 1 for objects
+        gpu.write_lcd(0x47, 0xE4);
+        gpu.write_lcd(0x48, 0x54);
+        gpu
+    }
+
+    #[test]
+    fn test_mode() {
+        let mut gpu = Gpu::headless();
+        assert_eq!(gpu.mode(), PpuMode::HBlank);
+        gpu.switch_mode_to(3);
+        assert_eq!(gpu.mode(), PpuMode::Drawing);
+        gpu.switch_mode_to(1);
+        assert_eq!(gpu.mode(), PpuMode::VBlank);
+    }
+
+    #[test]
+    fn test_set_ly() {
+        let mut gpu = Gpu::headless();
+        gpu.switch_mode_to(3);
+        // Interruption when LY == LYC
+        gpu.write_lcd(0x41, 0x43);
+        gpu.write_lcd(0x45, 0x42);
+        gpu.set_ly(0x41);
+        assert_eq!(gpu.read_lcd(0x44), 0x41);
+        assert_eq!(gpu.read_lcd(0x41) & 0x04, 0x00);
+        assert!(!gpu.pending_stat_interrupt);
+        gpu.set_ly(0x42);
+        assert_eq!(gpu.read_lcd(0x41) & 0x04, 0x04);
+        assert!(gpu.pending_stat_interrupt);
+    }
+
+    #[test]
+    fn test_mode_trace() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x91);
+        gpu.update(DOTS_PER_LINE);
+        assert!(gpu.take_mode_trace().is_empty());
+        gpu.set_mode_trace(true);
+        gpu.update(DOTS_PER_LINE);
+        let trace = gpu.take_mode_trace();
+        let modes: Vec<PpuMode> = trace.iter().map(|t| t.mode).collect();
+        assert_eq!(
+            modes,
+            vec![PpuMode::OamScan, PpuMode::Drawing, PpuMode::HBlank]
+        );
+        let dots: Vec<u32> = trace.iter().map(|t| t.dot).collect();
+        assert_eq!(dots, vec![0, 80, 252]);
+        assert!(trace.iter().all(|t| t.ly == 1));
+        assert!(gpu.take_mode_trace().is_empty());
+    }
+
+    #[test]
+    fn test_lcd_enable_restarts_frame() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x47, 0xE4);
+        gpu.write_lcd(0x40, 0x91);
+        gpu.update(DOTS_PER_LINE * 50 + 100);
+        assert_eq!(gpu.read_lcd(0x44), 50);
+        // Switched off mid-frame, LY stays at 0
+        gpu.write_lcd(0x40, 0x11);
+        assert_eq!(gpu.read_lcd(0x44), 0);
+        assert_eq!(gpu.mode(), PpuMode::HBlank);
+        gpu.update(DOTS_PER_LINE * 10);
+        assert_eq!(gpu.read_lcd(0x44), 0);
+        // Every tile of the background is black from now on
+        gpu.set_tile(0, [0xFF; 16]);
+        let frame_count = gpu.frame_count();
+        gpu.write_lcd(0x40, 0x91);
+        assert_eq!(gpu.read_lcd(0x44), 0);
+        gpu.update(DOTS_PER_LINE * 144 - 1);
+        assert_eq!(gpu.read_lcd(0x44), 143);
+        assert_eq!(gpu.frame_count(), frame_count);
+        gpu.update(1);
+        assert_eq!(gpu.read_lcd(0x44), 144);
+        assert_eq!(gpu.frame_count(), frame_count + 1);
+        assert!(gpu.framebuffer().iter().all(|color| *color == 0x000000));
+    }
+
+    #[test]
+    fn test_oam_out_of_range() {
+        let mut gpu = Gpu::headless();
+        gpu.write_oam(0xFE9F, 0x42);
+        assert_eq!(gpu.read_oam(0xFE9F), 0x42);
+        for address in 0xFEA0..=0xFEFF {
+            gpu.write_oam(address, 0x00);
+            assert_eq!(gpu.read_oam(address), 0xFF);
+        }
+        assert_eq!(gpu.read_oam(0xFE9F), 0x42);
+        assert_eq!(gpu.objects().len(), 40);
+    }
+
+    #[test]
+    fn test_stat_bit_7() {
+        let mut gpu = Gpu::headless();
+        assert_eq!(gpu.read_lcd(0x41), 0x80);
+        for value in [0x00, 0x7F, 0x80, 0xFF] {
+            gpu.write_lcd(0x41, value);
+            assert_eq!(gpu.read_lcd(0x41) & 0x80, 0x80);
+        }
+        gpu.write_lcd(0x41, 0x40);
+        assert_eq!(gpu.read_lcd(0x41), 0xC0);
+    }
+
+    #[test]
+    fn test_stat_rising_edge() {
+        let mut gpu = Gpu::headless();
+        gpu.switch_mode_to(3);
+        // Interruptions for the modes 2 and 0, the mode is read only
+        gpu.write_lcd(0x41, 0x28);
+        assert_eq!(gpu.read_lcd(0x41), 0xAB);
+        assert!(!gpu.pending_stat_interrupt);
+        gpu.switch_mode_to(2);
+        assert!(gpu.pending_stat_interrupt);
+        gpu.pending_stat_interrupt = false;
+        // The line falls in mode 3 and rises again in mode 0
+        gpu.switch_mode_to(3);
+        assert!(!gpu.pending_stat_interrupt);
+        gpu.switch_mode_to(0);
+        assert!(gpu.pending_stat_interrupt);
+        gpu.pending_stat_interrupt = false;
+        // From mode 0 to mode 2, the line stays high
+        gpu.switch_mode_to(2);
+        assert!(!gpu.pending_stat_interrupt);
+        // lyc == ly while in mode 2 does not request another interruption
+        gpu.write_lcd(0x41, 0x60);
+        gpu.lcd_y_coordinate = 1;
+        gpu.write_lcd(0x45, 1);
+        assert_eq!(gpu.read_lcd(0x41), 0xE6);
+        assert!(gpu.stat_line);
+        assert!(!gpu.pending_stat_interrupt);
+        // The LYC flag is read only
+        gpu.write_lcd(0x41, 0x40);
+        assert_eq!(gpu.read_lcd(0x41), 0xC6);
+        // Writing STAT during VBlank keeps the mode 1 and its condition
+        gpu.switch_mode_to(3);
+        gpu.write_lcd(0x45, 0);
+        gpu.write_lcd(0x41, 0x10);
+        gpu.switch_mode_to(1);
+        assert!(gpu.pending_stat_interrupt);
+        gpu.pending_stat_interrupt = false;
+        gpu.write_lcd(0x41, 0x10);
+        assert_eq!(gpu.mode(), PpuMode::VBlank);
+        assert!(gpu.stat_line);
+        gpu.switch_mode_to(2);
+        assert!(!gpu.pending_stat_interrupt);
+        // Once the line fell, lyc == ly requests one interruption per frame
+        gpu.switch_mode_to(3);
+        gpu.write_lcd(0x45, 1);
+        gpu.write_lcd(0x41, 0x40);
+        gpu.write_lcd(0x40, 0x80);
+        let mut n_stat = 0;
+        for _ in 0..(DOTS_PER_LINE * LINES_PER_FRAME as u32 / 4) {
+            gpu.update(4);
+            if gpu.pending_stat_interrupt {
+                assert_eq!(gpu.read_lcd(0x44), 1);
+                gpu.pending_stat_interrupt = false;
+                n_stat += 1;
+            }
+        }
+        assert_eq!(n_stat, 1);
+    }
+
+    #[test]
+    fn test_one_vblank_per_frame() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x80);
+        let mut n_vblank = 0;
+        for _ in 0..(DOTS_PER_LINE * LINES_PER_FRAME as u32 / 4) {
+            gpu.update(4);
+            if gpu.pending_vblank_interrupt {
+                assert_eq!(gpu.read_lcd(0x44), 144);
+                assert_eq!(gpu.mode(), PpuMode::VBlank);
+                gpu.pending_vblank_interrupt = false;
+                n_vblank += 1;
+            }
+        }
+        assert_eq!(n_vblank, 1);
+        // The frame is over and the next one begins
+        assert_eq!(gpu.read_lcd(0x44), 0);
+    }
+
+    #[test]
+    fn test_set_tile_and_bg_map_entry() {
+        let mut gpu = Gpu::headless();
+        // LCD and background on, tiles from 0x8000, map at 0x9800
+        gpu.write_lcd(0x40, 0x91);
+        gpu.write_lcd(0x47, 0xE4);
+        // Color id 1 on the first line, color id 2 on the others
+        let mut data = [0x00; 16];
+        data[0] = 0xFF;
+        for line in 1..8 {
+            data[2 * line + 1] = 0xFF;
+        }
+        gpu.set_tile(1, data);
+        gpu.set_tile(0x80, data);
+        gpu.set_bg_map_entry(1, 0, 1);
+        gpu.set_bg_map_entry(2, 0, 0x80);
+        for _ in 0..LINES_PER_FRAME {
+            gpu.update(DOTS_PER_LINE);
+        }
+        let frame = gpu.capture_frame();
+        for x in 8..24 {
+            assert_eq!(frame[x], 0xAAAAAA);
+            for y in 1..8 {
+                assert_eq!(frame[y * 160 + x], 0x555555);
+            }
+        }
+        assert_eq!(frame[0], 0xFFFFFF);
+        assert_eq!(frame[24], 0xFFFFFF);
+        assert_eq!(frame[8 * 160 + 8], 0xFFFFFF);
+    }
+
+    #[test]
+    fn test_tile_cache() {
+        let mut gpu = Gpu::headless();
+        assert_eq!(gpu.color_id_in_tile(0x8010, 1, 0), 0);
+        // Second line of the tile 1
+        gpu.write_ram(0x8012, 0x80);
+        assert_eq!(gpu.color_id_in_tile(0x8010, 1, 0), 1);
+        gpu.write_ram(0x8013, 0x80);
+        assert_eq!(gpu.color_id_in_tile(0x8010, 1, 0), 3);
+        // The other lines are not affected
+        assert_eq!(gpu.color_id_in_tile(0x8010, 0, 0), 0);
+        assert_eq!(gpu.color_id_in_tile(0x8010, 2, 0), 0);
+    }
+
+    #[test]
+    fn test_oam_bytes() {
+        let mut gpu = Gpu::headless();
+        let bytes: Vec<u8> = (0..160).map(|i| (i * 7) as u8).collect();
+        gpu.load_oam_bytes(&bytes);
+        assert_eq!(gpu.objects()[1].y_position, 28);
+        assert_eq!(gpu.objects()[1].flags, 49);
+        let mut restored = Gpu::headless();
+        restored.load_oam_bytes(&gpu.oam_bytes());
+        assert_eq!(restored.oam_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_vram_banks() {
+        let mut gpu = Gpu::headless();
+        assert_eq!(gpu.vram().len(), 0x2000);
+        gpu.write_vram_bank(0x01);
+        assert_eq!(gpu.read_vram_bank(), 0xFF);
+        gpu.set_model(Model::Cgb);
+        assert_eq!(gpu.vram().len(), 0x4000);
+        assert_eq!(gpu.read_vram_bank(), 0xFE);
+        gpu.write_ram(0x8000, 0x12);
+        gpu.write_vram_bank(0x01);
+        assert_eq!(gpu.read_vram_bank(), 0xFF);
+        assert_eq!(gpu.read_ram(0x8000), 0x00);
+        gpu.write_ram(0x8000, 0x34);
+        gpu.write_ram(0x9FFF, 0x56);
+        assert_eq!(gpu.read_ram(0x8000), 0x34);
+        gpu.write_vram_bank(0x00);
+        assert_eq!(gpu.read_ram(0x8000), 0x12);
+        assert_eq!(gpu.read_ram(0x9FFF), 0x00);
+        assert_eq!(gpu.vram()[0x2000], 0x34);
+        assert_eq!(gpu.vram()[0x3FFF], 0x56);
+        // The tiles drawn come from the bank 0
+        assert_eq!(gpu.tile_rows[0], [0, 0, 0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_vram_mut() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x91);
+        gpu.vram_mut()[0x0010] = 0xFF;
+        assert_eq!(gpu.vram()[0x0010], 0xFF);
+        assert_eq!(gpu.read_ram(0x8010), 0xFF);
+        // The tile cache is refreshed before drawing
+        gpu.update(DOTS_PER_LINE);
+        assert_eq!(gpu.color_id_in_tile(0x8010, 0, 0), 1);
+    }
+
+    /// Creates a GPU whose window only uses a tile with color id 1 in its
+    /// first column and color id 2 in its last column
+    fn gpu_with_window() -> Gpu {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0xB1);
+        for line in 0..8 {
+            gpu.write_ram(0x8000 + 2 * line, 0x80);
+            gpu.write_ram(0x8001 + 2 * line, 0x01);
+        }
+        gpu
+    }
+
+    #[test]
+    fn test_window_top_left() {
+        let mut gpu = gpu_with_window();
+        gpu.write_lcd(0x4B, 7);
+        gpu.write_lcd(0x4A, 0);
+        assert_eq!(gpu.color_window(0, 0), 1);
+        assert_eq!(gpu.color_window(7, 0), 2);
+        gpu.write_lcd(0x4A, 10);
+        assert_eq!(gpu.color_window(0, 9), 4);
+        assert_eq!(gpu.color_window(0, 10), 1);
+    }
+
+    #[test]
+    fn test_window_wx_0() {
+        let mut gpu = gpu_with_window();
+        gpu.write_lcd(0x4B, 0);
+        // The first 7 columns of the window are hidden
+        assert_eq!(gpu.color_window(0, 0), 2);
+        assert_eq!(gpu.color_window(1, 0), 1);
+    }
+
+    #[test]
+    fn test_window_wx_166() {
+        let mut gpu = gpu_with_window();
+        gpu.write_lcd(0x4B, 166);
+        // Only the last column of the screen shows the window
+        assert_eq!(gpu.color_window(158, 0), 4);
+        assert_eq!(gpu.color_window(159, 0), 1);
+        gpu.write_lcd(0x4B, 167);
+        assert_eq!(gpu.color_window(159, 0), 4);
+    }
+
+    #[test]
+    fn test_lcdc_bit_0_dmg() {
+        let mut gpu = gpu_with_object_behind_background(Model::Dmg);
+        let obj_in_line = gpu.objects_in_line(0);
+        gpu.write_lcd(0x40, 0x93);
+        assert_eq!(gpu.draw_pixel(20, 0, &obj_in_line), 3);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 3);
+        // The background is blank and the object is drawn over it
+        gpu.write_lcd(0x40, 0x92);
+        assert_eq!(gpu.draw_pixel(20, 0, &obj_in_line), 0);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 1);
+    }
+
+    #[test]
+    fn test_lcdc_bit_0_cgb() {
+        let mut gpu = gpu_with_object_behind_background(Model::Cgb);
+        let obj_in_line = gpu.objects_in_line(0);
+        gpu.write_lcd(0x40, 0x93);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 3);
+        // The background is still drawn but the object is drawn over it
+        gpu.write_lcd(0x40, 0x92);
+        assert_eq!(gpu.draw_pixel(20, 0, &obj_in_line), 3);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 1);
+    }
+
+    #[test]
+    fn test_priority_uses_background_color_id() {
+        let mut gpu = gpu_with_object_behind_background(Model::Dmg);
+        gpu.write_lcd(0x40, 0x93);
+        // BGP maps the id 0 to the darkest shade and the id 3 to white
+        gpu.write_lcd(0x47, 0x03);
+        let obj_in_line = gpu.objects_in_line(0);
+        // The object hides behind the id 3 of the background, even if white
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 0);
+        // The object is drawn over the id 0 of the background, even if black
+        for address in 0x8000..0x8010 {
+            gpu.write_ram(address, 0x00);
+        }
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 1);
+        assert_eq!(gpu.draw_pixel(20, 0, &obj_in_line), 3);
+    }
+
+    #[test]
+    fn test_layer_enabled() {
+        let mut gpu = gpu_with_object_behind_background(Model::Dmg);
+        // The object is drawn over the background
+        gpu.write_oam(0xFE03, 0x00);
+        gpu.write_lcd(0x40, 0x93);
+        let obj_in_line = gpu.objects_in_line(0);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 1);
+        gpu.set_layer_enabled(Layer::Objects, false);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 3);
+        gpu.set_layer_enabled(Layer::Background, false);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 0);
+        gpu.set_layer_enabled(Layer::Objects, true);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 1);
+    }
+
+    /// Creates a GPU with two overlapping objects on the first line, the
+    /// first one in the OAM of shade 1 at x = 4 and the second one of shade
+    /// 2 at x = 2
+    fn gpu_with_overlapping_objects() -> Gpu {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x82);
+        for address in 0x8000..0x8010 {
+            gpu.write_ram(address, 0xFF);
+        }
+        gpu.write_lcd(0x48, 0x54);
+        gpu.write_lcd(0x49, 0xA8);
+        gpu.write_oam(0xFE00, 16);
+        gpu.write_oam(0xFE01, 12);
+        gpu.write_oam(0xFE04, 16);
+        gpu.write_oam(0xFE05, 10);
+        gpu.write_oam(0xFE07, 0x10);
+        gpu
+    }
+
+    #[test]
+    fn test_oam_x_priority_dmg() {
+        let mut gpu = gpu_with_overlapping_objects();
+        let obj_in_line = gpu.objects_in_line(0);
+        assert_eq!(obj_in_line, vec![1, 0]);
+        // The second object has a smaller x
+        assert_eq!(gpu.draw_pixel(2, 0, &obj_in_line), 2);
+        assert_eq!(gpu.draw_pixel(4, 0, &obj_in_line), 2);
+        assert_eq!(gpu.draw_pixel(10, 0, &obj_in_line), 1);
+        // With the same x, the first object in the OAM wins
+        gpu.write_oam(0xFE05, 12);
+        let obj_in_line = gpu.objects_in_line(0);
+        assert_eq!(gpu.draw_pixel(4, 0, &obj_in_line), 1);
+    }
+
+    #[test]
+    fn test_oam_x_priority_cgb() {
+        let mut gpu = gpu_with_overlapping_objects();
+        gpu.set_oam_x_priority(false);
+        let obj_in_line = gpu.objects_in_line(0);
+        // The first object in the OAM wins
+        assert_eq!(gpu.draw_pixel(2, 0, &obj_in_line), 2);
+        assert_eq!(gpu.draw_pixel(4, 0, &obj_in_line), 1);
+        assert_eq!(gpu.draw_pixel(10, 0, &obj_in_line), 1);
+    }
+
+    #[test]
+    fn test_oam_priority_follows_model() {
+        let mut gpu = gpu_with_overlapping_objects();
+        gpu.set_model(Model::Cgb);
+        let obj_in_line = gpu.objects_in_line(0);
+        assert_eq!(obj_in_line, vec![0, 1]);
+        // The first object in the OAM wins even with a greater x
+        assert_eq!(gpu.draw_pixel(4, 0, &obj_in_line), 1);
+        gpu.set_model(Model::Dmg);
+        let obj_in_line = gpu.objects_in_line(0);
+        assert_eq!(obj_in_line, vec![1, 0]);
+        assert_eq!(gpu.draw_pixel(4, 0, &obj_in_line), 2);
+        // The priority forced by set_oam_x_priority ignores the model
+        gpu.set_oam_x_priority(true);
+        gpu.set_model(Model::Cgb);
+        let obj_in_line = gpu.objects_in_line(0);
+        assert_eq!(gpu.draw_pixel(4, 0, &obj_in_line), 2);
+    }
+
+    #[test]
+    fn test_headless_framebuffer() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x91);
+        gpu.write_lcd(0x47, 0xE4);
+        // Diagonal stripes of the four shades
+        let mut data = [0x00; 16];
+        for line in 0..8 {
+            data[2 * line] = 0x33 << (line % 4);
+            data[2 * line + 1] = 0x0F << (line % 4);
+        }
+        gpu.set_tile(1, data);
+        for x in 0..32 {
+            for y in 0..32 {
+                if (x + y) % 3 == 0 {
+                    gpu.set_bg_map_entry(x, y, 1);
+                }
+            }
+        }
+        gpu.update(DOTS_PER_LINE * 100);
+        // Nothing is presented before the end of the frame
+        assert!(gpu.framebuffer().iter().all(|color| *color == 0xFFFFFF));
+        gpu.update(DOTS_PER_LINE * 44);
+        for shade in [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000] {
+            assert!(gpu.framebuffer().contains(&shade));
+        }
+        let mut hasher = StateHasher::new();
+        gpu.framebuffer().hash(&mut hasher);
+        assert_eq!(hasher.finish(), 0x918E07AA5C8B2007);
+    }
+
+    /// Creates a GPU with an 8x16 object at the top left corner of the
+    /// screen, whose top tile has the color id 3 on its first line and 1
+    /// elsewhere, and whose bottom tile has the color id 3 on its last line
+    /// and 2 elsewhere
+    fn gpu_with_tall_object() -> Gpu {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x86);
+        gpu.write_lcd(0x48, 0xE4);
+        let mut top = [0x00; 16];
+        let mut bottom = [0x00; 16];
+        for line in 0..8 {
+            top[2 * line] = 0xFF;
+            bottom[2 * line + 1] = 0xFF;
+        }
+        top[1] = 0xFF;
+        bottom[14] = 0xFF;
+        gpu.set_tile(2, top);
+        gpu.set_tile(3, bottom);
+        gpu.write_oam(0xFE00, 16);
+        gpu.write_oam(0xFE01, 8);
+        // The low bit of the tile index is ignored
+        gpu.write_oam(0xFE02, 3);
+        gpu
+    }
+
+    #[test]
+    fn test_tall_object_top_half() {
+        let mut gpu = gpu_with_tall_object();
+        let obj_in_line = gpu.objects_in_line(0);
+        assert_eq!(gpu.draw_pixel(0, 0, &obj_in_line), 3);
+        let obj_in_line = gpu.objects_in_line(7);
+        assert_eq!(gpu.draw_pixel(0, 7, &obj_in_line), 1);
+    }
+
+    #[test]
+    fn test_tall_object_bottom_half() {
+        let mut gpu = gpu_with_tall_object();
+        let obj_in_line = gpu.objects_in_line(8);
+        assert_eq!(gpu.draw_pixel(0, 8, &obj_in_line), 2);
+        let obj_in_line = gpu.objects_in_line(15);
+        assert_eq!(gpu.draw_pixel(0, 15, &obj_in_line), 3);
+        assert!(gpu.objects_in_line(16).is_empty());
+    }
+
+    #[test]
+    fn test_tall_object_y_flip() {
+        let mut gpu = gpu_with_tall_object();
+        gpu.write_oam(0xFE03, 0x40);
+        for (y, color) in [(0, 3), (1, 2), (7, 2), (8, 1), (14, 1), (15, 3)] {
+            let obj_in_line = gpu.objects_in_line(y);
+            assert_eq!(gpu.draw_pixel(0, y, &obj_in_line), color);
+        }
+    }
+
+    #[test]
+    fn test_object_buckets() {
+        let mut gpu = Gpu::headless();
+        // Pseudo-random OAM, concentrated on the top of the screen so that
+        // some lines contain more than 10 objects
+        let mut seed: u32 = 0x1234_5678;
+        for address in 0xFE00..=0xFE9F {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let value = (seed >> 16) as u8;
+            let value = if address & 0x03 == 0 { value % 48 } else { value };
+            gpu.write_oam(address, value);
+        }
+        for lcd_control in [0x80, 0x84] {
+            gpu.write_lcd(0x40, lcd_control);
+            gpu.refresh_object_buckets();
+            assert!(!gpu.are_object_buckets_stale);
+            for y in 0..SCREEN_HEIGHT as u8 {
+                let cached = gpu.objects_in_line(y);
+                gpu.are_object_buckets_stale = true;
+                let scanned = gpu.objects_in_line(y);
+                gpu.are_object_buckets_stale = false;
+                assert_eq!(cached, scanned, "line {} LCDC {:#04x}", y, lcd_control);
+            }
+            assert!(gpu.object_buckets.iter().any(|bucket| bucket.len() == 10));
+        }
+        // A write to the OAM invalidates the buckets
+        gpu.write_oam(0xFE00, 0x20);
+        assert!(gpu.are_object_buckets_stale);
+    }
+
+    #[test]
+    fn test_color_mapper() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x91);
+        gpu.write_lcd(0x47, 0xE4);
+        gpu.set_color_mapper(|_| 0x123456);
+        gpu.update(DOTS_PER_LINE * LINES_PER_FRAME as u32);
+        assert!(gpu.framebuffer().iter().all(|color| *color == 0x123456));
+        // The mapper can change the colors from one frame to the next
+        let mut level = 0;
+        gpu.set_color_mapper(move |_| {
+            level = (level + 1) % 0x100;
+            level * 0x010101
+        });
+        gpu.update(DOTS_PER_LINE * LINES_PER_FRAME as u32);
+        assert_ne!(gpu.framebuffer()[0], gpu.framebuffer()[1]);
+        gpu.clear_color_mapper();
+        gpu.update(DOTS_PER_LINE * LINES_PER_FRAME as u32);
+        assert!(gpu.framebuffer().iter().all(|color| *color == 0xFFFFFF));
+    }
+
+    #[test]
+    fn test_render_tileset() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x47, 0xE4);
+        // Tile 17 (second line, second column) only uses the color id 3
+        gpu.set_tile(17, [0xFF; 16]);
+        let tileset = gpu.render_tileset();
+        assert_eq!(tileset.len(), 128 * 192);
+        for (i, color) in tileset.iter().enumerate() {
+            let (x, y) = (i % TILESET_WIDTH, i / TILESET_WIDTH);
+            let is_tile_17 = (8..16).contains(&x) && (8..16).contains(&y);
+            assert_eq!(*color, if is_tile_17 { 0x000000 } else { 0xFFFFFF });
+        }
+    }
+
+    #[test]
+    fn test_render_tileset_cgb() {
+        let mut gpu = Gpu::headless();
+        gpu.set_model(Model::Cgb);
+        gpu.write_lcd(0x47, 0xE4);
+        // Tile 1 of the bank 1 only uses the color id 3
+        gpu.write_vram_bank(0x01);
+        for address in 0x8010..0x8020 {
+            gpu.write_ram(address, 0xFF);
+        }
+        assert_eq!(gpu.tileset_width(), 2 * TILESET_WIDTH);
+        let tileset = gpu.render_tileset();
+        assert_eq!(tileset.len(), 256 * 192);
+        for (i, color) in tileset.iter().enumerate() {
+            let (x, y) = (i % 256, i / 256);
+            let is_tile_1 = (136..144).contains(&x) && y < 8;
+            assert_eq!(*color, if is_tile_1 { 0x000000 } else { 0xFFFFFF });
+        }
+    }
+
+    #[test]
+    fn test_render_tilemap_with_viewport() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x91);
+        gpu.write_lcd(0x47, 0xE4);
+        // The tile 1 is drawn in the second column of the second line
+        gpu.set_tile(1, [0xFF; 16]);
+        gpu.write_ram(0x9800 + 33, 0x01);
+        let tilemap = gpu.render_tilemap();
+        assert_eq!(tilemap.len(), TILEMAP_SIZE * TILEMAP_SIZE);
+        assert_eq!(tilemap[8 * TILEMAP_SIZE + 8], 0x000000);
+        assert_eq!(tilemap[15 * TILEMAP_SIZE + 15], 0x000000);
+        assert_eq!(tilemap[16 * TILEMAP_SIZE + 16], 0xFFFFFF);
+        // The viewport wraps around both edges of the map
+        gpu.write_lcd(0x43, 200);
+        gpu.write_lcd(0x42, 180);
+        let tilemap = gpu.render_tilemap_with_viewport();
+        let is_border = |x: usize, y: usize| {
+            tilemap[y * TILEMAP_SIZE + x] == DEBUG_VIEWPORT_COLOR
+        };
+        // Top left, top right, bottom left and bottom right corners
+        assert!(is_border(200, 180));
+        assert!(is_border((200 + 159) % 256, 180));
+        assert!(is_border(200, (180 + 143) % 256));
+        assert!(is_border((200 + 159) % 256, (180 + 143) % 256));
+        // Wrapped top line and left column
+        assert!(is_border(0, 180));
+        assert!(is_border(200, 0));
+        assert!(!is_border(150, 100));
+        assert!(!is_border(8, 8));
+        let n_border_pixels = tilemap
+            .iter()
+            .filter(|color| **color == DEBUG_VIEWPORT_COLOR)
+            .count();
+        assert_eq!(n_border_pixels, 2 * 160 + 2 * 144 - 4);
+    }
+
+    #[cfg(feature = "recording")]
+    #[test]
+    fn test_save_tileset_png() {
+        let gpu = Gpu::headless();
+        let path = std::env::temp_dir().join("gb_emulator_rust_tileset.png");
+        gpu.save_tileset_png(&path).unwrap();
+        let image = image::open(&path).unwrap();
+        assert_eq!(image.width(), TILESET_WIDTH as u32);
+        assert_eq!(image.height(), TILESET_HEIGHT as u32);
+        std::fs::remove_file(&path).unwrap();
+        // Both banks side by side on CGB
+        let mut gpu = Gpu::headless();
+        gpu.set_model(Model::Cgb);
+        gpu.save_tileset_png(&path).unwrap();
+        let image = image::open(&path).unwrap();
+        assert_eq!(image.width(), 2 * TILESET_WIDTH as u32);
+        assert_eq!(image.height(), TILESET_HEIGHT as u32);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_render_debug_overlay() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x91);
+        // An object whose top left corner is at (20, 30)
+        gpu.write_oam(0xFE00, 46);
+        gpu.write_oam(0xFE01, 28);
+        for _ in 0..LINES_PER_FRAME {
+            gpu.update(DOTS_PER_LINE);
+        }
+        let overlay = gpu.render_debug_overlay();
+        let frame = gpu.capture_frame();
+        for x in 0..160 {
+            assert_ne!(overlay[x], frame[x]);
+            assert_ne!(overlay[143 * 160 + x], frame[143 * 160 + x]);
+        }
+        for y in 0..144 {
+            assert_ne!(overlay[y * 160], frame[y * 160]);
+            assert_ne!(overlay[y * 160 + 159], frame[y * 160 + 159]);
+        }
+        assert_eq!(overlay[30 * 160 + 20], DEBUG_OBJECT_COLOR);
+        assert_eq!(overlay[37 * 160 + 27], DEBUG_OBJECT_COLOR);
+        assert_eq!(overlay[31 * 160 + 21], frame[31 * 160 + 21]);
+        // The real output is not modified
+        assert!(frame.iter().all(|color| *color == 0xFFFFFF));
+    }
+
+    #[test]
+    fn test_one_frame_per_70224_dots() {
+        let mut gpu = Gpu::headless();
+        gpu.write_lcd(0x40, 0x80);
+        let dots_per_frame = DOTS_PER_LINE * LINES_PER_FRAME as u32;
+        assert_eq!(dots_per_frame, 70224);
+        gpu.update(dots_per_frame - 1);
+        assert!(gpu.frame_ready());
+        assert_eq!(gpu.read_lcd(0x44), 153);
+        gpu.capture_frame();
+        gpu.update(1);
+        assert!(!gpu.frame_ready());
+        assert_eq!(gpu.read_lcd(0x44), 0);
+        for _ in 0..3 {
+            gpu.update(dots_per_frame);
+            assert!(gpu.frame_ready());
+            assert_eq!(gpu.read_lcd(0x44), 0);
+            gpu.capture_frame();
+        }
+    }
 }
@@ -1,3 +1,6 @@
+use crate::state::ram_init::RamInit;
+
+#[derive(Hash)]
 /// Contains the data of the High ram
 pub struct Hram {
     ram: Vec<u8>
@@ -37,4 +40,12 @@ impl Hram {
     ) {
         self.ram[(address - 0xFF80) as usize] = value;
     }
+
+    /// Overwrite every byte of the HRAM with a pattern
+    ///
+    /// # Arguments
+    /// **pattern (RamInit)**: Values written in the HRAM
+    pub fn fill(&mut self, pattern: RamInit) {
+        pattern.fill(&mut self.ram);
+    }
 }
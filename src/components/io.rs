@@ -1,5 +1,7 @@
 use crate::state::key_state::KeyState;
+use crate::state::timer_state::TimerState;
 
+#[derive(Hash)]
 /// Contains the memory of the gameboy used to store time and user input
 pub struct IO {
     /// State of the buttons of the joypad
@@ -7,11 +9,12 @@ pub struct IO {
     /// start and down/select and up/b and left/a and right
     joypad_input: u8,
     /// Serial transfer (should not be used)
+    /// data/control
     serial_transfer: u16,
-    /// Divider
-    /// Mesure the time
-    divider: u8,
-    /// Number of cpu cycle elapsed to determin the timers
+    /// Every byte sent through the serial port
+    serial_output: Vec<u8>,
+    /// Internal divider: number of cpu cycle elapsed to determin the timers,
+    /// whose high byte is the divider register (0xFF04)
     cpu_cycle: u16,
     /// Timer counting the time
     timer_counter: u8,
@@ -41,7 +44,7 @@ impl IO {
         Self {
             joypad_input: 0x00,
             serial_transfer: 0x0000,
-            divider: 0x00,
+            serial_output: vec![],
             cpu_cycle: 0x0000,
             timer_counter: 0x00,
             timer_modulo: 0x00,
@@ -76,7 +79,7 @@ impl IO {
             },
             // Timer and divider
             0x04 => {
-                self.divider
+                (self.cpu_cycle >> 8) as u8
             },
             0x05 => {
                 self.timer_counter
@@ -128,11 +131,18 @@ impl IO {
                     (self.serial_transfer & 0xFF00) |
                     value as u16
                 ;
+                // A transfer using the internal clock sends the data at once
+                if value & 0x81 == 0x81 {
+                    self.serial_output.push(
+                        ((self.serial_transfer & 0xFF00) >> 8) as u8
+                    );
+                    self.serial_transfer &= 0xFF7F;
+                }
             },
             // Timer and divider
             // Writing any value to it will set it to 0.
             0x04 => {
-                self.divider = 0x00;
+                self.reset_divider();
             },
             0x05 => {
                 self.timer_counter = value;
@@ -153,14 +163,79 @@ impl IO {
         }
     }
 
+    /// Returns a copy of every IO register
+    ///
+    /// The internal state of the timer is copied by timer_state.
+    ///
+    /// # Returns
+    /// **[u8; 0x80]**: Value of the registers from 0xFF00 to 0xFF7F
+    pub fn snapshot(&self) -> [u8; 0x80] {
+        let mut res = [0x00; 0x80];
+        for (i, value) in res.iter_mut().enumerate() {
+            *value = self.read(0xFF00 + i as u16);
+        }
+        res
+    }
+
+    /// Returns a copy of the internal state of the timer
+    ///
+    /// # Returns
+    /// **TimerState**: Divider and flags of the timer
+    pub fn timer_state(&self) -> TimerState {
+        TimerState {
+            divider: self.cpu_cycle,
+            is_stopped: self.is_stopped,
+            pending_joypad_interruption: self.pending_joypad_interruption,
+            pending_timer_interruption: self.pending_timer_interruption,
+        }
+    }
+
+    /// Restores the internal state of the timer
+    ///
+    /// # Arguments
+    /// **state (&TimerState)**: State to restore, as returned by timer_state
+    pub fn restore_timer_state(&mut self, state: &TimerState) {
+        self.cpu_cycle = state.divider;
+        self.is_stopped = state.is_stopped;
+        self.pending_joypad_interruption = state.pending_joypad_interruption;
+        self.pending_timer_interruption = state.pending_timer_interruption;
+    }
+
+    /// Restores every IO register from a snapshot
+    ///
+    /// # Arguments
+    /// **snapshot (&[u8; 0x80])**: Value of the registers, as returned by
+    /// snapshot
+    pub fn restore(&mut self, snapshot: &[u8; 0x80]) {
+        self.other[..0x80].copy_from_slice(snapshot);
+        self.joypad_input = snapshot[0x00];
+        self.serial_transfer =
+            (snapshot[0x01] as u16) << 8 |
+            snapshot[0x02] as u16;
+        self.timer_counter = snapshot[0x05];
+        self.timer_modulo = snapshot[0x06];
+        self.timer_control = snapshot[0x07];
+        self.disable_boot_rom = snapshot[0x50];
+    }
+
+    /// Returns every byte sent through the serial port
+    ///
+    /// Test ROMs usually print their results on the serial port.
+    ///
+    /// # Returns
+    /// **&[u8]**: Bytes sent through the serial port, oldest first
+    pub fn serial_output(&self) -> &[u8] {
+        &self.serial_output
+    }
+
     /// Update the joypad byte with the given informations
     ///
     /// # Arguments
     /// **keys (&KeyState)**: Contains information about what key is being
     /// pushed
     fn listen_for_buttons(&mut self, keys: &KeyState) {
-        // Was a button already being pushed
-        let was_pushed = self.joypad_input & 0x0F == 0x0F;
+        // Buttons being pushed before this update (0 when pushed)
+        let previous = self.joypad_input & 0x0F;
         let joypad_input_ssba =
             if keys.is_start_pressed {
                 0x00
@@ -206,17 +281,16 @@ impl IO {
             }
         ;
         self.joypad_input |= 0x0F;
-        // If the movements keys are used
+        // If the movements keys are used (the selection bits are kept)
         if self.joypad_input & 0x10 == 0x00 {
-            self.joypad_input &= joypad_movement;
+            self.joypad_input &= 0xF0 | joypad_movement;
         }
         // If the SSBA keys are being used
         if self.joypad_input & 0x20 == 0x00 {
-            self.joypad_input &= joypad_input_ssba;
+            self.joypad_input &= 0xF0 | joypad_input_ssba;
         }
-        // Is a button currently being pushed
-        let is_pushed = self.joypad_input & 0x0F == 0x0F;
-        if !was_pushed && is_pushed {
+        // A button of a selected line has just been pushed (1 to 0)
+        if previous & !self.joypad_input & 0x0F != 0x00 {
             self.send_joypad_interrupt();
         }
     }
@@ -233,19 +307,16 @@ impl IO {
         keys: &KeyState
     ) {
         self.listen_for_buttons(keys);
-        if !self.is_stopped {
-            // The clock frequency of the CPU is 4194304 Hz
-            // The divider increment frequency is  16384 Hz (every 256 cycle)
-            let increment_divider = ((
-                ((self.cpu_cycle & 0x00FF).wrapping_add(
-                    (n_ticks & 0xFFFF) as u16
-                )) & 0xFF00
-            ) >> 8) as u8;
-            self.divider = self.divider.wrapping_add(increment_divider);
+        // The internal divider and the timer are stopped by a stop
+        // instruction
+        if self.is_stopped {
+            return;
         }
+        // The clock frequency of the CPU is 4194304 Hz, the divider register
+        // is incremented at 16384 Hz (every 256 cycle)
         // The timer is incremented at the clock frequency specified by the TAC
         // register (0xFF07)
-        if self.timer_control & 0x20 == 0x20 {
+        if self.timer_control & 0x04 == 0x04 {
             let increment_timer = match self.timer_control & 0x03 {
                 // Frequency: 4096 Hz (1024 cycles)
                 0 => {
@@ -275,27 +346,68 @@ impl IO {
                     panic!("Invalid increment");
                 }
             };
-            let (timer_counter, did_overflow) = self.timer_counter
-                .overflowing_add(increment_timer as u8);
-            self.timer_counter = timer_counter;
-            // When the value exceeds 0xFF, it is reet to the value specified in
-            // TMA (0xFF06) and an interrupt is requested.
-            if did_overflow {
-                self.timer_counter = self.timer_counter.wrapping_add(
-                    self.timer_modulo
-                );
-                self.send_timer_interrupt();
-            }
+            self.increment_timer(increment_timer as u8);
         }
         self.cpu_cycle = self.cpu_cycle.wrapping_add(n_ticks as u16);
     }
 
+    /// Increments the timer counter (TIMA)
+    ///
+    /// # Arguments
+    /// **increment (u8)**: Number of ticks of the timer
+    fn increment_timer(&mut self, increment: u8) {
+        let (timer_counter, did_overflow) = self.timer_counter
+            .overflowing_add(increment);
+        self.timer_counter = timer_counter;
+        // When the value exceeds 0xFF, it is reet to the value specified in
+        // TMA (0xFF06) and an interrupt is requested.
+        if did_overflow {
+            self.timer_counter = self.timer_counter.wrapping_add(
+                self.timer_modulo
+            );
+            self.send_timer_interrupt();
+        }
+    }
+
+    /// Resets the internal divider
+    ///
+    /// The timer is incremented on the falling edges of one bit of the
+    /// internal divider, chosen by TAC: resetting the divider while this bit
+    /// is set increments the timer.
+    fn reset_divider(&mut self) {
+        let timer_bit = match self.timer_control & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        let is_falling_edge = self.timer_control & 0x04 == 0x04 &&
+            (self.cpu_cycle >> timer_bit) & 0x01 == 0x01;
+        self.cpu_cycle = 0;
+        if is_falling_edge {
+            self.increment_timer(1);
+        }
+    }
+
     /// Reacts to a stop instruction
     ///
     /// Re-initialize and stop the divider
     pub fn receive_stop(&mut self) {
-        self.divider = 0;
-        self.is_stopped = !self.is_stopped;
+        self.reset_divider();
+        self.is_stopped = true;
+    }
+
+    /// Restarts the divider after a stop instruction
+    pub fn resume(&mut self) {
+        self.is_stopped = false;
+    }
+
+    /// Returns true iff a button of a selected line is pressed
+    ///
+    /// # Returns
+    /// **bool**: Is one of the 4 lower bits of the joypad register reset
+    pub fn is_button_pressed(&self) -> bool {
+        self.joypad_input & 0x0F != 0x0F
     }
 
     /// Signify that a joypad interruption is waiting to be handled by the cpu
@@ -310,3 +422,75 @@ impl IO {
         self.pending_timer_interruption = true;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_output() {
+        let mut io = IO::new();
+        for byte in b"Ok" {
+            io.write(0xFF01, *byte);
+            io.write(0xFF02, 0x81);
+            // The transfer is over
+            assert_eq!(io.read(0xFF02), 0x01);
+        }
+        // Without the start bit, nothing is sent
+        io.write(0xFF01, b'!');
+        io.write(0xFF02, 0x01);
+        assert_eq!(io.serial_output(), b"Ok");
+    }
+
+    #[test]
+    fn test_divider_write() {
+        let keys = KeyState::new();
+        let mut io = IO::new();
+        io.update(0x1234, &keys);
+        assert_eq!(io.read(0xFF04), 0x12);
+        io.write(0xFF04, 0xAB);
+        assert_eq!(io.read(0xFF04), 0x00);
+        // The internal divider was cleared too
+        io.update(0xFF, &keys);
+        assert_eq!(io.read(0xFF04), 0x00);
+        io.update(1, &keys);
+        assert_eq!(io.read(0xFF04), 0x01);
+        // Timer enabled at 262144 Hz (bit 3 of the divider)
+        io.write(0xFF04, 0x00);
+        io.write(0xFF07, 0x05);
+        io.update(4, &keys);
+        io.write(0xFF04, 0x00);
+        assert_eq!(io.read(0xFF05), 0x00);
+        // A reset while the bit is set is a falling edge
+        io.update(8, &keys);
+        io.write(0xFF04, 0x00);
+        assert_eq!(io.read(0xFF05), 0x01);
+        assert_eq!(io.read(0xFF04), 0x00);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let keys = KeyState::new();
+        let mut io = IO::new();
+        // Timer enabled at 262144 Hz (16 cycles), reset to 0xF0
+        io.write(0xFF05, 0xFE);
+        io.write(0xFF06, 0xF0);
+        io.write(0xFF07, 0x25);
+        io.update(8, &keys);
+        let mut restored = IO::new();
+        restored.restore(&io.snapshot());
+        restored.restore_timer_state(&io.timer_state());
+        assert_eq!(restored.snapshot(), io.snapshot());
+        assert_eq!(restored.timer_state(), io.timer_state());
+        for _ in 0..8 {
+            io.update(4, &keys);
+            restored.update(4, &keys);
+            assert_eq!(restored.read(0xFF05), io.read(0xFF05));
+            assert_eq!(
+                restored.pending_timer_interruption,
+                io.pending_timer_interruption
+            );
+        }
+        assert!(restored.pending_timer_interruption);
+    }
+}
@@ -0,0 +1,31 @@
+/// Memory seen by the CPU
+///
+/// The Mmu is the memory of the GameBoy. Other implementations let the CPU
+/// run on a simpler memory, for example a flat RAM in the unit tests.
+pub trait MemoryBus {
+    /// Read a byte in the memory
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the byte to read
+    ///
+    /// # Returns
+    /// **u8**: Value of the byte at this address
+    fn read_byte(&self, address: u16) -> u8;
+
+    /// Change a byte in the memory
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the byte to modify
+    /// **value (u8)**: New value to put at this address
+    fn write_byte(&mut self, address: u16, value: u8);
+
+    /// Advances the components behind the memory
+    ///
+    /// # Arguments
+    /// **n_cycles (u32)**: Number of cycles used by the CPU since the last
+    /// update
+    ///
+    /// # Returns
+    /// **bool**: Should the emulation stop
+    fn update(&mut self, n_cycles: u32) -> bool;
+}
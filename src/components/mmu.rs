@@ -1,9 +1,55 @@
+#[cfg(any(test, feature = "test-vectors"))]
+use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use crate::components::apu::Apu;
 use crate::components::io::IO;
 use crate::components::hram::Hram;
 use crate::components::wram::Wram;
 use crate::components::gpu::Gpu;
+use crate::components::memory_bus::MemoryBus;
 use crate::components::cartridge::Cartridge;
+use crate::state::filter::Filter;
+use crate::state::key_state::KeyState;
+use crate::state::model::Model;
+use crate::state::palette::Palette;
+use crate::state::ram_init::RamInit;
+use crate::state::register::Registers;
+use crate::state::save_state::SaveState;
 
+/// Addresses of the LCD registers kept in a save state
+const LCD_REGISTERS: [u16; 11] = [
+    0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x47, 0x48, 0x49, 0x4A, 0x4B
+];
+
+/// Addresses and values of the bytes read or written while the accesses are
+/// logged
+#[cfg(any(test, feature = "test-vectors"))]
+struct AccessLog(RefCell<Option<Vec<(u16, u8)>>>);
+
+#[cfg(any(test, feature = "test-vectors"))]
+impl Hash for AccessLog {
+    /// The log is not a part of the state of the gameboy
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+/// Writes to a region of the memory, logged for tracing
+struct WriteLog {
+    /// Region whose writes are logged, if any
+    range: Option<RangeInclusive<u16>>,
+    /// Address of the instruction being executed
+    pc: u16,
+    /// Address of the instruction, address and value of each write
+    writes: Vec<(u16, u16, u8)>,
+}
+
+impl Hash for WriteLog {
+    /// The log is not a part of the state of the gameboy
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+#[derive(Hash)]
 /// Memory management unit of the GameBoy
 pub struct Mmu {
     /// Interrupt flag: unused/unused/unused/joypad/serial/timer/lcd/vblank
@@ -23,8 +69,34 @@ pub struct Mmu {
     hram: Hram,
     /// I/0 Registers
     io: IO,
+    /// Audio Processing Unit
+    apu: Apu,
     /// Is the gameboy in double speed mode
-    is_double_speed: bool
+    is_double_speed: bool,
+    /// Should the next stop instruction switch the speed (KEY1 bit 0)
+    is_speed_switch_prepared: bool,
+    /// Value returned when reading an address mapped to nothing
+    open_bus: u8,
+    /// Do the accesses to 0xFEA0~0xFEFF reach the OAM (hardware quirk) instead
+    /// of the open bus
+    is_oam_gap_corrupting: bool,
+    /// Should a write to a mapper that is not emulated panic
+    is_strict: bool,
+    /// Source address of the next VRAM DMA block (0xFF51/0xFF52)
+    hdma_source: u16,
+    /// Destination address in VRAM of the next VRAM DMA block (0xFF53/0xFF54)
+    hdma_destination: u16,
+    /// Number of blocks of 16 bytes still to be copied by the VRAM DMA
+    hdma_blocks: u8,
+    /// Is a VRAM DMA copying one block per horizontal blank
+    is_hblank_dma_active: bool,
+    /// Memory accesses since start_access_log, if it was called
+    #[cfg(any(test, feature = "test-vectors"))]
+    access_log: AccessLog,
+    /// Writes to the region given to log_writes
+    write_log: WriteLog,
+    /// Pattern written in the WRAM, VRAM and HRAM at each reset
+    ram_init: RamInit,
 }
 
 impl Mmu {
@@ -37,26 +109,170 @@ impl Mmu {
     /// # Returns
     /// **MMU**: New Memory Management Unit
     pub fn new(cartridge_path: &str) -> Self {
+        Self::with_gpu(cartridge_path, Gpu::new())
+    }
+
+    /// Create a new Memory management unit whose GPU does not open a window
+    ///
+    /// # Arguments
+    /// **cartridge_path (&str)**: Path of the file containing the ROM of the
+    /// game
+    ///
+    /// # Returns
+    /// **MMU**: New Memory Management Unit
+    pub fn headless(cartridge_path: &str) -> Self {
+        Self::with_gpu(cartridge_path, Gpu::headless())
+    }
+
+    /// Create a new Memory management unit using the given GPU
+    ///
+    /// # Arguments
+    /// **cartridge_path (&str)**: Path of the file containing the ROM of the
+    /// game
+    /// **gpu (Gpu)**: GPU used for the VRAM and the LCD registers
+    ///
+    /// # Returns
+    /// **MMU**: New Memory Management Unit
+    fn with_gpu(cartridge_path: &str, mut gpu: Gpu) -> Self {
+        let cartridge = Cartridge::new(cartridge_path);
+        if !cartridge.is_supported() {
+            eprintln!(
+                "Warning: the {} mapper of {} is not supported, the game may \
+                not run correctly",
+                cartridge.mapper_name(),
+                cartridge_path
+            );
+        }
+        gpu.set_model(cartridge.model());
         Self {
             interrupt_flag: 0x00,
             ie: 0x00,
-            cartridge: Cartridge::new(cartridge_path),
-            gpu: Gpu::new(),
+            cartridge,
+            gpu,
             wram: Wram::new(),
             hram: Hram::new(),
             io: IO::new(),
-            is_double_speed: false
+            apu: Apu::default(),
+            is_double_speed: false,
+            is_speed_switch_prepared: false,
+            open_bus: 0xFF,
+            is_oam_gap_corrupting: false,
+            is_strict: false,
+            hdma_source: 0x0000,
+            hdma_destination: 0x8000,
+            hdma_blocks: 0,
+            is_hblank_dma_active: false,
+            #[cfg(any(test, feature = "test-vectors"))]
+            access_log: AccessLog(RefCell::new(None)),
+            write_log: WriteLog {
+                range: None,
+                pc: 0x0000,
+                writes: vec![],
+            },
+            ram_init: RamInit::Zero,
         }
     }
 
     /// Read a byte in the memory of the GameBoy
     ///
+    /// The access is logged if start_access_log was called.
+    ///
     /// # Arguments
     /// **address (u16)**: Address of the byte to read
     ///
     /// # Returns
     /// **u8**: Value read at this address
-    pub fn read_byte(
+    pub fn read_byte(&self, address: u16) -> u8 {
+        let value = self.read_mapped(address);
+        #[cfg(any(test, feature = "test-vectors"))]
+        self.log_access(address, value);
+        value
+    }
+
+    /// Change a byte in the memory of the GameBoy
+    ///
+    /// The access is logged if start_access_log was called.
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the byte to modifiy
+    /// **value (u8)**: New value to put at this address
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        #[cfg(any(test, feature = "test-vectors"))]
+        self.log_access(address, value);
+        if self.write_log
+            .range
+            .as_ref()
+            .is_some_and(|range| range.contains(&address)) {
+            self.write_log.writes.push((self.write_log.pc, address, value));
+        }
+        self.write_mapped(address, value);
+    }
+
+    /// Starts logging the writes to a region of the memory
+    ///
+    /// Replaces the region previously logged, the writes already logged are
+    /// kept until take_write_log is called.
+    ///
+    /// # Arguments
+    /// **range (`RangeInclusive<u16>`)**: Addresses whose writes are logged
+    pub fn log_writes(&mut self, range: RangeInclusive<u16>) {
+        self.write_log.range = Some(range);
+    }
+
+    /// Returns the writes logged since the last call
+    ///
+    /// # Returns
+    /// **`Vec<(u16, u16, u8)>`**: Address of the instruction, address and
+    /// value of each write to the region given to log_writes, in order
+    pub fn take_write_log(&mut self) -> Vec<(u16, u16, u8)> {
+        std::mem::take(&mut self.write_log.writes)
+    }
+
+    /// Change the address of the instruction being executed, kept with the
+    /// logged writes
+    ///
+    /// # Arguments
+    /// **pc (u16)**: Address of the instruction
+    pub fn set_instruction_address(&mut self, pc: u16) {
+        self.write_log.pc = pc;
+    }
+
+    /// Adds a memory access to the log, if the accesses are logged
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the byte read or written
+    /// **value (u8)**: Value read or written
+    #[cfg(any(test, feature = "test-vectors"))]
+    fn log_access(&self, address: u16, value: u8) {
+        if let Some(log) = self.access_log.0.borrow_mut().as_mut() {
+            log.push((address, value));
+        }
+    }
+
+    /// Starts logging every byte read or written
+    #[cfg(any(test, feature = "test-vectors"))]
+    pub fn start_access_log(&mut self) {
+        *self.access_log.0.borrow_mut() = Some(vec![]);
+    }
+
+    /// Stops logging the memory accesses
+    ///
+    /// # Returns
+    /// **`Vec<(u16, u8)>`**: Address and value of each byte read or written
+    /// since start_access_log, in order
+    #[cfg(any(test, feature = "test-vectors"))]
+    pub fn take_access_log(&mut self) -> Vec<(u16, u8)> {
+        self.access_log.0.borrow_mut().take().unwrap_or_default()
+    }
+
+    /// Read a byte from the memory area mapped at an address
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address of the byte to read
+    ///
+    /// # Returns
+    /// **u8**: Value read at this address
+    fn read_mapped(
         &self,
         address: u16
     ) -> u8 {
@@ -66,8 +282,8 @@ impl Mmu {
                 self.interrupt_flag
             },
             // LCD
-            0x40..=0x4F => {
-                self.gpu.read_lcd(address)
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => {
+                self.gpu.read_lcd(address & 0x00FF)
             },
             // 16 KiB ROM bank 00
             // From cartridge, usually a fixed bank
@@ -112,7 +328,57 @@ impl Mmu {
             // Not Usable
             // Nintendo says use of this area is prohibited
             0xFEA0..=0xFEFF => {
-                panic!("Tried to access to a prohibited memory address");
+                if self.is_oam_gap_corrupting {
+                    self.gpu.read_oam(address - 0x00A0)
+                } else {
+                    self.open_bus
+                }
+            },
+            // Unmapped I/O registers, KEY0 and BANK are only used by the boot
+            // ROM
+            0xFF03 | 0xFF08..=0xFF0E | 0xFF27..=0xFF2F | 0xFF4C | 0xFF4E |
+            0xFF50 => {
+                self.open_bus
+            },
+            // No infrared port, color palettes nor WRAM bank on DMG
+            0xFF56..=0xFF7F if !self.is_cgb() => {
+                self.open_bus
+            },
+            // Sound channel 4
+            0xFF20..=0xFF23 => {
+                self.apu.read(address)
+            },
+            // No KEY1 on DMG
+            0xFF4D if !self.is_cgb() => {
+                0xFF
+            },
+            // KEY1: current speed (bit 7) and prepared speed switch (bit 0)
+            0xFF4D => {
+                0x7E |
+                    if self.is_double_speed { 0x80 } else { 0x00 } |
+                    if self.is_speed_switch_prepared { 0x01 } else { 0x00 }
+            },
+            // VBK: bank of the VRAM
+            0xFF4F => {
+                self.gpu.read_vram_bank()
+            },
+            // VRAM DMA source and destination (write only), and no VRAM DMA
+            // on DMG
+            0xFF51..=0xFF54 => {
+                0xFF
+            },
+            0xFF55 if !self.is_cgb() => {
+                0xFF
+            },
+            // VRAM DMA length/mode/start
+            // Number of blocks remaining minus one while an HBlank DMA is
+            // active, 0xFF otherwise
+            0xFF55 => {
+                if self.is_hblank_dma_active {
+                    self.hdma_blocks.wrapping_sub(1) & 0x7F
+                } else {
+                    0xFF
+                }
             },
             // I/0 Registers
             //
@@ -132,12 +398,12 @@ impl Mmu {
         }
     }
 
-    /// Change a byte in the memory of the GameBoy
+    /// Change a byte in the memory area mapped at an address
     ///
     /// # Arguments
     /// **address (u16)**: Address of the byte to modifiy
     /// **value (u8)**: New value to put at this address
-    pub fn write_byte(
+    fn write_mapped(
         &mut self,
         address: u16,
         value: u8
@@ -148,12 +414,13 @@ impl Mmu {
                 self.interrupt_flag = value;
             },
             // LCD
-            0x40..=0x4F => {
-                self.gpu.write_lcd(address, value);
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => {
+                self.gpu.write_lcd(address & 0x00FF, value);
             },
             // 16 KiB ROM bank 00
             // From cartridge, usually a fixed bank
             0x0000..=0x3FFF => {
+                self.check_mapper_write(address);
                 self.cartridge.write_rom(
                     address,
                     value
@@ -162,6 +429,7 @@ impl Mmu {
             // 16 KiB ROM Bank 01~NN
             // From cartridge, switchable bank via mapper (if any)
             0x4000..=0x7FFF => {
+                self.check_mapper_write(address);
                 self.cartridge.write_rom(
                     address,
                     value
@@ -218,7 +486,48 @@ impl Mmu {
             // Not Usable
             // Nintendo says use of this area is prohibited
             0xFEA0..=0xFEFF => {
-                panic!("Tried to access to a prohibited memory address");
+                if self.is_oam_gap_corrupting {
+                    self.gpu.write_oam(address - 0x00A0, value);
+                }
+            },
+            // Sound channel 4
+            0xFF20..=0xFF23 => {
+                self.apu.write(address, value);
+            },
+            // No KEY1 on DMG
+            0xFF4D if !self.is_cgb() => {},
+            // KEY1: prepare a speed switch
+            0xFF4D => {
+                self.is_speed_switch_prepared = value & 0x01 == 0x01;
+            },
+            // VBK: select the bank of the VRAM
+            0xFF4F => {
+                self.gpu.write_vram_bank(value);
+            },
+            // No VRAM DMA on DMG
+            0xFF51..=0xFF55 if !self.is_cgb() => {},
+            // VRAM DMA source
+            0xFF51 => {
+                self.hdma_source =
+                    (value as u16) << 8 | (self.hdma_source & 0x00FF);
+            },
+            0xFF52 => {
+                self.hdma_source =
+                    (self.hdma_source & 0xFF00) | (value & 0xF0) as u16;
+            },
+            // VRAM DMA destination
+            0xFF53 => {
+                self.hdma_destination = 0x8000 |
+                    ((value & 0x1F) as u16) << 8 |
+                    (self.hdma_destination & 0x00FF);
+            },
+            0xFF54 => {
+                self.hdma_destination =
+                    (self.hdma_destination & 0xFF00) | (value & 0xF0) as u16;
+            },
+            // VRAM DMA length/mode/start
+            0xFF55 => {
+                self.start_vram_dma(value);
             },
             // I/0 Registers
             //
@@ -294,7 +603,13 @@ impl Mmu {
             n_cycles,
             self.gpu.transmit_key()
         );
-        let res = self.gpu.update(n_cycles as u16);
+        self.apu.update(n_cycles);
+        let res = self.gpu.update(n_cycles);
+        for _ in 0..self.gpu.take_hblanks() {
+            if self.is_hblank_dma_active {
+                self.copy_vram_dma_block();
+            }
+        }
         // INT 0x60
         if self.io.pending_joypad_interruption {
             self.interrupt_flag |= 0x10;
@@ -308,27 +623,678 @@ impl Mmu {
         // INT 0x48
         if self.gpu.pending_stat_interrupt {
             self.interrupt_flag |= 0x02;
-            self.gpu.pending_stat_interrupt  = false;
+            self.gpu.pending_stat_interrupt = false;
         }
         // INT 0x40
         if self.gpu.pending_vblank_interrupt {
             self.interrupt_flag |= 0x01;
-            self.gpu.pending_stat_interrupt  = false;
+            self.gpu.pending_vblank_interrupt = false;
         }
         res
     }
 
+    /// Starts or cancels a VRAM DMA after a write to 0xFF55
+    ///
+    /// The bit 7 selects the mode: 0 for a General Purpose DMA copying every
+    /// block at once, 1 for an HBlank DMA copying one block per horizontal
+    /// blank. Writing 0 to bit 7 during an HBlank DMA cancels it.
+    ///
+    /// # Arguments
+    /// **value (u8)**: Mode (bit 7) and number of blocks minus one (bit 0-6)
+    fn start_vram_dma(&mut self, value: u8) {
+        if self.is_hblank_dma_active && value & 0x80 == 0 {
+            self.is_hblank_dma_active = false;
+            return;
+        }
+        self.hdma_blocks = (value & 0x7F) + 1;
+        if value & 0x80 == 0x80 {
+            self.is_hblank_dma_active = true;
+        } else {
+            while self.hdma_blocks > 0 {
+                self.copy_vram_dma_block();
+            }
+        }
+    }
+
+    /// Copies the next block of 16 bytes of the VRAM DMA
+    fn copy_vram_dma_block(&mut self) {
+        for i in 0..16 {
+            let value = self.read_byte(self.hdma_source.wrapping_add(i));
+            self.gpu.write_ram(
+                0x8000 | (self.hdma_destination.wrapping_add(i) & 0x1FFF),
+                value
+            );
+        }
+        self.hdma_source = self.hdma_source.wrapping_add(16);
+        self.hdma_destination =
+            0x8000 | (self.hdma_destination.wrapping_add(16) & 0x1FFF);
+        self.hdma_blocks -= 1;
+        if self.hdma_blocks == 0 {
+            self.is_hblank_dma_active = false;
+        }
+    }
+
     /// React to a stop from the cpu
     ///
-    /// Change the cpu cycle and transmit the stop to the memory zone that use
-    /// it
-    pub fn receive_stop(&mut self) {
-        self.is_double_speed = !self.is_double_speed;
+    /// If a speed switch was prepared through KEY1, the speed is switched and
+    /// the CPU keeps running. Otherwise, the stop is transmitted to the
+    /// memory zone that use it.
+    ///
+    /// # Returns
+    /// **bool**: Should the CPU stop until a button is pressed
+    pub fn receive_stop(&mut self) -> bool {
+        if self.is_speed_switch_prepared {
+            self.is_speed_switch_prepared = false;
+            self.is_double_speed = !self.is_double_speed;
+            return false;
+        }
         self.io.receive_stop();
+        true
+    }
+
+    /// Returns true iff a button of a selected joypad line is pressed
+    ///
+    /// # Returns
+    /// **bool**: Should a stopped CPU resume
+    pub fn is_button_pressed(&self) -> bool {
+        self.io.is_button_pressed()
+    }
+
+    /// Restarts the memory zones stopped by a stop instruction
+    pub fn resume(&mut self) {
+        self.io.resume();
+    }
+
+    /// Returns every byte sent through the serial port
+    ///
+    /// # Returns
+    /// **&[u8]**: Bytes sent through the serial port, oldest first
+    pub fn serial_output(&self) -> &[u8] {
+        self.io.serial_output()
+    }
+
+    /// Copy the state of every memory area
+    ///
+    /// The state of the CPU is left to its initial value.
+    ///
+    /// # Returns
+    /// **SaveState**: Copy of the state of the memory
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            registers: Registers::new(),
+            is_halted: false,
+            is_stopped: false,
+            is_halt_bug: false,
+            ei: 0,
+            di: 0,
+            ime: true,
+            interrupt_flag: self.interrupt_flag,
+            ie: self.ie,
+            is_double_speed: self.is_double_speed,
+            vram: self.gpu.vram().to_vec(),
+            vram_bank: self.gpu.read_vram_bank() & 0x01,
+            cartridge_ram: self.cartridge.ram().to_vec(),
+            cartridge_registers: self.cartridge.registers(),
+            wram: (0xC000..=0xDFFF).map(|a| self.wram.read(a)).collect(),
+            oam: self.gpu.oam_bytes(),
+            io: self.io.snapshot(),
+            timer: self.io.timer_state(),
+            apu: self.apu.state(),
+            lcd: LCD_REGISTERS.iter().map(|a| self.gpu.read_lcd(*a)).collect(),
+            ppu: self.gpu.ppu_state(),
+            hram: (0xFF80..=0xFFFE).map(|a| self.hram.read(a)).collect(),
+        }
+    }
+
+    /// Checks that a state can be restored in this memory
+    ///
+    /// # Arguments
+    /// **state (&SaveState)**: State to check, as returned by save_state
+    ///
+    /// # Returns
+    /// **`Result<(), String>`**: First memory area whose size does not match
+    /// this memory, if any
+    pub fn validate_state(&self, state: &SaveState) -> Result<(), String> {
+        let areas = [
+            ("vram", state.vram.len(), self.gpu.vram().len()),
+            (
+                "cartridge_ram",
+                state.cartridge_ram.len(),
+                self.cartridge.ram().len()
+            ),
+            ("wram", state.wram.len(), 0x2000),
+            ("oam", state.oam.len(), 0xA0),
+            ("lcd", state.lcd.len(), LCD_REGISTERS.len()),
+            ("hram", state.hram.len(), 0x7F),
+        ];
+        for (name, length, expected) in areas {
+            if length != expected {
+                return Err(format!(
+                    "{} has {} bytes instead of {}",
+                    name,
+                    length,
+                    expected
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore the state of every memory area
+    ///
+    /// # Arguments
+    /// **state (&SaveState)**: State to restore, as returned by save_state
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.interrupt_flag = state.interrupt_flag;
+        self.ie = state.ie;
+        self.is_double_speed = state.is_double_speed;
+        for (byte, value) in self.gpu.vram_mut().iter_mut().zip(&state.vram) {
+            *byte = *value;
+        }
+        self.gpu.write_vram_bank(state.vram_bank);
+        self.cartridge.restore(
+            &state.cartridge_ram,
+            state.cartridge_registers
+        );
+        for (address, value) in (0xC000..=0xDFFF).zip(&state.wram) {
+            self.wram.write(address, *value);
+        }
+        self.gpu.load_oam_bytes(&state.oam);
+        self.io.restore(&state.io);
+        self.io.restore_timer_state(&state.timer);
+        self.apu.restore(&state.apu);
+        for (address, value) in LCD_REGISTERS.iter().zip(&state.lcd) {
+            self.gpu.write_lcd(*address, *value);
+        }
+        self.gpu.restore_ppu_state(&state.ppu);
+        for (address, value) in (0xFF80..=0xFFFE).zip(&state.hram) {
+            self.hram.write(address, *value);
+        }
+    }
+
+    /// Change what buttons are pressed
+    ///
+    /// # Arguments
+    /// **keys (KeyState)**: Buttons pressed from now on
+    pub fn set_buttons(&mut self, keys: KeyState) {
+        self.gpu.set_key_state(keys);
+    }
+
+    /// Change what buttons are pressed by a player
+    ///
+    /// # Arguments
+    /// **player (usize)**: Index of the player (0 is the keyboard)
+    /// **keys (KeyState)**: Buttons pressed from now on
+    pub fn set_buttons_for(&mut self, player: usize, keys: KeyState) {
+        self.gpu.set_key_state_for(player, keys);
+    }
+
+    /// Checks the global checksum of the header of the cartridge
+    ///
+    /// # Returns
+    /// **bool**: true iff the checksum matches the content of the ROM
+    pub fn verify_global_checksum(&self) -> bool {
+        self.cartridge.verify_global_checksum()
+    }
+
+    /// Gives access to the GPU
+    ///
+    /// # Returns
+    /// **&Gpu**: GPU used by the memory
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    /// Enables or disables the panics on the writes to a mapper that is not
+    /// emulated
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should these writes panic
+    pub fn set_strict_debug(&mut self, value: bool) {
+        self.is_strict = value;
+    }
+
+    /// Panics in strict mode if the cartridge uses a mapper that is not
+    /// emulated, whose registers are written
+    ///
+    /// # Arguments
+    /// **address (u16)**: Address written in the ROM
+    fn check_mapper_write(&self, address: u16) {
+        if self.is_strict && !self.cartridge.is_supported() {
+            panic!(
+                "Write at {:#06x} to the unsupported mapper {}",
+                address,
+                self.cartridge.mapper_name()
+            );
+        }
+    }
+
+    /// Returns the APU used by the memory
+    ///
+    /// # Returns
+    /// **&Apu**: APU used by the memory
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// Change the samples produced by the APU, keeping its channels
+    ///
+    /// # Arguments
+    /// **sample_rate (u32)**: Number of samples for each second of emulation
+    /// **stereo (bool)**: Should the left and right samples be interleaved
+    pub fn set_audio_output(&mut self, sample_rate: u32, stereo: bool) {
+        self.apu.set_output(sample_rate, stereo);
+    }
+
+    /// Returns the samples produced by the APU since the last call
+    ///
+    /// # Returns
+    /// **`Vec<f32>`**: Samples between 0 and 1, left then right in stereo
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.apu.generate()
+    }
+
+    /// Change the maximum number of objects drawn on a single line by the GPU
+    ///
+    /// # Arguments
+    /// **value (usize)**: New maximum number of objects per line
+    pub fn set_max_sprites_per_line(&mut self, value: usize) {
+        self.gpu.set_max_sprites_per_line(value);
+    }
+
+    /// Replaces the colors displayed by the GPU by those of a .pal file
+    ///
+    /// # Arguments
+    /// **path (&Path)**: Path of the .pal file
+    ///
+    /// # Returns
+    /// **`Result<(), String>`**: Message explaining why the file is invalid,
+    /// if it is
+    pub fn load_palette_file(&mut self, path: &Path) -> Result<(), String> {
+        self.gpu.load_palette_file(path)
+    }
+
+    /// Replaces the colors displayed by the GPU
+    ///
+    /// # Arguments
+    /// **palette (Palette)**: New colors of the shades
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.gpu.set_palette(palette);
+    }
+
+    /// Change the post-processing applied to the image shown in the window
+    ///
+    /// # Arguments
+    /// **filter (Filter)**: Scanlines, LCD grid or none
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.gpu.set_filter(filter);
+    }
+
+    /// Gives the color of each pixel with a function instead of the palette
+    ///
+    /// # Arguments
+    /// **color_mapper (impl FnMut(u8) -> u32)**: Function returning the
+    /// 0xRRGGBB color of a shade (00 to 11 for white to black)
+    pub fn set_color_mapper(
+        &mut self,
+        color_mapper: impl FnMut(u8) -> u32 + 'static
+    ) {
+        self.gpu.set_color_mapper(color_mapper);
+    }
+
+    /// Gives the color of each pixel with the palette again
+    pub fn clear_color_mapper(&mut self) {
+        self.gpu.clear_color_mapper();
+    }
+
+    /// Change how the GPU decides which object is drawn on top
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the objects be sorted by x (true for DMG)
+    pub fn set_oam_x_priority(&mut self, value: bool) {
+        self.gpu.set_oam_x_priority(value);
+    }
+
+    /// Returns the last frame drawn by the GPU and marks it as presented
+    ///
+    /// # Returns
+    /// **`Vec<u32>`**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn capture_frame(&mut self) -> Vec<u32> {
+        self.gpu.capture_frame()
+    }
+
+    /// Change the value returned when reading an address mapped to nothing
+    ///
+    /// Depending on the hardware, it is often 0xFF, sometimes the last byte
+    /// read.
+    ///
+    /// # Arguments
+    /// **value (u8)**: New value read from the unmapped addresses
+    pub fn set_open_bus(&mut self, value: u8) {
+        self.open_bus = value;
+    }
+
+    /// Change what the accesses to the prohibited area 0xFEA0~0xFEFF do
+    ///
+    /// By default, the reads return the open bus value and the writes are
+    /// ignored. With the quirk, the area mirrors the start of the OAM, so the
+    /// reads return OAM data and the writes corrupt the first objects.
+    ///
+    /// # Arguments
+    /// **value (bool)**: Should the OAM corruption quirk be emulated
+    pub fn set_oam_gap_corruption(&mut self, value: bool) {
+        self.is_oam_gap_corrupting = value;
+    }
+
+    /// Change the content of the RAM at power up
+    ///
+    /// The pattern is written immediately in the WRAM, the VRAM and the HRAM,
+    /// and again each time a cartridge is loaded.
+    ///
+    /// # Arguments
+    /// **pattern (RamInit)**: Values of the RAM at power up (Zero by default)
+    pub fn set_ram_init_pattern(&mut self, pattern: RamInit) {
+        self.ram_init = pattern;
+        self.fill_ram();
+    }
+
+    /// Writes the power up pattern in the WRAM, the VRAM and the HRAM
+    fn fill_ram(&mut self) {
+        self.wram.fill(self.ram_init);
+        self.hram.fill(self.ram_init);
+        self.ram_init.fill(self.gpu.vram_mut());
+    }
+
+    /// Are the registers of the GameBoy Color mapped
+    ///
+    /// # Returns
+    /// **bool**: True iff the emulated model is a CGB
+    fn is_cgb(&self) -> bool {
+        self.gpu.model() == Model::Cgb
+    }
+
+    /// Change the model of GameBoy emulated, regardless of the cartridge
+    ///
+    /// # Arguments
+    /// **model (Model)**: Model of GameBoy to emulate
+    #[cfg(test)]
+    pub fn set_model(&mut self, model: Model) {
+        self.gpu.set_model(model);
+    }
+
+    /// Is the gameboy in double speed mode
+    ///
+    /// # Returns
+    /// **bool**: True iff the gameboy is in double speed mode
+    pub fn is_double_speed(&self) -> bool {
+        self.is_double_speed
     }
 
     /// Function called when the MMU is no longer needed
     pub fn close(&self) {
         self.cartridge.close();
     }
+
+    /// Saves the current cartridge and replaces it with a new one
+    ///
+    /// Every memory area is reset, but the screen of the GPU and the behavior
+    /// of the unmapped addresses are kept.
+    ///
+    /// # Arguments
+    /// **cartridge_path (&str)**: Path of the file containing the ROM of the
+    /// new game
+    pub fn load_cartridge(&mut self, cartridge_path: &str) {
+        self.close();
+        let mut gpu = std::mem::replace(&mut self.gpu, Gpu::headless());
+        gpu.reset();
+        let open_bus = self.open_bus;
+        let is_oam_gap_corrupting = self.is_oam_gap_corrupting;
+        let is_strict = self.is_strict;
+        let ram_init = self.ram_init;
+        let (sample_rate, stereo) = (
+            self.apu.sample_rate(),
+            self.apu.is_stereo()
+        );
+        let write_log_range = self.write_log.range.take();
+        *self = Self::with_gpu(cartridge_path, gpu);
+        self.open_bus = open_bus;
+        self.is_oam_gap_corrupting = is_oam_gap_corrupting;
+        self.is_strict = is_strict;
+        self.set_ram_init_pattern(ram_init);
+        self.apu.set_output(sample_rate, stereo);
+        self.write_log.range = write_log_range;
+    }
+}
+
+impl MemoryBus for Mmu {
+    fn read_byte(&self, address: u16) -> u8 {
+        Mmu::read_byte(self, address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        Mmu::write_byte(self, address, value);
+    }
+
+    fn update(&mut self, n_cycles: u32) -> bool {
+        Mmu::update(self, n_cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_joypad_interrupt() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        // Select the action buttons
+        mmu.write_byte(0xFF00, 0x10);
+        mmu.update(4);
+        assert_eq!(mmu.interrupt_flag & 0x10, 0x00);
+        let mut keys = KeyState::new();
+        keys.is_a_pressed = true;
+        mmu.set_buttons(keys);
+        mmu.update(4);
+        assert_eq!(mmu.interrupt_flag & 0x10, 0x10);
+        mmu.interrupt_flag = 0x00;
+        // Keeping the button pressed does not request another interruption
+        for _ in 0..10 {
+            mmu.update(4);
+        }
+        assert_eq!(mmu.interrupt_flag & 0x10, 0x00);
+    }
+
+    #[test]
+    fn test_ram_init_pattern() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        assert_eq!(mmu.read_byte(0xC123), 0x00);
+        mmu.set_ram_init_pattern(RamInit::Ones);
+        for address in [0x8000, 0x9FFF, 0xC000, 0xDFFF, 0xFF80, 0xFFFE] {
+            assert_eq!(mmu.read_byte(address), 0xFF);
+        }
+        mmu.set_ram_init_pattern(RamInit::Alternating);
+        mmu.write_byte(0xC000, 0x42);
+        mmu.load_cartridge("cartridges/Tetris.gb");
+        for address in [0x8000, 0xC000, 0xFF80] {
+            assert_eq!(mmu.read_byte(address), 0x00);
+            assert_eq!(mmu.read_byte(address + 1), 0xFF);
+        }
+    }
+
+    #[test]
+    fn test_log_writes() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        mmu.write_byte(0x8000, 0x01);
+        mmu.log_writes(0x8000..=0x9FFF);
+        mmu.set_instruction_address(0xC000);
+        mmu.write_byte(0x8000, 0x3C);
+        mmu.write_byte(0xC000, 0x42);
+        mmu.set_instruction_address(0xC003);
+        mmu.write_byte(0x9FFF, 0x7E);
+        mmu.write_byte(0xA000, 0x00);
+        assert_eq!(
+            mmu.take_write_log(),
+            vec![(0xC000, 0x8000, 0x3C), (0xC003, 0x9FFF, 0x7E)]
+        );
+        assert!(mmu.take_write_log().is_empty());
+        // The reads are not logged
+        mmu.read_byte(0x8000);
+        assert!(mmu.take_write_log().is_empty());
+    }
+
+    #[test]
+    fn test_vblank_interrupt_flag() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        mmu.write_byte(0xFF40, 0x80);
+        // Until the line 144
+        mmu.update(456 * 144 - 4);
+        assert_eq!(mmu.interrupt_flag & 0x01, 0x00);
+        mmu.update(4);
+        assert_eq!(mmu.interrupt_flag & 0x01, 0x01);
+        assert!(!mmu.gpu.pending_vblank_interrupt);
+        // Once handled, the interruption is not requested again until the
+        // next frame
+        mmu.interrupt_flag = 0x00;
+        mmu.update(456 * 10 - 4);
+        assert_eq!(mmu.interrupt_flag & 0x01, 0x00);
+        mmu.update(456 * 144 + 4);
+        assert_eq!(mmu.interrupt_flag & 0x01, 0x01);
+    }
+
+    #[test]
+    fn test_lcd_registers() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        mmu.write_byte(0xFF42, 0x12);
+        mmu.write_byte(0xFF43, 0x34);
+        assert_eq!(mmu.gpu().scroll(), (0x34, 0x12));
+        assert_eq!(mmu.read_byte(0xFF42), 0x12);
+        // The interruption vectors are read from the cartridge
+        assert_eq!(mmu.read_byte(0x0040), mmu.cartridge.read_rom(0x0040));
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        assert_eq!(mmu.read_byte(0xFEA0), 0xFF);
+        mmu.set_open_bus(0x00);
+        assert_eq!(mmu.read_byte(0xFEA0), 0x00);
+        assert_eq!(mmu.read_byte(0xFF03), 0x00);
+        // Unimplemented registers do not keep what is written to them
+        for address in [0xFF4C, 0xFF4E, 0xFF50, 0xFF56, 0xFF68, 0xFF7F] {
+            mmu.write_byte(address, 0x12);
+            assert_eq!(mmu.read_byte(address), 0x00);
+        }
+        // The CGB registers above 0xFF56 are kept on CGB
+        mmu.gpu.set_model(Model::Cgb);
+        mmu.write_byte(0xFF70, 0x12);
+        assert_eq!(mmu.read_byte(0xFF70), 0x12);
+        // Writes to the prohibited area are ignored
+        mmu.write_byte(0xFEFF, 0x12);
+        assert_eq!(mmu.read_byte(0xFEFF), 0x00);
+    }
+
+    #[test]
+    fn test_vram_bank_register() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        mmu.gpu.set_model(Model::Cgb);
+        mmu.write_byte(0x8010, 0xAA);
+        mmu.write_byte(0xFF4F, 0x01);
+        assert_eq!(mmu.read_byte(0xFF4F), 0xFF);
+        mmu.write_byte(0x8010, 0xBB);
+        assert_eq!(mmu.read_byte(0x8010), 0xBB);
+        mmu.write_byte(0xFF4F, 0x00);
+        assert_eq!(mmu.read_byte(0xFF4F), 0xFE);
+        assert_eq!(mmu.read_byte(0x8010), 0xAA);
+    }
+
+    #[test]
+    fn test_oam_gap() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        mmu.write_byte(0xFE00, 0x12);
+        // By default, the gap is open bus and does not touch the OAM
+        for address in 0xFEA0..=0xFEFF {
+            assert_eq!(mmu.read_byte(address), 0xFF);
+            mmu.write_byte(address, 0x34);
+        }
+        assert_eq!(mmu.read_byte(0xFE01), 0x00);
+        mmu.set_oam_gap_corruption(true);
+        assert_eq!(mmu.read_byte(0xFEA0), 0x12);
+        mmu.write_byte(0xFEA1, 0x34);
+        assert_eq!(mmu.read_byte(0xFE01), 0x34);
+        mmu.load_cartridge("cartridges/Tetris.gb");
+        assert!(mmu.is_oam_gap_corrupting);
+    }
+
+    /// Writes 0x00, 0x01, 0x02... from 0xC000 and selects them as the source
+    /// of a VRAM DMA towards the given address
+    fn mmu_with_vram_dma_source(destination: u16) -> Mmu {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        mmu.gpu.set_model(Model::Cgb);
+        for i in 0..0x80 {
+            mmu.write_byte(0xC000 + i, i as u8);
+        }
+        mmu.write_byte(0xFF51, 0xC0);
+        mmu.write_byte(0xFF52, 0x00);
+        mmu.write_byte(0xFF53, (destination >> 8) as u8);
+        mmu.write_byte(0xFF54, destination as u8);
+        mmu
+    }
+
+    #[test]
+    fn test_general_purpose_dma() {
+        let mut mmu = mmu_with_vram_dma_source(0x8000);
+        // 2 blocks
+        mmu.write_byte(0xFF55, 0x01);
+        for i in 0..0x20 {
+            assert_eq!(mmu.read_byte(0x8000 + i), i as u8);
+        }
+        assert_eq!(mmu.read_byte(0x8020), 0x00);
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn test_key1_only_on_cgb() {
+        let mut mmu = Mmu::headless("cartridges/Tetris.gb");
+        mmu.write_byte(0xFF4D, 0x01);
+        assert_eq!(mmu.read_byte(0xFF4D), 0xFF);
+        assert!(mmu.receive_stop());
+        assert!(!mmu.is_double_speed());
+        mmu.set_model(Model::Cgb);
+        assert_eq!(mmu.read_byte(0xFF4D), 0x7E);
+        mmu.write_byte(0xFF4D, 0x01);
+        assert_eq!(mmu.read_byte(0xFF4D), 0x7F);
+        assert!(!mmu.receive_stop());
+        assert_eq!(mmu.read_byte(0xFF4D), 0xFE);
+    }
+
+    #[test]
+    fn test_no_vram_dma_on_dmg() {
+        let mut mmu = mmu_with_vram_dma_source(0x8000);
+        mmu.gpu.set_model(Model::Dmg);
+        mmu.write_byte(0xFF55, 0x01);
+        for i in 0..0x20 {
+            assert_eq!(mmu.read_byte(0x8000 + i), 0x00);
+        }
+        mmu.write_byte(0xFF55, 0x82);
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+        mmu.update(456);
+        assert_eq!(mmu.read_byte(0x8000), 0x00);
+    }
+
+    #[test]
+    fn test_hblank_dma() {
+        let mut mmu = mmu_with_vram_dma_source(0x8100);
+        mmu.write_byte(0xFF40, 0x80);
+        // 3 blocks, one per horizontal blank
+        mmu.write_byte(0xFF55, 0x82);
+        assert_eq!(mmu.read_byte(0xFF55), 0x02);
+        for line in 1..=3 {
+            mmu.update(456);
+            for i in 0..0x30 {
+                let expected = if i < line * 0x10 { i as u8 } else { 0x00 };
+                assert_eq!(mmu.read_byte(0x8100 + i), expected);
+            }
+        }
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+    }
 }
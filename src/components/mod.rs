@@ -1,8 +1,13 @@
+pub mod apu;
+pub mod audio_sink;
 pub mod cpu;
+pub mod gpu;
+pub mod memory_bus;
 
 mod cartridge;
 mod mmu;
-mod gpu;
+#[cfg(feature = "recording")]
+mod recorder;
 mod hram;
 mod io;
 mod screen;
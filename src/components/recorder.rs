@@ -0,0 +1,87 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use image::{ImageResult, Rgb, RgbImage};
+
+/// Game Boy screen width
+const WIDTH: u32 = 160;
+/// Game Boy screen height
+const HEIGHT: u32 = 144;
+
+/// Writes the frames of the GameBoy as PNG files in a directory
+pub struct Recorder {
+    /// Directory where the frames are written
+    directory: PathBuf,
+    /// Number of frames written
+    n_frames: usize,
+}
+
+impl Recorder {
+    /// Create a recorder writing in the given directory
+    ///
+    /// The directory is created if it does not exist.
+    ///
+    /// # Arguments
+    /// **directory (&Path)**: Directory where the frames are written
+    ///
+    /// # Returns
+    /// **`io::Result<Recorder>`**: New recorder, or the error encountered
+    /// while creating the directory
+    pub fn new(directory: &Path) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            n_frames: 0,
+        })
+    }
+
+    /// Writes a frame in the next PNG file (frame_00000.png, frame_00001.png,
+    /// ...)
+    ///
+    /// # Arguments
+    /// **frame (&[u32])**: 0xRRGGBB color of each pixel, line by line
+    /// (160 x 144)
+    ///
+    /// # Returns
+    /// **`ImageResult<()>`**: Error encountered while writing the file, if
+    /// any
+    pub fn record(&mut self, frame: &[u32]) -> ImageResult<()> {
+        let path = self
+            .directory
+            .join(format!("frame_{:05}.png", self.n_frames));
+        save_png(&path, WIDTH, HEIGHT, frame)?;
+        self.n_frames += 1;
+        Ok(())
+    }
+
+    /// Returns the number of frames written
+    ///
+    /// # Returns
+    /// **usize**: Number of PNG files written by the recorder
+    pub fn n_frames(&self) -> usize {
+        self.n_frames
+    }
+}
+
+/// Writes an image as a PNG file
+///
+/// # Arguments
+/// **path (&Path)**: Path of the file
+/// **width (u32)**: Number of columns of the image
+/// **height (u32)**: Number of lines of the image
+/// **pixels (&[u32])**: 0xRRGGBB color of each pixel, line by line
+///
+/// # Returns
+/// **`ImageResult<()>`**: Error encountered while writing the file, if any
+pub fn save_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[u32]
+) -> ImageResult<()> {
+    let image = RgbImage::from_fn(width, height, |x, y| {
+        let color = pixels[(y * width + x) as usize];
+        Rgb([(color >> 16) as u8, (color >> 8) as u8, color as u8])
+    });
+    image.save(path)
+}
@@ -1,7 +1,10 @@
 extern crate minifb;
 
 use minifb::{Key, Window, WindowOptions};
+use std::path::Path;
+use crate::state::filter::Filter;
 use crate::state::key_state::KeyState;
+use crate::state::palette::Palette;
 
 /// Game Boy screen width
 const WIDTH: u8 = 160; 
@@ -9,6 +12,8 @@ const WIDTH: u8 = 160;
 const HEIGHT: u8 = 144;
 /// Scale of the window of the emulator
 const PIXEL_SIZE: usize = 5;
+/// Number of players whose buttons are known (the keyboard is the player 0)
+pub const N_PLAYERS: usize = 2;
 
 /// Key Mapping
 const KEY_START: Key = Key::I;
@@ -25,10 +30,23 @@ const KEY_QUIT: Key = Key::Escape;
 pub struct Screen {
     /// Buffer for the screen
     buffer: Vec<u32>,
-    /// Window to draw on
-    window: Window,
-    /// State of the key presses
-    pub key_state: KeyState,
+    /// Color of each pixel of the GameBoy, line by line (160 x 144)
+    frame: Vec<u32>,
+    /// Last frame presented by a headless screen, line by line (160 x 144)
+    framebuffer: Vec<u32>,
+    /// Window to draw on (None when the screen is headless)
+    window: Option<Window>,
+    /// State of the key presses of each player
+    key_states: Vec<KeyState>,
+    /// Colors displayed for the shades of the GameBoy
+    palette: Palette,
+    /// Function giving the color of each shade instead of the palette, if
+    /// set
+    color_mapper: Option<Box<dyn FnMut(u8) -> u32>>,
+    /// Post-processing applied to the image shown in the window
+    filter: Filter,
+    /// Last frame presented, upscaled and filtered (empty without filter)
+    filtered_buffer: Vec<u32>,
 }
 
 impl Screen {
@@ -42,7 +60,9 @@ impl Screen {
                 0xFFFFFF;
                 PIXEL_SIZE * WIDTH as usize * PIXEL_SIZE * HEIGHT as usize
             ],
-            window: Window::new(
+            frame: vec![0xFFFFFF; WIDTH as usize * HEIGHT as usize],
+            framebuffer: vec![0xFFFFFF; WIDTH as usize * HEIGHT as usize],
+            window: Some(Window::new(
                 "Game Boy Graphics",
                 PIXEL_SIZE * WIDTH as usize,
                 PIXEL_SIZE * HEIGHT as usize,
@@ -51,28 +71,109 @@ impl Screen {
                 .unwrap_or_else(|e| {
                     panic!("Could not create screen: {}", e);
                 }
-            ),
-            key_state: KeyState::new(),
+            )),
+            key_states: vec![KeyState::new(); N_PLAYERS],
+            palette: Palette::default(),
+            color_mapper: None,
+            filter: Filter::None,
+            filtered_buffer: vec![],
         };
         res.update();
         res
     }
 
+    /// Create a screen without any window
+    ///
+    /// The pixels are still received in the buffer, but nothing is displayed
+    /// and no key is ever pressed.
+    ///
+    /// # Returns
+    /// **Screen**: Screen that can be used by the emulator without a display
+    pub fn headless() -> Screen {
+        Screen {
+            buffer: vec![
+                0xFFFFFF;
+                PIXEL_SIZE * WIDTH as usize * PIXEL_SIZE * HEIGHT as usize
+            ],
+            frame: vec![0xFFFFFF; WIDTH as usize * HEIGHT as usize],
+            framebuffer: vec![0xFFFFFF; WIDTH as usize * HEIGHT as usize],
+            window: None,
+            key_states: vec![KeyState::new(); N_PLAYERS],
+            palette: Palette::default(),
+            color_mapper: None,
+            filter: Filter::None,
+            filtered_buffer: vec![],
+        }
+    }
+
     /// Verify what button is being pushed
     ///
     /// # Returns
     /// **bool**: Is the escape key being pressed
     pub fn update_key_press(&mut self) -> bool {
+        if self.window.is_none() {
+            return false;
+        }
         self.update();
-        self.key_state.is_start_pressed = self.window.is_key_down(KEY_START);
-        self.key_state.is_select_pressed = self.window.is_key_down(KEY_SELECT);
-        self.key_state.is_a_pressed = self.window.is_key_down(KEY_A);
-        self.key_state.is_b_pressed = self.window.is_key_down(KEY_B);
-        self.key_state.is_up_pressed = self.window.is_key_down(KEY_UP);
-        self.key_state.is_down_pressed = self.window.is_key_down(KEY_DOWN);
-        self.key_state.is_right_pressed = self.window.is_key_down(KEY_RIGHT);
-        self.key_state.is_left_pressed = self.window.is_key_down(KEY_LEFT);
-        self.window.is_key_down(KEY_QUIT)
+        let Some(window) = &self.window else {
+            return false;
+        };
+        // The keyboard controls the player 0
+        let key_state = &mut self.key_states[0];
+        key_state.is_start_pressed = window.is_key_down(KEY_START);
+        key_state.is_select_pressed = window.is_key_down(KEY_SELECT);
+        key_state.is_a_pressed = window.is_key_down(KEY_A);
+        key_state.is_b_pressed = window.is_key_down(KEY_B);
+        key_state.is_up_pressed = window.is_key_down(KEY_UP);
+        key_state.is_down_pressed = window.is_key_down(KEY_DOWN);
+        key_state.is_right_pressed = window.is_key_down(KEY_RIGHT);
+        key_state.is_left_pressed = window.is_key_down(KEY_LEFT);
+        window.is_key_down(KEY_QUIT)
+    }
+
+    /// Change the state of the keys of the player 0
+    ///
+    /// Used to press buttons without any window, the state is overwritten by
+    /// the keyboard when there is a window.
+    ///
+    /// # Arguments
+    /// **key_state (KeyState)**: New state of the keys
+    pub fn set_key_state(&mut self, key_state: KeyState) {
+        self.set_key_state_for(0, key_state);
+    }
+
+    /// Returns the state of the keys of the keyboard
+    ///
+    /// # Returns
+    /// **&KeyState**: Keys pressed by the player 0
+    pub fn key_state(&self) -> &KeyState {
+        &self.key_states[0]
+    }
+
+    /// Change the state of the keys of a player
+    ///
+    /// Only the player 0 is controlled by the keyboard, the others can only
+    /// be fed by the program. Ignored if the player does not exist.
+    ///
+    /// # Arguments
+    /// **player (usize)**: Index of the player (lower than N_PLAYERS)
+    /// **key_state (KeyState)**: New state of the keys
+    pub fn set_key_state_for(&mut self, player: usize, key_state: KeyState) {
+        if let Some(state) = self.key_states.get_mut(player) {
+            *state = key_state;
+        }
+    }
+
+    /// Returns the state of the keys of a player
+    ///
+    /// # Arguments
+    /// **player (usize)**: Index of the player
+    ///
+    /// # Returns
+    /// **`Option<&KeyState>`**: Keys pressed by this player, None if the
+    /// index is not lower than N_PLAYERS
+    pub fn key_state_for(&self, player: usize) -> Option<&KeyState> {
+        self.key_states.get(player)
     }
 
     /// Change the color of a pixel of the GameBoy
@@ -87,36 +188,141 @@ impl Screen {
         y: u8,
         c: u8
     ) {
+        let color = match &mut self.color_mapper {
+            Some(color_mapper) => color_mapper(c),
+            None => self.palette.color(c),
+        };
+        self.frame[y as usize * WIDTH as usize + x as usize] = color;
         for i in 0..PIXEL_SIZE {
             for j in 0..PIXEL_SIZE {
                 self.buffer[
                     i + PIXEL_SIZE * y as usize * WIDTH as usize +
                     j + PIXEL_SIZE * x as usize
-                ]  = match c {
-                    0x01 => {
-                        0x555555
-                    },
-                    0x02 => {
-                        0xAAAAAA
-                    },
-                    0x03 => {
-                        0x000000
-                    },
-                    _ => {
-                        0xFFFFFF
-                    }
-                }
+                ] = color;
             }
         }
     }
 
+    /// Replaces the colors displayed by those of a .pal file
+    ///
+    /// # Arguments
+    /// **path (&Path)**: Path of the .pal file
+    ///
+    /// # Returns
+    /// **`Result<(), String>`**: Message explaining why the file is invalid,
+    /// if it is
+    pub fn load_palette_file(&mut self, path: &Path) -> Result<(), String> {
+        self.palette = Palette::from_file(path)?;
+        Ok(())
+    }
+
+    /// Replaces the colors displayed for the shades of the GameBoy
+    ///
+    /// # Arguments
+    /// **palette (Palette)**: New colors of the shades
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Gives the color of each pixel with a function instead of the palette
+    ///
+    /// The function is called for every pixel, so it can change the colors
+    /// from one frame to the next (fade to black...).
+    ///
+    /// # Arguments
+    /// **color_mapper (impl FnMut(u8) -> u32)**: Function returning the
+    /// 0xRRGGBB color of a shade (00 to 11 for white to black)
+    pub fn set_color_mapper(
+        &mut self,
+        color_mapper: impl FnMut(u8) -> u32 + 'static
+    ) {
+        self.color_mapper = Some(Box::new(color_mapper));
+    }
+
+    /// Gives the color of each pixel with the palette again
+    pub fn clear_color_mapper(&mut self) {
+        self.color_mapper = None;
+    }
+
+    /// Returns the colors displayed for the shades of the GameBoy
+    ///
+    /// # Returns
+    /// **Palette**: Current palette of the screen
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Returns the color of the pixels of the GameBoy
+    ///
+    /// # Returns
+    /// **&[u32]**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    /// Returns the last frame presented
+    ///
+    /// # Returns
+    /// **&[u32]**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer
+    }
+
+    /// Presents a completed frame
+    ///
+    /// The received pixels are copied to the framebuffer, and displayed if
+    /// there is a window.
+    pub fn present(&mut self) {
+        self.framebuffer.copy_from_slice(&self.frame);
+        if self.filter != Filter::None && self.window.is_some() {
+            self.filter_framebuffer();
+        }
+        self.update();
+    }
+
+    /// Change the post-processing applied to the image shown in the window
+    ///
+    /// # Arguments
+    /// **filter (Filter)**: New post-processing, applied from the next frame
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        if filter == Filter::None {
+            self.filtered_buffer = vec![];
+        } else if self.window.is_some() {
+            self.filter_framebuffer();
+        }
+    }
+
+    /// Upscales the last frame presented and applies the filter to it
+    fn filter_framebuffer(&mut self) {
+        let width = PIXEL_SIZE * WIDTH as usize;
+        self.filtered_buffer.resize(width * PIXEL_SIZE * HEIGHT as usize, 0);
+        for (i, color) in self.filtered_buffer.iter_mut().enumerate() {
+            let (x, y) = ((i % width) / PIXEL_SIZE, (i / width) / PIXEL_SIZE);
+            *color = self.framebuffer[y * WIDTH as usize + x];
+        }
+        self.filter.apply(&mut self.filtered_buffer, width, PIXEL_SIZE);
+    }
+
     /// Refresh the screen
     pub fn update(&mut self) {
-        self.window
+        let Some(window) = &mut self.window else {
+            return;
+        };
+        let (buffer, width, height) = if self.filter == Filter::None {
+            (&self.buffer, WIDTH as usize, HEIGHT as usize)
+        } else {
+            (
+                &self.filtered_buffer,
+                PIXEL_SIZE * WIDTH as usize,
+                PIXEL_SIZE * HEIGHT as usize
+            )
+        };
+        window
             .update_with_buffer_size(
-                &self.buffer,
-                WIDTH as usize,
-                HEIGHT as usize
+                buffer,
+                width,
+                height
             ).unwrap_or_else(|e| {
                 panic!("{}", e);
         });
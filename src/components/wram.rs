@@ -1,3 +1,6 @@
+use crate::state::ram_init::RamInit;
+
+#[derive(Hash)]
 /// Contains the data of the Working ram
 pub struct Wram {
     ram: Vec<u8>
@@ -37,4 +40,12 @@ impl Wram {
     ) {
         self.ram[(address - 0xC000) as usize] = value;
     }
+
+    /// Overwrite every byte of the WRAM with a pattern
+    ///
+    /// # Arguments
+    /// **pattern (RamInit)**: Values written in the WRAM
+    pub fn fill(&mut self, pattern: RamInit) {
+        pattern.fill(&mut self.ram);
+    }
 }
@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::{self, Read};
+use crate::components::cpu::CPU;
+use crate::state::button::Button;
+use crate::state::key_state::KeyState;
+use crate::state::palette::Palette;
+use crate::state::save_state::SaveState;
+use crate::state::turbo::Turbo;
+
+/// Front-end interface of the emulator
+///
+/// Wraps a CPU without window, paced by the host program. The CPU stays
+/// available through cpu and cpu_mut for the advanced uses.
+pub struct Emulator {
+    /// CPU of the emulated gameboy
+    cpu: CPU,
+    /// Keys held by the player, before the turbos are applied
+    held_keys: KeyState,
+    /// Buttons pressed repeatedly while their key is held
+    turbos: Vec<Turbo>,
+}
+
+impl Emulator {
+    /// Create an emulator running a game
+    ///
+    /// # Arguments
+    /// **rom_path (&str)**: Path of the file containing the ROM of the game
+    ///
+    /// # Returns
+    /// **`Result<Emulator, io::Error>`**: New emulator, ready to run the
+    /// first frame, or why the ROM cannot be read
+    pub fn new(rom_path: &str) -> Result<Self, io::Error> {
+        Self::check_rom(rom_path)?;
+        Ok(Self {
+            cpu: CPU::new_embedded(rom_path),
+            held_keys: KeyState::new(),
+            turbos: vec![],
+        })
+    }
+
+    /// Saves the current game and starts another one
+    ///
+    /// # Arguments
+    /// **rom_path (&str)**: Path of the file containing the ROM of the game
+    ///
+    /// # Returns
+    /// **`Result<(), io::Error>`**: Why the ROM cannot be read, in which case
+    /// the current game keeps running
+    pub fn load_rom(&mut self, rom_path: &str) -> Result<(), io::Error> {
+        Self::check_rom(rom_path)?;
+        self.cpu.load_cartridge(rom_path);
+        Ok(())
+    }
+
+    /// Checks that a ROM can be read before the CPU loads it
+    ///
+    /// # Arguments
+    /// **rom_path (&str)**: Path of the file containing the ROM of the game
+    ///
+    /// # Returns
+    /// **`Result<(), io::Error>`**: Why the ROM cannot be read, if it cannot
+    fn check_rom(rom_path: &str) -> Result<(), io::Error> {
+        File::open(rom_path)?.read_exact(&mut [0; 1])
+    }
+
+    /// Runs the emulation for the duration of one frame
+    ///
+    /// # Returns
+    /// **u32**: Number of cycles used during the frame
+    pub fn run_frame(&mut self) -> u32 {
+        let n_cycles = self.cpu.run_frame();
+        for turbo in &mut self.turbos {
+            let is_held = self.held_keys.is_pressed(turbo.button());
+            turbo.advance(is_held, n_cycles as u64);
+        }
+        self.apply_buttons();
+        n_cycles
+    }
+
+    /// Returns the last completed frame
+    ///
+    /// # Returns
+    /// **&[u32]**: 0xRRGGBB color of each pixel, line by line (160 x 144)
+    pub fn framebuffer(&self) -> &[u32] {
+        self.cpu.framebuffer()
+    }
+
+    /// Change what buttons are pressed
+    ///
+    /// # Arguments
+    /// **keys (KeyState)**: Buttons pressed from now on
+    pub fn set_buttons(&mut self, keys: KeyState) {
+        self.held_keys = keys;
+        self.apply_buttons();
+    }
+
+    /// Presses and releases a button repeatedly while its key is held
+    ///
+    /// # Arguments
+    /// **button (Button)**: Button pressed repeatedly
+    /// **hz (u32)**: Number of presses per second, 0 to disable the turbo
+    pub fn set_turbo_button(&mut self, button: Button, hz: u32) {
+        self.turbos.retain(|turbo| turbo.button() != button);
+        if hz > 0 {
+            self.turbos.push(Turbo::new(button, hz));
+        }
+        self.apply_buttons();
+    }
+
+    /// Gives the held keys to the game, with the buttons of the turbos
+    /// pressed or released
+    fn apply_buttons(&mut self) {
+        let mut keys = self.held_keys.clone();
+        for turbo in &self.turbos {
+            let is_held = self.held_keys.is_pressed(turbo.button());
+            keys.set_pressed(turbo.button(), turbo.is_pressed(is_held));
+        }
+        self.cpu.set_buttons(keys);
+    }
+
+    /// Copy the state of the whole gameboy
+    ///
+    /// # Returns
+    /// **SaveState**: Copy of the state, that can be given to load_state
+    pub fn save_state(&self) -> SaveState {
+        self.cpu.save_state()
+    }
+
+    /// Restore the state of the whole gameboy
+    ///
+    /// # Arguments
+    /// **state (&SaveState)**: State to restore, as returned by save_state
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.cpu.load_state(state);
+    }
+
+    /// Replaces the colors displayed for the shades of the GameBoy
+    ///
+    /// # Arguments
+    /// **palette (Palette)**: New colors of the shades
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.set_palette(palette);
+    }
+
+    /// Gives access to the CPU
+    ///
+    /// # Returns
+    /// **&CPU**: CPU of the emulated gameboy
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// Gives a mutable access to the CPU
+    ///
+    /// # Returns
+    /// **&mut CPU**: CPU of the emulated gameboy
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_emulator() {
+        // Turns the LCD on with every shade of the background black, then
+        // loops forever
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x010A].copy_from_slice(&[
+            0x3E, 0x91, // LD A, 0x91
+            0xE0, 0x40, // LDH (0x40), A
+            0x3E, 0xFF, // LD A, 0xFF
+            0xE0, 0x47, // LDH (0x47), A
+            0x18, 0xFE, // JR -2
+        ]);
+        let path = env::temp_dir().join("gb_emulator_rust_test_emulator.gb");
+        fs::write(&path, rom).unwrap();
+        let mut emulator = Emulator::new(&path.display().to_string())
+            .unwrap();
+        assert!(emulator.framebuffer().iter().all(|color| *color == 0xFFFFFF));
+        emulator.set_buttons(KeyState::new());
+        emulator.run_frame();
+        emulator.run_frame();
+        assert!(emulator.framebuffer().iter().all(|color| *color == 0x000000));
+        let state = emulator.save_state();
+        emulator.set_palette(
+            Palette::new([0xE0F8D0, 0x88C070, 0x346856, 0x081820])
+        );
+        emulator.run_frame();
+        assert!(emulator.framebuffer().iter().all(|color| *color == 0x081820));
+        emulator.load_state(&state);
+        assert_eq!(emulator.cpu().reg_pc(), state.registers.pc);
+        emulator.load_rom(&path.display().to_string()).unwrap();
+        assert_eq!(emulator.cpu().reg_pc(), 0x0100);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_rom() {
+        let missing = env::temp_dir().join("gb_emulator_rust_missing.gb");
+        let missing = missing.display().to_string();
+        let error = Emulator::new(&missing).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        let mut emulator = Emulator::new("cartridges/Tetris.gb").unwrap();
+        emulator.run_frame();
+        let pc = emulator.cpu().reg_pc();
+        assert!(emulator.load_rom(&missing).is_err());
+        // A folder cannot be read either
+        let folder = env::temp_dir().display().to_string();
+        assert!(emulator.load_rom(&folder).is_err());
+        assert_eq!(emulator.cpu().reg_pc(), pc);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod components;
-mod state;
+pub mod emulator;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+pub mod state;
@@ -1,47 +1,232 @@
+use std::env;
 use std::fs;
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
 use gb_emulator_rust::components::cpu::CPU;
 
 /// Name of the foler where the cartridge will be searched
 const CARTRIDGES_FOLDER_NAME: &str = "cartridges";
 
+/// Environment variable overriding the folder of the cartridges
+const CARTRIDGES_DIR_VARIABLE: &str = "GB_CARTRIDGES_DIR";
+
+/// Number of cycles each ROM runs for in batch mode (one minute of GameBoy)
+const BATCH_CYCLE_BUDGET: u64 = 4_194_304 * 60;
+
+/// Parses the address given to --entry
+///
+/// # Arguments
+/// **text (&str)**: Address in hexadecimal, with or without 0x
+///
+/// # Returns
+/// **`Option<u16>`**: Address, if the text is valid
+fn parse_entry_point(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Returns the folder where the cartridges are searched
+///
+/// # Returns
+/// **PathBuf**: Value of GB_CARTRIDGES_DIR if set, cartridges otherwise
+fn cartridges_folder() -> PathBuf {
+    env::var_os(CARTRIDGES_DIR_VARIABLE)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(CARTRIDGES_FOLDER_NAME))
+}
+
+/// Lists the files of the cartridge folder
+///
+/// # Arguments
+/// **dir (&Path)**: Folder containing the cartridges
+///
+/// # Returns
+/// **`Result<Vec<PathBuf>, String>`**: Paths of the files of the folder
+/// sorted by name, or a message explaining what to do if there is none
+fn list_cartridges(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut cartridges: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect()
+        )
+        .unwrap_or_default();
+    if cartridges.is_empty() {
+        return Err(format!(
+            "No ROMs found in {}; pass a path or set {}",
+            dir.display(),
+            CARTRIDGES_DIR_VARIABLE
+        ));
+    }
+    cartridges.sort();
+    Ok(cartridges)
+}
+
 /// Allow the user to chose one of the file of the cartridge folder
 ///
+/// # Arguments
+/// **cartridges (&[PathBuf])**: Files of the cartridge folder
+///
 /// # Returns
 /// **String**: Name of the chosen file
-fn chose_cartridge() -> String {
-    for (i, path) in fs::read_dir(CARTRIDGES_FOLDER_NAME)
-        .unwrap()
-        .enumerate() {
-        println!(
-            "Chose {} for {}",
-            i,
-            path.unwrap().path().display()
-        );
+fn chose_cartridge(cartridges: &[PathBuf]) -> String {
+    for (i, path) in cartridges.iter().enumerate() {
+        println!("Chose {} for {}", i, path.display());
     }
     let mut answer = String::new();
     io::stdin()
         .read_line(&mut answer)
         .expect("Failed to read the answer");
-    format!(
-        "{}",
-        fs::read_dir(CARTRIDGES_FOLDER_NAME)
-            .unwrap()
-            .nth(
-                answer
-                    .trim()
-                    .parse()
-                    .expect("Integer not found")
-            ).expect("Wrong value")
-            .unwrap()
-            .path()
-            .display()
-    )
+    let index: usize = answer
+        .trim()
+        .parse()
+        .expect("Integer not found");
+    cartridges
+        .get(index)
+        .expect("Wrong value")
+        .display()
+        .to_string()
+}
+
+/// Lists the ROMs of a directory
+///
+/// # Arguments
+/// **dir (&Path)**: Directory containing the ROMs
+///
+/// # Returns
+/// **`io::Result<Vec<PathBuf>>`**: Paths of the .gb and .gbc files of the
+/// directory, sorted by name
+fn batch_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("gb") | Some("gbc")
+        ))
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+/// Runs every ROM of a directory without window and prints their results
+///
+/// A ROM passes if it prints "Passed" on the serial port.
+///
+/// # Arguments
+/// **dir (&Path)**: Directory containing the ROMs
+fn run_batch(dir: &Path) {
+    let roms = batch_roms(dir).expect("Cannot read the batch directory");
+    for rom in roms {
+        let path = rom.display().to_string();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut cpu = CPU::new_embedded(&path);
+            cpu.run_until_serial_contains("Passed", BATCH_CYCLE_BUDGET);
+            cpu.serial_output()
+        }));
+        match result {
+            Ok(serial_output) => {
+                println!("{}:\n{}", path, serial_output);
+                if serial_output.contains("Passed") {
+                    println!("{}: PASS", path);
+                } else {
+                    println!("{}: FAIL", path);
+                }
+            },
+            Err(_) => {
+                println!("{}: FAIL (crashed)", path);
+            }
+        }
+    }
 }
 
 /// Emulate a GameBoy DMG
+///
+/// With `--batch <dir>`, runs every ROM of the directory without window.
+/// With a path, runs this ROM, otherwise asks which ROM of the cartridge
+/// folder to run. `<path> --entry <address>` starts the ROM at the given
+/// address instead of 0x0100.
 fn main() {
-    let cartridge_name = chose_cartridge();
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--batch") {
+        if args.len() != 3 {
+            eprintln!("Usage: {} --batch <dir>", args[0]);
+            process::exit(1);
+        }
+        run_batch(Path::new(&args[2]));
+        return;
+    }
+    let entry_point = if args.len() == 4 && args[2] == "--entry" {
+        match parse_entry_point(&args[3]) {
+            Some(address) => Some(address),
+            None => {
+                eprintln!("Invalid entry point {}", args[3]);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let cartridge_name = if args.len() == 2 || entry_point.is_some() {
+        args[1].clone()
+    } else {
+        match list_cartridges(&cartridges_folder()) {
+            Ok(cartridges) => chose_cartridge(&cartridges),
+            Err(message) => {
+                eprintln!("{}", message);
+                process::exit(1);
+            }
+        }
+    };
     let mut cpu = CPU::new(&cartridge_name);
+    cpu.set_tracing(true);
+    if let Some(address) = entry_point {
+        cpu.set_pc(address);
+    }
     cpu.run();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_roms() {
+        let dir = env::temp_dir().join("gb_emulator_rust_test_batch_roms");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["b.gbc", "a.gb", "notes.txt", "c.gb.save"] {
+            fs::write(dir.join(name), []).unwrap();
+        }
+        let roms = batch_roms(&dir).unwrap();
+        assert_eq!(roms, vec![dir.join("a.gb"), dir.join("b.gbc")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_entry_point() {
+        assert_eq!(parse_entry_point("0xC000"), Some(0xC000));
+        assert_eq!(parse_entry_point("150"), Some(0x0150));
+        assert_eq!(parse_entry_point("0x10000"), None);
+        assert_eq!(parse_entry_point("start"), None);
+    }
+
+    #[test]
+    fn test_list_cartridges_empty() {
+        let dir = env::temp_dir().join("gb_emulator_rust_test_no_cartridge");
+        let _ = fs::remove_dir_all(&dir);
+        let message = list_cartridges(&dir).unwrap_err();
+        assert!(message.contains("No ROMs found"));
+        assert!(message.contains("GB_CARTRIDGES_DIR"));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(list_cartridges(&dir).is_err());
+        fs::write(dir.join("game.gb"), []).unwrap();
+        assert_eq!(list_cartridges(&dir).unwrap(), vec![dir.join("game.gb")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use serde::Deserialize;
+use crate::components::cpu::CPU;
+
+/// Test ROM of a manifest and what it prints when it passes
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ManifestRom {
+    /// Path of the ROM, relative to the folder of the manifest
+    pub path: String,
+    /// Number of cycles after which the ROM fails
+    pub max_cycles: u64,
+    /// Text printed on the serial port by the ROM when it passes
+    pub expected_serial: String,
+}
+
+/// Suite of test ROMs, written in TOML as a list of [[rom]] tables
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct Manifest {
+    /// ROMs of the suite, in the order they are run
+    #[serde(default, rename = "rom")]
+    pub roms: Vec<ManifestRom>,
+}
+
+/// Outcome of one ROM of a manifest
+#[derive(Debug, PartialEq)]
+pub struct ManifestResult {
+    /// Path of the ROM, as written in the manifest
+    pub path: String,
+    /// Did the ROM print the expected text before the end of its cycles
+    pub passed: bool,
+    /// Everything printed by the ROM, empty if it crashed the emulator
+    pub serial_output: String,
+}
+
+impl Manifest {
+    /// Reads a manifest
+    ///
+    /// # Arguments
+    /// **text (&str)**: Content of the manifest
+    ///
+    /// # Returns
+    /// **`Result<Manifest, String>`**: ROMs of the manifest, or why it is
+    /// invalid
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+/// Runs a ROM without window until it prints the expected text
+///
+/// # Arguments
+/// **rom (&ManifestRom)**: ROM to run
+/// **folder (&Path)**: Folder of the manifest
+///
+/// # Returns
+/// **ManifestResult**: Outcome of the ROM
+fn run_rom(rom: &ManifestRom, folder: &Path) -> ManifestResult {
+    let rom_path = folder.join(&rom.path).display().to_string();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut cpu = CPU::new_embedded(&rom_path);
+        let passed = cpu.run_until_serial_contains(
+            &rom.expected_serial,
+            rom.max_cycles
+        );
+        (passed, cpu.serial_output())
+    }));
+    let (passed, serial_output) = result.unwrap_or((false, String::new()));
+    ManifestResult {
+        path: rom.path.clone(),
+        passed,
+        serial_output,
+    }
+}
+
+/// Runs every ROM of a manifest and prints whether it passed
+///
+/// # Arguments
+/// **path (&str)**: Path of the TOML manifest
+///
+/// # Returns
+/// **`Result<Vec<ManifestResult>, String>`**: Outcome of each ROM in the
+/// order of the manifest, or why the manifest could not be read
+pub fn run_manifest(path: &str) -> Result<Vec<ManifestResult>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let manifest = Manifest::parse(&text)?;
+    let folder = Path::new(path).parent().unwrap_or(Path::new(""));
+    let results: Vec<ManifestResult> = manifest.roms
+        .iter()
+        .map(|rom| run_rom(rom, folder))
+        .collect();
+    for result in &results {
+        println!(
+            "{}: {}",
+            result.path,
+            if result.passed { "PASS" } else { "FAIL" }
+        );
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = Manifest::parse(r#"
+            [[rom]]
+            path = "cpu_instrs/01-special.gb"
+            max_cycles = 100_000_000
+            expected_serial = "Passed"
+
+            [[rom]]
+            path = "instr_timing.gb"
+            max_cycles = 50_000_000
+            expected_serial = "Passed all tests"
+        "#).unwrap();
+        assert_eq!(manifest.roms, vec![
+            ManifestRom {
+                path: "cpu_instrs/01-special.gb".to_string(),
+                max_cycles: 100_000_000,
+                expected_serial: "Passed".to_string(),
+            },
+            ManifestRom {
+                path: "instr_timing.gb".to_string(),
+                max_cycles: 50_000_000,
+                expected_serial: "Passed all tests".to_string(),
+            },
+        ]);
+        assert_eq!(Manifest::parse("").unwrap(), Manifest::default());
+        assert!(Manifest::parse("[[rom]]\npath = \"a.gb\"").is_err());
+    }
+}
@@ -0,0 +1,13 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+/// Registers and internal state of the channels of the APU
+pub struct ApuState {
+    /// NR41, NR42, NR43 and NR44
+    pub channel4_registers: [u8; 4],
+    /// Linear Feedback Shift Register generating the noise of channel 4
+    pub channel4_lfsr: u16,
+    /// Has channel 4 been triggered
+    pub is_channel4_on: bool,
+    /// Number of CPU cycles since the last clock of the LFSR
+    pub channel4_cycles: u32,
+}
@@ -0,0 +1,20 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// One of the eight buttons of the GameBoy
+pub enum Button {
+    /// The A button
+    A,
+    /// The B button
+    B,
+    /// The select button
+    Select,
+    /// The start button
+    Start,
+    /// The Up Arrow
+    Up,
+    /// The Down Arrow
+    Down,
+    /// The Left Arrow
+    Left,
+    /// The Right Arrow
+    Right,
+}
@@ -0,0 +1,83 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Post-processing applied to the upscaled image shown in the window
+pub enum Filter {
+    /// The pixels are shown as they are
+    #[default]
+    None,
+    /// One line out of two is darker, like on a CRT
+    Scanlines,
+    /// The border of each pixel of the GameBoy is darker, like the grid of
+    /// its LCD
+    LcdGrid,
+}
+
+impl Filter {
+    /// Applies the filter to an upscaled image
+    ///
+    /// # Arguments
+    /// **buffer (&mut [u32])**: 0xRRGGBB color of each pixel, line by line
+    /// **width (usize)**: Number of pixels of each line
+    /// **pixel_size (usize)**: Width and height of the square drawn for each
+    /// pixel of the GameBoy
+    pub fn apply(&self, buffer: &mut [u32], width: usize, pixel_size: usize) {
+        for (i, color) in buffer.iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+            let is_darkened = match self {
+                Filter::None => false,
+                Filter::Scanlines => y % 2 == 1,
+                Filter::LcdGrid =>
+                    x % pixel_size == pixel_size - 1 ||
+                    y % pixel_size == pixel_size - 1,
+            };
+            if is_darkened {
+                *color = Self::darken(*color);
+            }
+        }
+    }
+
+    /// Returns a color with three quarters of its intensity
+    ///
+    /// # Arguments
+    /// **color (u32)**: 0xRRGGBB color
+    ///
+    /// # Returns
+    /// **u32**: Darker 0xRRGGBB color
+    fn darken(color: u32) -> u32 {
+        ((color >> 1) & 0x7F7F7F) + ((color >> 2) & 0x3F3F3F)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanlines() {
+        let mut buffer = vec![0xFFFFFF; 4 * 4];
+        Filter::Scanlines.apply(&mut buffer, 4, 2);
+        for (i, color) in buffer.iter().enumerate() {
+            let expected = if (i / 4) % 2 == 1 { 0xBEBEBE } else { 0xFFFFFF };
+            assert_eq!(*color, expected);
+        }
+        let mut buffer = vec![0x804020; 4 * 4];
+        Filter::None.apply(&mut buffer, 4, 2);
+        assert!(buffer.iter().all(|color| *color == 0x804020));
+        Filter::Scanlines.apply(&mut buffer, 4, 2);
+        assert_eq!(buffer[4], 0x603018);
+    }
+
+    #[test]
+    fn test_lcd_grid() {
+        let mut buffer = vec![0xFFFFFF; 6 * 3];
+        Filter::LcdGrid.apply(&mut buffer, 6, 3);
+        let darkened: Vec<bool> = buffer
+            .iter()
+            .map(|color| *color != 0xFFFFFF)
+            .collect();
+        assert_eq!(darkened, vec![
+            false, false, true, false, false, true,
+            false, false, true, false, false, true,
+            true, true, true, true, true, true,
+        ]);
+    }
+}
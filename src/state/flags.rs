@@ -0,0 +1,34 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+/// Flags of the CPU, stored in the upper nibble of the F register
+pub struct Flags {
+    /// Zero flag (bit 7): the result was 0
+    pub z: bool,
+    /// Subtraction flag (bit 6): the last operation was a subtraction
+    pub n: bool,
+    /// Half carry flag (bit 5): carry from the lower nibble
+    pub h: bool,
+    /// Carry flag (bit 4): carry from the highest bit
+    pub c: bool,
+}
+
+impl From<u8> for Flags {
+    /// Decodes the flags from the F register, ignoring its lower nibble
+    fn from(f: u8) -> Self {
+        Self {
+            z: f & 0x80 != 0,
+            n: f & 0x40 != 0,
+            h: f & 0x20 != 0,
+            c: f & 0x10 != 0,
+        }
+    }
+}
+
+impl From<Flags> for u8 {
+    /// Encodes the flags as in the F register, with its lower nibble clear
+    fn from(flags: Flags) -> Self {
+        (flags.z as u8) << 7
+            | (flags.n as u8) << 6
+            | (flags.h as u8) << 5
+            | (flags.c as u8) << 4
+    }
+}
@@ -0,0 +1,53 @@
+/// Counts the pixels that differ between two frames
+///
+/// When the frames do not have the same size, the pixels missing from the
+/// shorter one count as different. A test can compare the result with a
+/// tolerance to accept small rendering changes.
+///
+/// # Arguments
+/// **a (&[u32])**: First frame, one color per pixel
+/// **b (&[u32])**: Second frame, one color per pixel
+///
+/// # Returns
+/// **usize**: Number of differing pixels
+pub fn frame_diff(a: &[u32], b: &[u32]) -> usize {
+    let n_common_differences = a
+        .iter()
+        .zip(b)
+        .filter(|(pixel_a, pixel_b)| pixel_a != pixel_b)
+        .count();
+    n_common_differences + a.len().abs_diff(b.len())
+}
+
+/// Checks that two frames are identical
+///
+/// # Arguments
+/// **a (&[u32])**: First frame, one color per pixel
+/// **b (&[u32])**: Second frame, one color per pixel
+///
+/// # Returns
+/// **bool**: true iff the frames have the same size and pixels
+pub fn frames_equal(a: &[u32], b: &[u32]) -> bool {
+    frame_diff(a, b) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_diff() {
+        let a = vec![0x00FFFFFF; 160 * 144];
+        let mut b = a.clone();
+        assert_eq!(frame_diff(&a, &b), 0);
+        assert!(frames_equal(&a, &b));
+        for index in [0, 1000, 160 * 144 - 1] {
+            b[index] = 0x00000000;
+        }
+        assert_eq!(frame_diff(&a, &b), 3);
+        assert_eq!(frame_diff(&b, &a), 3);
+        assert!(!frames_equal(&a, &b));
+        assert_eq!(frame_diff(&a, &a[..160]), 160 * 143);
+        assert!(!frames_equal(&a, &a[..160]));
+    }
+}
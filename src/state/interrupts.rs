@@ -0,0 +1,46 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Set of interruptions, as found in the five lowest bits of IF or IE
+pub struct Interrupts {
+    /// Bit 0: the PPU entered the VBlank
+    pub vblank: bool,
+    /// Bit 1: one of the enabled STAT conditions was met
+    pub stat: bool,
+    /// Bit 2: TIMA overflowed
+    pub timer: bool,
+    /// Bit 3: a serial transfer completed
+    pub serial: bool,
+    /// Bit 4: a button was pressed
+    pub joypad: bool,
+}
+
+impl Interrupts {
+    /// Decode the interruptions from the value of IF or IE
+    ///
+    /// # Arguments
+    /// **bits (u8)**: Value of the register, the three highest bits are
+    /// ignored
+    ///
+    /// # Returns
+    /// **Interrupts**: Interruptions whose bit is set
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            vblank: bits & 0x01 != 0,
+            stat: bits & 0x02 != 0,
+            timer: bits & 0x04 != 0,
+            serial: bits & 0x08 != 0,
+            joypad: bits & 0x10 != 0,
+        }
+    }
+
+    /// Encode the interruptions as in IF or IE
+    ///
+    /// # Returns
+    /// **u8**: One bit per interruption, from VBlank (bit 0) to joypad (bit 4)
+    pub fn bits(&self) -> u8 {
+        (self.vblank as u8)
+            | (self.stat as u8) << 1
+            | (self.timer as u8) << 2
+            | (self.serial as u8) << 3
+            | (self.joypad as u8) << 4
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use crate::state::button::Button;
+
+#[derive(Clone, Debug)]
 /// Contains information about what key is being pushed
 pub struct KeyState {
     /// Is the start key pressed
@@ -36,4 +38,49 @@ impl KeyState {
             is_left_pressed: false,
         }
     }
+
+    /// Returns whether a button is pressed
+    ///
+    /// # Arguments
+    /// **button (Button)**: Button to check
+    ///
+    /// # Returns
+    /// **bool**: Is the button pressed
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::A => self.is_a_pressed,
+            Button::B => self.is_b_pressed,
+            Button::Select => self.is_select_pressed,
+            Button::Start => self.is_start_pressed,
+            Button::Up => self.is_up_pressed,
+            Button::Down => self.is_down_pressed,
+            Button::Left => self.is_left_pressed,
+            Button::Right => self.is_right_pressed,
+        }
+    }
+
+    /// Press or release a button
+    ///
+    /// # Arguments
+    /// **button (Button)**: Button to change
+    /// **value (bool)**: Is the button pressed from now on
+    pub fn set_pressed(&mut self, button: Button, value: bool) {
+        let is_pressed = match button {
+            Button::A => &mut self.is_a_pressed,
+            Button::B => &mut self.is_b_pressed,
+            Button::Select => &mut self.is_select_pressed,
+            Button::Start => &mut self.is_start_pressed,
+            Button::Up => &mut self.is_up_pressed,
+            Button::Down => &mut self.is_down_pressed,
+            Button::Left => &mut self.is_left_pressed,
+            Button::Right => &mut self.is_right_pressed,
+        };
+        *is_pressed = value;
+    }
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
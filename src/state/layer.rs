@@ -0,0 +1,10 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Layer drawn by the PPU, that can be hidden while debugging
+pub enum Layer {
+    /// Background, scrolled with SCX and SCY
+    Background,
+    /// Window, drawn over the background from WX and WY
+    Window,
+    /// Objects (sprites) from the OAM
+    Objects,
+}
@@ -1,3 +1,23 @@
+pub mod apu_state;
+pub mod button;
+pub mod filter;
+pub mod flags;
+pub mod frame_diff;
+pub mod interrupts;
 pub mod key_state;
-pub mod register;
+pub mod layer;
+pub mod model;
+pub mod mode_transition;
+pub mod palette;
+pub mod ppu_mode;
+pub mod ppu_state;
+pub mod ram_init;
+pub(crate) mod register;
+pub mod registers_snapshot;
+pub mod save_state;
+pub(crate) mod state_hasher;
+pub mod stop_reason;
+pub mod sync_mode;
 pub mod tile_object;
+pub mod timer_state;
+pub mod turbo;
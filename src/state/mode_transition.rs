@@ -0,0 +1,13 @@
+use crate::state::ppu_mode::PpuMode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Change of mode of the PPU, logged when the mode trace is enabled
+pub struct ModeTransition {
+    /// Mode entered by the PPU
+    pub mode: PpuMode,
+    /// Line being drawn when the mode was entered
+    pub ly: u8,
+    /// Dot of the line at which the GameBoy enters the mode (0 for the OAM
+    /// scan and VBlank, 80 for the drawing and 252 for the HBlank)
+    pub dot: u32,
+}
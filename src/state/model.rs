@@ -0,0 +1,25 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Model of GameBoy being emulated
+pub enum Model {
+    /// Original GameBoy (DMG)
+    Dmg,
+    /// GameBoy Color (CGB)
+    Cgb,
+}
+
+impl Model {
+    /// Detect the model to emulate from the CGB flag of a cartridge header
+    ///
+    /// # Arguments
+    /// **cgb_flag (u8)**: Value of the byte at 0x0143 of the cartridge
+    ///
+    /// # Returns
+    /// **Model**: CGB if the cartridge supports CGB functions, DMG otherwise
+    pub fn from_cgb_flag(cgb_flag: u8) -> Self {
+        if cgb_flag & 0x80 == 0x80 {
+            Model::Cgb
+        } else {
+            Model::Dmg
+        }
+    }
+}
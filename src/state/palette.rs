@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Colors displayed for the 4 shades of the GameBoy
+pub struct Palette {
+    /// 0xRRGGBB color of each shade, from the lightest to the darkest
+    colors: [u32; 4],
+}
+
+impl Palette {
+    /// Create a palette from the color of each shade
+    ///
+    /// # Arguments
+    /// **colors ([u32; 4])**: 0xRRGGBB color of each shade, from the lightest
+    /// to the darkest
+    ///
+    /// # Returns
+    /// **Palette**: New palette
+    pub fn new(colors: [u32; 4]) -> Self {
+        Self {
+            colors
+        }
+    }
+
+    /// Reads a palette from a .pal file
+    ///
+    /// The file contains exactly four colors written as RRGGBB in
+    /// hexadecimal, one per line from the lightest to the darkest. Blank
+    /// lines are ignored.
+    ///
+    /// # Arguments
+    /// **path (&Path)**: Path of the .pal file
+    ///
+    /// # Returns
+    /// **`Result<Palette, String>`**: Palette read, or a message explaining
+    /// why the file is invalid
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let colors = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                if line.len() != 6 {
+                    return Err(format!("Invalid color {:?}", line));
+                }
+                u32::from_str_radix(line, 16)
+                    .map_err(|_| format!("Invalid color {:?}", line))
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+        let colors: [u32; 4] = colors.try_into().map_err(|colors: Vec<u32>|
+            format!("Expected 4 colors, found {}", colors.len())
+        )?;
+        Ok(Self::new(colors))
+    }
+
+    /// Returns the color of a shade
+    ///
+    /// # Arguments
+    /// **shade (u8)**: Shade of the GameBoy (00 to 11 for white to black)
+    ///
+    /// # Returns
+    /// **u32**: 0xRRGGBB color displayed for this shade
+    pub fn color(&self, shade: u8) -> u32 {
+        self.colors[(shade & 0x03) as usize]
+    }
+
+    /// Returns the luminance of a color
+    ///
+    /// # Arguments
+    /// **color (u32)**: 0xRRGGBB color
+    ///
+    /// # Returns
+    /// **u8**: Gray level of the color (0 for black, 255 for white)
+    pub fn luminance(color: u32) -> u8 {
+        let red = (color >> 16) & 0xFF;
+        let green = (color >> 8) & 0xFF;
+        let blue = color & 0xFF;
+        ((299 * red + 587 * green + 114 * blue) / 1000) as u8
+    }
+}
+
+impl Default for Palette {
+    /// Shades of gray
+    fn default() -> Self {
+        Self::new([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_from_file() {
+        let path = env::temp_dir().join("gb_emulator_rust_test_palette.pal");
+        fs::write(&path, "E0F8D0\n88C070\n346856\n081820\n").unwrap();
+        let palette = Palette::from_file(&path).unwrap();
+        assert_eq!(
+            palette,
+            Palette::new([0xE0F8D0, 0x88C070, 0x346856, 0x081820])
+        );
+        assert_eq!(palette.color(3), 0x081820);
+        for malformed in [
+            "E0F8D0\n88C070\n346856\n",
+            "E0F8D0\n88C070\n346856\n081820\nFFFFFF\n",
+            "E0F8D0\n88C070\n3468ZZ\n081820\n",
+            "E0F8D0\n88C070\n#34685\n081820\n",
+        ] {
+            fs::write(&path, malformed).unwrap();
+            assert!(Palette::from_file(&path).is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_luminance() {
+        let palette = Palette::default();
+        let levels: Vec<u8> = (0..4)
+            .map(|shade| Palette::luminance(palette.color(shade)))
+            .collect();
+        assert_eq!(levels, vec![255, 170, 85, 0]);
+        assert_eq!(Palette::luminance(0x00FF00), 149);
+    }
+}
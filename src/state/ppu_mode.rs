@@ -0,0 +1,30 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Mode of the PPU, as found in the two lowest bits of the LCD status
+pub enum PpuMode {
+    /// Mode 0: waiting for the end of the scanline
+    HBlank,
+    /// Mode 1: waiting until the next frame
+    VBlank,
+    /// Mode 2: searching for objects which overlap the line
+    OamScan,
+    /// Mode 3: sending pixels to the LCD
+    Drawing,
+}
+
+impl PpuMode {
+    /// Decode the mode from the LCD status
+    ///
+    /// # Arguments
+    /// **lcd_status (u8)**: Value of the LCD status register
+    ///
+    /// # Returns
+    /// **PpuMode**: Mode found in the two lowest bits of the register
+    pub fn from_lcd_status(lcd_status: u8) -> Self {
+        match lcd_status & 0x03 {
+            0 => PpuMode::HBlank,
+            1 => PpuMode::VBlank,
+            2 => PpuMode::OamScan,
+            _ => PpuMode::Drawing,
+        }
+    }
+}
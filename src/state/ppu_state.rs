@@ -0,0 +1,11 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+/// Internal state of the PPU, not writable through its registers
+pub struct PpuState {
+    /// Number of dots elapsed since the beginning of the current line
+    pub dot: u32,
+    /// Read only bits of STAT: the LYC flag and the current mode
+    pub status: u8,
+    /// Is one of the enabled STAT conditions met
+    pub stat_line: bool,
+}
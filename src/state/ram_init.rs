@@ -0,0 +1,31 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+/// Content of the RAM when the GameBoy is powered up
+///
+/// The hardware starts with semi-random values, filling the RAM with
+/// something else than zeros reveals the games reading uninitialized memory.
+pub enum RamInit {
+    /// Every byte is 0x00
+    #[default]
+    Zero,
+    /// Every byte is 0xFF
+    Ones,
+    /// The bytes alternate between 0x00 and 0xFF, starting with 0x00
+    Alternating,
+}
+
+impl RamInit {
+    /// Fills a memory with the pattern
+    ///
+    /// # Arguments
+    /// **ram (&mut [u8])**: Memory to fill, from its first byte
+    pub fn fill(&self, ram: &mut [u8]) {
+        for (index, byte) in ram.iter_mut().enumerate() {
+            *byte = match self {
+                RamInit::Zero => 0x00,
+                RamInit::Ones => 0xFF,
+                RamInit::Alternating if index % 2 == 0 => 0x00,
+                RamInit::Alternating => 0xFF,
+            };
+        }
+    }
+}
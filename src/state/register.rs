@@ -39,7 +39,8 @@ macro_rules! form_16_bits_register {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 /// The registers used by the CPU to store values
 pub struct Registers {
     /// 8 bit register A 
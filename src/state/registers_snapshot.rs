@@ -0,0 +1,61 @@
+use crate::state::register::Registers;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+/// Copy of the registers of the CPU, used to inspect or set its state
+pub struct RegistersSnapshot {
+    /// 8 bit register A
+    pub a: u8,
+    /// 8 bit register B
+    pub b: u8,
+    /// 8 bit register C
+    pub c: u8,
+    /// 8 bit register D
+    pub d: u8,
+    /// 8 bit register E
+    pub e: u8,
+    /// 8 bit register F (flags)
+    pub f: u8,
+    /// 8 bit register H
+    pub h: u8,
+    /// 8 bit register L
+    pub l: u8,
+    /// 16 bit Program Counter register
+    pub pc: u16,
+    /// 16 bit Stack Pointer register
+    pub sp: u16,
+}
+
+impl From<&Registers> for RegistersSnapshot {
+    fn from(registers: &Registers) -> Self {
+        Self {
+            a: registers.a,
+            b: registers.b,
+            c: registers.c,
+            d: registers.d,
+            e: registers.e,
+            f: registers.f,
+            h: registers.h,
+            l: registers.l,
+            pc: registers.pc,
+            sp: registers.sp,
+        }
+    }
+}
+
+impl From<RegistersSnapshot> for Registers {
+    fn from(snapshot: RegistersSnapshot) -> Self {
+        Self {
+            a: snapshot.a,
+            b: snapshot.b,
+            c: snapshot.c,
+            d: snapshot.d,
+            e: snapshot.e,
+            // The low nibble of F is always 0
+            f: snapshot.f & 0xF0,
+            h: snapshot.h,
+            l: snapshot.l,
+            pc: snapshot.pc,
+            sp: snapshot.sp,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::state::apu_state::ApuState;
+use crate::state::ppu_state::PpuState;
+use crate::state::register::Registers;
+use crate::state::timer_state::TimerState;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+/// Copy of the state of the whole gameboy, except the ROM of the cartridge
+pub struct SaveState {
+    /// Registers of the CPU
+    pub(crate) registers: Registers,
+    /// Is the CPU halted
+    pub(crate) is_halted: bool,
+    /// Is the CPU stopped until a button is pressed
+    pub(crate) is_stopped: bool,
+    /// Will the next byte be read twice after the HALT bug
+    pub(crate) is_halt_bug: bool,
+    /// Countdown before enabling interruptions
+    pub(crate) ei: u32,
+    /// Countdown before disabling interruptions
+    pub(crate) di: u32,
+    /// Should pending interruptions be managed
+    pub(crate) ime: bool,
+    /// Interrupt flag
+    pub(crate) interrupt_flag: u8,
+    /// Interrupt enable
+    pub(crate) ie: u8,
+    /// Is the gameboy in double speed mode
+    pub(crate) is_double_speed: bool,
+    /// Content of the VRAM (0x8000 to 0x9FFF)
+    pub(crate) vram: Vec<u8>,
+    /// Bank of the VRAM selected by VBK (always 0 on DMG)
+    pub(crate) vram_bank: u8,
+    /// Content of every bank of the RAM of the cartridge
+    pub(crate) cartridge_ram: Vec<u8>,
+    /// Registers of the mapper of the cartridge
+    pub(crate) cartridge_registers: [u8; 4],
+    /// Content of the WRAM (0xC000 to 0xDFFF)
+    pub(crate) wram: Vec<u8>,
+    /// Content of the OAM (0xFE00 to 0xFE9F)
+    pub(crate) oam: Vec<u8>,
+    /// Content of the IO registers (0xFF00 to 0xFF7F)
+    #[cfg_attr(feature = "json", serde(with = "io_registers"))]
+    pub(crate) io: [u8; 0x80],
+    /// Internal state of the timer
+    pub(crate) timer: TimerState,
+    /// Registers and channels of the APU
+    pub(crate) apu: ApuState,
+    /// Content of the LCD registers (0xFF40 to 0xFF4B, except 0xFF46)
+    pub(crate) lcd: Vec<u8>,
+    /// Internal state of the PPU
+    pub(crate) ppu: PpuState,
+    /// Content of the HRAM (0xFF80 to 0xFFFE)
+    pub(crate) hram: Vec<u8>,
+}
+
+/// Serde only handles arrays of up to 32 elements, the IO registers are
+/// written as a list instead
+#[cfg(feature = "json")]
+mod io_registers {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        io: &[u8; 0x80],
+        serializer: S
+    ) -> Result<S::Ok, S::Error> {
+        io.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D
+    ) -> Result<[u8; 0x80], D::Error> {
+        Vec::<u8>::deserialize(deserializer)?
+            .try_into()
+            .map_err(|_| D::Error::custom("expected 128 IO registers"))
+    }
+}
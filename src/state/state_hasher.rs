@@ -0,0 +1,56 @@
+use std::hash::Hasher;
+
+/// FNV-1a offset basis
+const FNV_OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+/// FNV-1a prime
+const FNV_PRIME: u64 = 0x00000100000001B3;
+
+/// Hasher giving the same result on every run and every platform
+///
+/// Uses the FNV-1a algorithm and always writes integers as little endian, so
+/// that hashes of the state of the emulator can be compared between runs.
+pub struct StateHasher {
+    /// Current value of the hash
+    hash: u64,
+}
+
+impl StateHasher {
+    /// Create a new hasher
+    ///
+    /// # Returns
+    /// **StateHasher**: Hasher that has not received any byte
+    pub fn new() -> Self {
+        Self {
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
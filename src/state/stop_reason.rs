@@ -0,0 +1,16 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Why the CPU stopped running
+pub enum StopReason {
+    /// The maximum number of cycles was reached
+    CycleLimit,
+    /// The user asked for the program to stop
+    Exit,
+    /// The next instruction has an opcode given to break_on_opcode or
+    /// break_on_cb_opcode
+    OpcodeBreak,
+    /// The PPU entered the VBlank and requested its interruption
+    VBlank,
+    /// The instruction at this address was executed again and again, as
+    /// many times as set by set_infinite_loop_threshold
+    InfiniteLoop(u16),
+}
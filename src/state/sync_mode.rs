@@ -0,0 +1,59 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+/// What the frame limiter paces the emulation to
+pub enum SyncMode {
+    /// Keeps the audio buffer filled, which avoids crackles when the clock of
+    /// the host is slightly off
+    Audio,
+    /// Follows the wall-clock duration of the frames
+    #[default]
+    Video,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Decision of the frame limiter
+pub enum Pacing {
+    /// The emulation is late and should not sleep
+    RunFaster,
+    /// The emulation runs at the right speed
+    Normal,
+    /// The emulation is early and should sleep
+    Wait,
+}
+
+impl Pacing {
+    /// Decides how to pace the emulation from the fill level of the audio
+    /// buffer
+    ///
+    /// Below a quarter of the buffer, the samples may run out; above three
+    /// quarters, they are produced faster than they are played.
+    ///
+    /// # Arguments
+    /// **buffered (usize)**: Number of samples waiting to be played
+    /// **capacity (usize)**: Number of samples the buffer can hold
+    ///
+    /// # Returns
+    /// **Pacing**: Should the emulation run faster, normally or wait
+    pub fn from_audio_buffer(buffered: usize, capacity: usize) -> Self {
+        if buffered * 4 < capacity {
+            Pacing::RunFaster
+        } else if buffered * 4 > capacity * 3 {
+            Pacing::Wait
+        } else {
+            Pacing::Normal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_audio_buffer() {
+        assert_eq!(Pacing::from_audio_buffer(0, 4096), Pacing::RunFaster);
+        assert_eq!(Pacing::from_audio_buffer(1023, 4096), Pacing::RunFaster);
+        assert_eq!(Pacing::from_audio_buffer(2048, 4096), Pacing::Normal);
+        assert_eq!(Pacing::from_audio_buffer(3100, 4096), Pacing::Wait);
+        assert_eq!(Pacing::from_audio_buffer(4096, 4096), Pacing::Wait);
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 /// Describe an object in the OAM
 pub struct TileObject {
     /// y position of the object on the screen
@@ -8,7 +8,7 @@ pub struct TileObject {
     /// Index of the tile in the VRAM
     pub tile_index: u8,
     /// Gives some information about the status of the object
-    /// priority/y flip/x flip/dmg palette/unused/unused/unused/unused
+    /// priority/y flip/x flip/dmg palette/bank/cgb palette*3
     pub flags: u8
 }
 
@@ -26,13 +26,13 @@ impl TileObject {
         }
     }
     
-    /// Does the object as priority for rendering?
+    /// Does the background have priority over the object for rendering?
     ///
-    /// Reads the 1st bit of the flag to know if the object should be drawn on
-    /// top or behind other objects
+    /// Reads the 1st bit of the flag to know if the colors 1 to 3 of the
+    /// background and window should be drawn on top of the object
     ///
     /// # Returns
-    /// **bool**: true iff the object has priority
+    /// **bool**: true iff the background has priority over the object
     pub fn get_priority(&self) -> bool {
         self.flags & 0x80 == 0x80
     }
@@ -60,4 +60,43 @@ impl TileObject {
     pub fn get_dmg_palette(&self) -> bool {
         self.flags & 0x10 == 0x10
     }
+
+    /// Indicate from what VRAM bank the tile is read (CGB only)
+    ///
+    /// # Returns
+    /// **u8**: VRAM bank of the tile (0 or 1)
+    pub fn get_bank(&self) -> u8 {
+        (self.flags & 0x08) >> 3
+    }
+
+    /// Indicate what cgb palette to use (CGB only)
+    ///
+    /// # Returns
+    /// **u8**: Index of the object palette (0 to 7)
+    pub fn get_cgb_palette(&self) -> u8 {
+        self.flags & 0x07
+    }
+}
+
+impl Default for TileObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let mut object = TileObject::new();
+        object.flags = 0b10101101;
+        assert!(object.get_priority());
+        assert!(!object.get_y_flip());
+        assert!(object.get_x_flip());
+        assert!(!object.get_dmg_palette());
+        assert_eq!(object.get_bank(), 1);
+        assert_eq!(object.get_cgb_palette(), 0b101);
+    }
 }
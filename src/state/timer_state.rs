@@ -0,0 +1,14 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+/// Internal state of the timer, not visible through its registers
+pub struct TimerState {
+    /// Number of cpu cycles elapsed, whose high byte is the divider register
+    /// (0xFF04)
+    pub divider: u16,
+    /// Is the divider stopped
+    pub is_stopped: bool,
+    /// Is a joypad interruption waiting to be handled by the CPU
+    pub pending_joypad_interruption: bool,
+    /// Is a timer interruption waiting to be handled by the CPU
+    pub pending_timer_interruption: bool,
+}
@@ -0,0 +1,96 @@
+use crate::components::cpu::CLOCK_HZ;
+use crate::state::button::Button;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Presses and releases a button repeatedly while its key is held
+pub struct Turbo {
+    /// Button pressed repeatedly
+    button: Button,
+    /// Number of presses per second, each lasting half of its period
+    hz: u32,
+    /// Number of cycles since the key started being held
+    held_cycles: u64,
+}
+
+impl Turbo {
+    /// Create the turbo of a button
+    ///
+    /// # Arguments
+    /// **button (Button)**: Button pressed repeatedly
+    /// **hz (u32)**: Number of presses per second
+    ///
+    /// # Returns
+    /// **Turbo**: New turbo, waiting for the key to be held
+    pub fn new(button: Button, hz: u32) -> Self {
+        Self {
+            button,
+            hz,
+            held_cycles: 0,
+        }
+    }
+
+    /// Returns the button pressed repeatedly
+    ///
+    /// # Returns
+    /// **Button**: Button of the turbo
+    pub fn button(&self) -> Button {
+        self.button
+    }
+
+    /// Advances the time the key has been held for
+    ///
+    /// # Arguments
+    /// **is_held (bool)**: Is the key held, the count starts again otherwise
+    /// **n_cycles (u64)**: Number of cycles elapsed
+    pub fn advance(&mut self, is_held: bool, n_cycles: u64) {
+        self.held_cycles = if is_held {
+            self.held_cycles + n_cycles
+        } else {
+            0
+        };
+    }
+
+    /// Returns whether the game sees the button pressed
+    ///
+    /// The button is pressed as soon as the key is held, then toggles every
+    /// half period.
+    ///
+    /// # Arguments
+    /// **is_held (bool)**: Is the key held
+    ///
+    /// # Returns
+    /// **bool**: Is the button pressed
+    pub fn is_pressed(&self, is_held: bool) -> bool {
+        let n_half_periods =
+            self.held_cycles * 2 * self.hz as u64 / CLOCK_HZ as u64;
+        is_held && n_half_periods.is_multiple_of(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turbo_toggles() {
+        let mut turbo = Turbo::new(Button::A, 30);
+        assert!(!turbo.is_pressed(false));
+        assert!(turbo.is_pressed(true));
+        let mut is_pressed = true;
+        let mut n_toggles = 0;
+        for _ in 0..CLOCK_HZ / 1024 {
+            turbo.advance(true, 1024);
+            if turbo.is_pressed(true) != is_pressed {
+                is_pressed = !is_pressed;
+                n_toggles += 1;
+            }
+        }
+        // Pressed then released 30 times
+        assert_eq!(n_toggles, 60);
+        // Releasing the key starts again from a press
+        turbo.advance(false, 1024);
+        assert!(!turbo.is_pressed(false));
+        turbo.advance(true, 1024);
+        assert!(turbo.is_pressed(true));
+    }
+}